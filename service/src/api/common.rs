@@ -25,6 +25,11 @@ impl Display for ExperimentMemberKind {
     }
 }
 
+/// Deterministic bucket in [0, 10000) of the user for given seed, i.e. 1/100th of a percent.
+pub fn user_bucket(user_id: &str, seed: &str) -> u64 {
+    seahash::hash_seeded(user_id.as_bytes(), seahash::hash(seed.as_bytes()), 0, 0, 0) % 10000
+}
+
 pub fn merge_data(experiment_data: Option<serde_json::Value>, variation_data: Option<serde_json::Value>) -> Option<serde_json::Value> {
     match (experiment_data, variation_data) {
         (None, None) => None,
@@ -14,10 +14,11 @@ use rand::distributions::Distribution;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
 
-use crate::api::common::merge_data;
+use crate::api::common::{merge_data, user_bucket};
 use crate::api::experiment_tracking_data::{TrackedExperiment, TrackingData, TrackingDataParser};
+use crate::api::metrics::{ASSIGNMENTS, CONTEXT_VALIDATION_FAILURES, EXPERIMENT_SKIPS, HOLDOUTS, SCRIPT_ERRORS, TRACKING_PARSE_FAILURES};
 use crate::core;
-use crate::core::{Project, Script, TrackingMethod};
+use crate::core::{ContextValidation, ExperimentState, Project, Script, TrackingMethod};
 use crate::server::{ApiError, HttpRequest, HttpResponse, HttpRoute};
 use crate::service::AbOptimisationService;
 
@@ -53,6 +54,7 @@ pub struct ExperimentResponse<'a> {
     pub app_id: &'a str,
     pub project_id: &'a str,
     pub active_experiments: Vec<ActiveExperiment>,
+    pub holdout: bool,
     pub tracking_cookie_name: Option<String>,
     pub tracking_data: Option<String>,
 }
@@ -65,28 +67,28 @@ pub struct ActiveExperiment {
 }
 
 impl AbOptimisationService {
-    fn run_internal<'a, F, R>(&'a self, route: &HttpRoute<'a>, req: &'a ExperimentRequest, result_visitor: F) -> R
+    fn run_internal<'a, F, R>(&'a self, route: &HttpRoute<'a>, req: &'a mut ExperimentRequest, result_visitor: F) -> R
     where
         F: FnOnce(Result<ExperimentResponse, ApiError>) -> R,
     {
         let guard = &epoch::pin();
 
         // check app
-        let app_entry = self.apps.get(&req.app_id, guard);
+        let app_entry = self.apps.get(&req.app_id, guard).filter(|entry| !entry.value().read().archived);
         match app_entry {
             None => result_visitor(Err(ApiError::NotFound(format!("App:{} not found", req.app_id)))),
             Some(app_entry) => {
                 let app_lock = app_entry.value();
                 let app = app_lock.read();
 
-                let proj_entry = app.projects.get(&req.project_id, guard);
+                let proj_entry = app.projects.get(&req.project_id, guard).filter(|entry| !entry.value().read().archived);
                 match proj_entry {
                     None => result_visitor(Err(ApiError::NotFound(format!("Project:{} not found", req.project_id)))),
                     Some(proj_entry) => {
                         let proj_lock = proj_entry.value();
                         let proj = proj_lock.read();
 
-                        let result = self.run_for_project(route, &req, &app, &proj, guard);
+                        let result = self.run_for_project(route, req, &app, &proj, guard);
                         result_visitor(result)
                     }
                 }
@@ -94,26 +96,62 @@ impl AbOptimisationService {
         }
     }
 
+    #[tracing::instrument(
+        skip(self, route, req, app, proj, guard),
+        fields(app_id = %req.app_id, project_id = %req.project_id, holdout = tracing::field::Empty)
+    )]
     fn run_for_project<'a>(
         &self,
         route: &HttpRoute<'_>,
-        req: &'a ExperimentRequest,
+        req: &mut ExperimentRequest,
         app: &'a core::App,
         proj: &'a core::Project,
         guard: &'a Guard,
     ) -> Result<ExperimentResponse<'a>, ApiError> {
         let tracking_cookie_name = Self::tracking_cookie_name(&app, &proj);
 
+        Self::validate_context(req, proj)?;
+
+        if let Some(enrichment) = proj.request_enrichment.as_ref() {
+            let attributes = enrichment.enrich(route, &req.experiment_start_time);
+
+            if let jexl_eval::Value::Object(context_map) = &mut req.script_context {
+                context_map.insert("req".to_string(), jexl_eval::Value::from(&attributes));
+            }
+        }
+
         // get tracking history
-        let tracking_history = Self::parse_tracking_history(route, req, &proj, &tracking_cookie_name)?;
+        let tracking_history = Self::parse_tracking_history(route, req, &proj, &tracking_cookie_name).map_err(|err| {
+            TRACKING_PARSE_FAILURES.with_label_values(&[&req.app_id, &req.project_id]).inc();
+            ApiError::InvalidTrackingData(err)
+        })?;
+
+        // holdout users don't see any experiment of the project, while their history is kept as it is, so that they get
+        // the same assignments back if the holdout is shrunk or removed
+        if Self::in_holdout(req, proj) {
+            tracing::Span::current().record("holdout", &true);
+            HOLDOUTS.with_label_values(&[&req.app_id, &req.project_id]).inc();
+
+            let tracking_data = TrackingData {
+                holdout: true,
+                experiments: tracking_history.map_or_else(Vec::new, |tracking_history| tracking_history.experiments),
+            };
+
+            return Ok(ExperimentResponse {
+                app_id: &app.id,
+                project_id: &proj.id,
+                tracking_cookie_name: Some(tracking_cookie_name),
+                active_experiments: vec![],
+                holdout: true,
+                tracking_data: Some(tracking_data.to_string()),
+            });
+        }
 
         // build map from tracking history to the version
         let tracking_history = Self::build_tracking_history_map(tracking_history);
 
         // info!("Tracking History: {:#?}", tracking_history);
 
-        // LATER: parse cookie / header / context into final context object - not needed immediately...
-
         let mut tracked_experiments = Vec::<TrackedExperiment>::new();
         let mut active_experiments = Vec::<ActiveExperiment>::new();
 
@@ -123,14 +161,57 @@ impl AbOptimisationService {
             let experiment = exp_lock.read();
 
             // todo: check for schedule
-            if experiment.inactive {
+            if experiment.inactive || experiment.archived {
                 continue;
             }
 
             let existing_experiment = tracking_history.get(&experiment.short_name);
 
-            // sample user for the experiment
-            let (targeting_eligible, frequency_eligible, mut picked) = self.sample_experiment(&req, &proj, experiment.deref(), existing_experiment, guard)?;
+            match experiment.state {
+                ExperimentState::Running => {}
+                ExperimentState::Paused => {
+                    // keep the history as it is, so that user gets same assignment on resume
+                    if let Some(existing_experiment) = existing_experiment {
+                        tracked_experiments.push(existing_experiment.clone());
+                    }
+
+                    continue;
+                }
+                ExperimentState::Concluded => {
+                    if Self::is_skipped(req, experiment.deref()) {
+                        continue;
+                    }
+
+                    let rollout = self.rollout_experiment(&req, &proj, experiment.deref(), guard);
+                    if let Some(active_experiment) = Self::isolate_failure(req, experiment.deref(), rollout).flatten() {
+                        active_experiments.push(active_experiment);
+                    }
+
+                    continue;
+                }
+                ExperimentState::Draft | ExperimentState::PendingApproval => {
+                    continue;
+                }
+            }
+
+            // broken experiment is skipped, keeping the user's history of it, so that the rest of the experiments are still run
+            let sampled = if Self::is_skipped(req, experiment.deref()) {
+                None
+            } else {
+                let sampled = self.sample_experiment(req, &proj, experiment.deref(), existing_experiment, guard);
+                Self::isolate_failure(req, experiment.deref(), sampled)
+            };
+
+            let (targeting_eligible, frequency_eligible, mut picked) = match sampled {
+                Some(sampled) => sampled,
+                None => {
+                    if let Some(existing_experiment) = existing_experiment {
+                        tracked_experiments.push(existing_experiment.clone());
+                    }
+
+                    continue;
+                }
+            };
 
             // info!(
             //     "Experiment={}@{} targeting_eligible={}, frequency_eligible={}, picked={}",
@@ -233,6 +314,16 @@ impl AbOptimisationService {
                 experiment.control_size.fetch_add(1, Ordering::Relaxed);
             }
 
+            ASSIGNMENTS
+                .with_label_values(&[
+                    &req.app_id,
+                    &req.project_id,
+                    &experiment.short_name,
+                    selected_variation.as_deref().unwrap_or_default(),
+                    &selected_member_kind.to_string(),
+                ])
+                .inc();
+
             let tracking_experiment = TrackedExperiment {
                 short_name: experiment.short_name.to_string(),
                 invocation_version: experiment.version,
@@ -249,6 +340,7 @@ impl AbOptimisationService {
         }
 
         let tracking_data = TrackingData {
+            holdout: false,
             experiments: tracked_experiments,
         };
 
@@ -257,12 +349,60 @@ impl AbOptimisationService {
             project_id: &proj.id,
             tracking_cookie_name: Some(tracking_cookie_name),
             active_experiments,
+            holdout: false,
             tracking_data: Some(tracking_data.to_string()),
         };
 
         Ok(experiment_response)
     }
 
+    /// Validates the context against the context schema of the project, as per the strictness of the schema.
+    fn validate_context(req: &ExperimentRequest, proj: &core::Project) -> Result<(), ApiError> {
+        let schema = match proj.context_schema.as_ref() {
+            Some(schema) if schema.validation != ContextValidation::Off => schema,
+            _ => return Ok(()),
+        };
+
+        match schema.validate_context(req.context.as_ref()) {
+            Ok(()) => Ok(()),
+            Err(errors) => {
+                CONTEXT_VALIDATION_FAILURES.with_label_values(&[&req.app_id, &req.project_id]).inc();
+
+                if schema.validation == ContextValidation::Reject {
+                    return Err(ApiError::Invalid(errors));
+                }
+
+                warn!("Invalid context for app:{}, project:{} ==> {}", req.app_id, req.project_id, errors);
+                Ok(())
+            }
+        }
+    }
+
+    fn in_holdout(req: &ExperimentRequest, proj: &core::Project) -> bool {
+        match proj.holdout.as_ref() {
+            Some(holdout) if holdout.size > 0 => {
+                let salt = holdout.salt.as_ref().unwrap_or(&proj.id);
+
+                (user_bucket(&req.user_id, salt) as i64) < holdout.size * 100
+            }
+            _ => false,
+        }
+    }
+
+    fn in_layer_allocation(req: &ExperimentRequest, proj: &core::Project, allocation: &core::LayerAllocation) -> bool {
+        match proj.layer(&allocation.layer) {
+            None => false,
+            Some(layer) => {
+                let user_bucket = match layer.salt.as_ref() {
+                    Some(salt) => user_bucket(&req.user_id, salt),
+                    None => user_bucket(&req.user_id, &format!("{}/{}", proj.id, layer.short_name)),
+                };
+
+                allocation.contains(user_bucket)
+            }
+        }
+    }
+
     fn build_tracking_history_map(tracking_history: Option<TrackingData>) -> HashMap<String, TrackedExperiment> {
         match tracking_history {
             None => HashMap::new(),
@@ -288,6 +428,7 @@ impl AbOptimisationService {
         }
     }
 
+    #[tracing::instrument(skip(route, req, proj, tracking_cookie_name))]
     fn parse_tracking_history(
         route: &HttpRoute,
         req: &ExperimentRequest,
@@ -367,16 +508,21 @@ impl AbOptimisationService {
         (data, picked_variation)
     }
 
+    #[tracing::instrument(skip(self, req, proj, experiment, tracked_experiment, guard), fields(experiment = %experiment.short_name))]
     fn sample_experiment(
         &self,
-        req: &ExperimentRequest,
+        req: &mut ExperimentRequest,
         proj: &core::Project,
         experiment: &core::Experiment,
         tracked_experiment: Option<&TrackedExperiment>,
         guard: &Guard,
     ) -> anyhow::Result<(bool, bool, bool)> {
-        // TODO: calculate these seed once
-        let experiment_seed = seahash::hash(format!("{}/{}", proj.id, experiment.id).as_bytes());
+        // user outside experiment's slice of the layer is not eligible for it
+        if let Some(allocation) = experiment.layer.as_ref() {
+            if !Self::in_layer_allocation(req, proj, allocation) {
+                return Ok((false, true, false));
+            }
+        }
 
         let mut targeting_eligible: bool = false;
         let mut picked: bool = false;
@@ -386,7 +532,8 @@ impl AbOptimisationService {
         match (experiment.frequency_constraint.as_ref(), tracked_experiment) {
             (Some(frequency_constraint), Some(tracked_experiment)) => {
                 frequency_eligible = self
-                    .evaluate_frequency_constraint(tracked_experiment, &req.script_context, Some(frequency_constraint))
+                    .evaluate_frequency_constraint(tracked_experiment, &mut req.script_context, frequency_constraint)
+                    .map_err(|err| Self::script_error(req, experiment, err))
                     .with_context(|| {
                         format!(
                             "frequency_constraint=\"{}\" tracked_experiment={:?} ctx={:?}, experiment={}",
@@ -404,42 +551,10 @@ impl AbOptimisationService {
             }
         }
 
-        for core::Audience {
-            name,
-            list_id,
-            size,
-            picked_size,
-            script_src,
-            ..
-        } in experiment.audiences.iter()
-        {
-            let matches_script = self.evaluate_audience_condition(&req.script_context, script_src.as_ref()).with_context(|| {
-                format!(
-                    "script_src=\"{:?}\" ctx={:?}, experiment={}",
-                    script_src,
-                    req.context.as_ref(),
-                    experiment.short_name
-                )
-            })?;
-
-            let mut matches_list = true;
-            if let Some(list_id) = list_id {
-                let audience_entry = proj
-                    .audience_lists
-                    .get(list_id, guard)
-                    .ok_or_else(|| ApiError::BadRequest(anyhow!("Audience list not found for id: {}", list_id)))?;
+        for (audience, audience_seed) in experiment.plan.audiences(&experiment.audiences)? {
+            let core::Audience { size, picked_size, .. } = audience;
 
-                let audience_list = audience_entry.value();
-                let audience_list = audience_list.read();
-
-                if audience_list.list.contains(&req.user_id) {
-                    matches_list = true;
-                } else {
-                    matches_list = false;
-                }
-            }
-
-            let audience_candidate = matches_script && matches_list;
+            let audience_candidate = self.matches_audience(req, proj, experiment, audience, guard)?;
 
             if audience_candidate {
                 targeting_eligible = true;
@@ -453,11 +568,9 @@ impl AbOptimisationService {
                         }
                     }
                     core::SizeSpec::Percent { value, ../*, sampler*/ } => {
-                        // TODO: calculate these seed once
-                        let audience_seed = seahash::hash(name.as_bytes());
                         let user_hash_bucket = seahash::hash_seeded(
                             req.user_id.as_bytes(),
-                            experiment_seed,
+                            experiment.plan.experiment_seed,
                             audience_seed,
                             0,
                             0
@@ -484,24 +597,156 @@ impl AbOptimisationService {
         Ok((targeting_eligible, frequency_eligible, picked))
     }
 
-    fn evaluate_frequency_constraint(&self, experiment: &TrackedExperiment, ctx: &jexl_eval::Value, script: Option<&Script>) -> anyhow::Result<bool> {
-        if let jexl_eval::Value::Object(context_map) = ctx {
-            let mut context_map = context_map.clone();
-            let experiment = jexl_eval::to_value(experiment).with_context(|| format!("Error in converting experiment to EvaluationContext"))?;
-            context_map.insert("experiment".to_string(), experiment);
+    fn matches_audience(
+        &self,
+        req: &ExperimentRequest,
+        proj: &core::Project,
+        experiment: &core::Experiment,
+        audience: &core::Audience,
+        guard: &Guard,
+    ) -> anyhow::Result<bool> {
+        let matches_script = self
+            .evaluate_audience_condition(&req.script_context, audience.script_src.as_ref())
+            .map_err(|err| Self::script_error(req, experiment, err))
+            .with_context(|| {
+                format!(
+                    "script_src=\"{:?}\" ctx={:?}, experiment={}",
+                    audience.script_src,
+                    req.context.as_ref(),
+                    experiment.short_name
+                )
+            })?;
+
+        let mut matches_list = true;
+        if let Some(list_id) = audience.list_id.as_ref() {
+            let audience_entry = proj
+                .audience_lists
+                .get(list_id, guard)
+                .ok_or_else(|| ApiError::NotFound(format!("Audience list not found for id: {}", list_id)))?;
 
-            let context = jexl_eval::Value::from(context_map);
+            let audience_list = audience_entry.value();
+            let audience_list = audience_list.read();
 
-            self.script_evaluator.evaluate(script, &context)
-        } else {
-            Err(anyhow::anyhow!("Context is not a valid Object"))
+            // archived list doesn't match anyone
+            if !audience_list.archived && audience_list.members.contains(&req.user_id) {
+                matches_list = true;
+            } else {
+                matches_list = false;
+            }
         }
+
+        Ok(matches_script && matches_list)
+    }
+
+    /// Concluded experiment serves the winner to all targeted users, without any test / control split or tracking.
+    fn rollout_experiment(
+        &self,
+        req: &ExperimentRequest,
+        proj: &core::Project,
+        experiment: &core::Experiment,
+        guard: &Guard,
+    ) -> anyhow::Result<Option<ActiveExperiment>> {
+        let mut targeted = false;
+        for audience in experiment.audiences.iter() {
+            if self.matches_audience(req, proj, experiment, audience, guard)? {
+                targeted = true;
+                break;
+            }
+        }
+
+        if !targeted {
+            return Ok(None);
+        }
+
+        let winner = experiment.winner.as_ref().and_then(|winner| {
+            experiment
+                .variations
+                .as_ref()
+                .and_then(|variations| variations.iter().find(|variation| &variation.short_name == winner))
+        });
+
+        let data = merge_data(experiment.data.clone(), winner.and_then(|variation| variation.data.clone()));
+
+        Ok(Some(ActiveExperiment {
+            short_name: experiment.short_name.to_string(),
+            variation: winner.map(|variation| variation.short_name.to_string()),
+            data,
+        }))
+    }
+
+    /// Evaluates the frequency constraint with the tracked experiment set as `experiment` in the context of the request, for
+    /// the evaluation only, instead of copying the whole context for each of the experiments.
+    fn evaluate_frequency_constraint(&self, experiment: &TrackedExperiment, ctx: &mut jexl_eval::Value, script: &Script) -> anyhow::Result<bool> {
+        let experiment = jexl_eval::to_value(experiment).with_context(|| format!("Error in converting experiment to EvaluationContext"))?;
+
+        match ctx {
+            jexl_eval::Value::Object(context_map) => {
+                context_map.insert("experiment".to_string(), experiment);
+            }
+            _ => return Err(anyhow::anyhow!("Context is not a valid Object")),
+        }
+
+        let result = self.script_evaluator.evaluate(Some(script), ctx);
+
+        if let jexl_eval::Value::Object(context_map) = ctx {
+            context_map.remove("experiment");
+        }
+
+        result
     }
 
     fn evaluate_audience_condition(&self, ctx: &jexl_eval::Value, script: Option<&Script>) -> anyhow::Result<bool> {
         self.script_evaluator.evaluate(script, ctx)
     }
 
+    /// Whether the experiment is skipped, as its circuit breaker is open after repeated failures.
+    fn is_skipped(req: &ExperimentRequest, experiment: &core::Experiment) -> bool {
+        if !experiment.circuit_breaker.is_open(req.experiment_start_time.timestamp_millis()) {
+            return false;
+        }
+
+        EXPERIMENT_SKIPS
+            .with_label_values(&[&req.app_id, &req.project_id, &experiment.short_name, "circuit_open"])
+            .inc();
+
+        true
+    }
+
+    /// Result of the experiment, or None on its failure, which is logged and recorded in its circuit breaker.
+    fn isolate_failure<R>(req: &ExperimentRequest, experiment: &core::Experiment, result: anyhow::Result<R>) -> Option<R> {
+        match result {
+            Ok(result) => {
+                experiment.circuit_breaker.record_success();
+                Some(result)
+            }
+            Err(err) => {
+                warn!(
+                    "Skipping experiment={} for app:{}, project:{} on error ==> {:?}",
+                    experiment.short_name, req.app_id, req.project_id, err
+                );
+
+                EXPERIMENT_SKIPS
+                    .with_label_values(&[&req.app_id, &req.project_id, &experiment.short_name, "error"])
+                    .inc();
+
+                if experiment.circuit_breaker.record_failure(req.experiment_start_time.timestamp_millis()) {
+                    error!(
+                        "Circuit breaker opened for experiment={} of app:{}, project:{} after repeated failures",
+                        experiment.short_name, req.app_id, req.project_id
+                    );
+                }
+
+                None
+            }
+        }
+    }
+
+    fn script_error(req: &ExperimentRequest, experiment: &core::Experiment, err: Error) -> Error {
+        SCRIPT_ERRORS.with_label_values(&[&req.app_id, &req.project_id, &experiment.short_name]).inc();
+
+        err
+    }
+
     fn tracking_cookie_name(app: &core::App, proj: &core::Project) -> String {
         format!("X-abof-{}-{}", app.short_name, proj.short_name)
     }
@@ -531,6 +776,8 @@ impl AbOptimisationService {
 
     pub async fn run<'a>(&'a self, route: &HttpRoute<'a>, body: Body) -> Result<http::Response<Body>, ApiError> {
         let mut req = HttpRequest::value::<ExperimentRequest>(route, body).await?;
+        route.add_log_field("app_id", &req.app_id);
+        route.add_log_field("project_id", &req.project_id);
 
         let mut context_map = BTreeMap::new();
 
@@ -569,6 +816,106 @@ impl AbOptimisationService {
             })
         };
 
-        self.run_internal(route, &req, process_result)
+        self.run_internal(route, &mut req, process_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_epoch as epoch;
+    use serde_json::json;
+
+    use crate::api::experiment_runner::ExperimentRequest;
+    use crate::core::fixtures::{request, route, service};
+    use crate::core::Project;
+    use crate::service::AbOptimisationService;
+
+    fn project(holdout: serde_json::Value) -> anyhow::Result<Project> {
+        let mut project: Project = serde_json::from_value(json!({"name": "project", "short_name": "p", "holdout": holdout}))?;
+        project.id = "p1".to_string();
+
+        Ok(project)
+    }
+
+    fn holdout(size: i64, salt: Option<&str>) -> serde_json::Value {
+        json!({"size": size, "salt": salt})
+    }
+
+    fn held_out(project: &Project) -> anyhow::Result<Vec<bool>> {
+        (0..10000)
+            .map(|user| {
+                let req: ExperimentRequest = serde_json::from_value(json!({
+                    "app_id": "a1",
+                    "project_id": "p1",
+                    "user_id": format!("user-{}", user),
+                }))?;
+
+                Ok(AbOptimisationService::in_holdout(&req, project))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn holdout_size() -> anyhow::Result<()> {
+        assert!(held_out(&project(serde_json::Value::Null)?)?.iter().all(|held_out| !held_out));
+        assert!(held_out(&project(holdout(0, None))?)?.iter().all(|held_out| !held_out));
+        assert!(held_out(&project(holdout(100, None))?)?.iter().all(|held_out| *held_out));
+
+        let count = held_out(&project(holdout(10, None))?)?.into_iter().filter(|held_out| *held_out).count();
+        assert!(count > 800 && count < 1200, "held out {} of 10000 users for 10%", count);
+
+        Ok(())
+    }
+
+    #[test]
+    fn holdout_is_sticky() -> anyhow::Result<()> {
+        let users = held_out(&project(holdout(10, None))?)?;
+        assert_eq!(held_out(&project(holdout(10, None))?)?, users);
+
+        // growing the holdout keeps the users already held out
+        let grown = held_out(&project(holdout(20, None))?)?;
+        assert!(users.iter().zip(grown.iter()).all(|(held_out, grown)| !held_out || *grown));
+
+        // salt defaults to project id, and other salt reshuffles the holdout
+        assert_eq!(held_out(&project(holdout(10, Some("p1")))?)?, users);
+        assert_ne!(held_out(&project(holdout(10, Some("p1/v2")))?)?, users);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn holdout_keeps_history() -> anyhow::Result<()> {
+        let service = service().await?;
+        let http_req = request("u1")?;
+
+        let run = |tracking_data: Option<String>| {
+            let mut req: ExperimentRequest = serde_json::from_value(json!({
+                "app_id": "a1",
+                "project_id": "p1",
+                "user_id": "u1",
+                "tracking_data": tracking_data,
+            }))?;
+
+            service.run_internal(&route(&http_req), &mut req, |result| {
+                result
+                    .map(|response| (response.holdout, response.tracking_data.unwrap_or_default()))
+                    .map_err(anyhow::Error::from)
+            })
+        };
+
+        let (held_out, history) = run(None)?;
+        assert!(!held_out);
+        assert!(history.starts_with("e|1|"), "tracking data: {}", history);
+
+        service.visit_project("a1", "p1", &epoch::pin(), |entry| {
+            entry.value().write().holdout = project(holdout(100, None))?.holdout;
+            Ok(())
+        })?;
+
+        let (held_out, tracking_data) = run(Some(history.clone()))?;
+        assert!(held_out);
+        assert_eq!(tracking_data, format!("H~{}", history));
+
+        Ok(())
     }
 }
@@ -19,6 +19,9 @@ pub struct TrackingDataParser;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TrackingData {
+    /// User is part of the project holdout, and hence is not in any experiment.
+    #[serde(default)]
+    pub holdout: bool,
     pub experiments: Vec<TrackedExperiment>,
 }
 
@@ -38,6 +41,11 @@ pub struct TrackedExperiment {
 impl Display for TrackingData {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut first = true;
+        if self.holdout {
+            write!(f, "H")?;
+            first = false;
+        }
+
         for experiment in self.experiments.iter() {
             if !first {
                 write!(f, "~")?;
@@ -124,14 +132,20 @@ impl TrackingDataParser {
             Ok(result) => result,
             Err(err) => {
                 warn!("Error in parsing cookie: {} ==> {:?}", value, err);
-                TrackingData { experiments: vec![] }
+                TrackingData {
+                    holdout: false,
+                    experiments: vec![],
+                }
             }
         }
     }
 
     pub fn parse_tracking_data(value: &str) -> anyhow::Result<TrackingData> {
         if value.is_empty() {
-            return Ok(TrackingData { experiments: vec![] });
+            return Ok(TrackingData {
+                holdout: false,
+                experiments: vec![],
+            });
         }
 
         let cookie_rule = TrackingDataParser::parse(Rule::cookie, value)
@@ -139,14 +153,17 @@ impl TrackingDataParser {
             .next()
             .unwrap();
 
+        let mut holdout = false;
         let mut experiments = Vec::<TrackedExperiment>::new();
         for experiment_rule in cookie_rule.into_inner() {
-            if let Rule::experiment = experiment_rule.as_rule() {
-                experiments.push(Self::parse_experiment_rule(experiment_rule)?);
+            match experiment_rule.as_rule() {
+                Rule::holdout => holdout = true,
+                Rule::experiment => experiments.push(Self::parse_experiment_rule(experiment_rule)?),
+                _ => {}
             }
         }
 
-        let cookie_data = TrackingData { experiments };
+        let cookie_data = TrackingData { holdout, experiments };
 
         return Ok(cookie_data);
     }
@@ -258,4 +275,21 @@ mod tests {
         TrackingDataParser::parse_tracking_data("a|0|T|;a|0|C|d")?;
         Ok(())
     }
+
+    #[test]
+    fn parse_holdout() -> anyhow::Result<()> {
+        let tracking_data = TrackingDataParser::parse_tracking_data("H")?;
+        assert!(tracking_data.holdout);
+        assert!(tracking_data.experiments.is_empty());
+        assert_eq!(tracking_data.to_string(), "H");
+
+        let tracking_data = TrackingDataParser::parse_tracking_data("H~a|1|T||10|1|1|0|1")?;
+        assert!(tracking_data.holdout);
+        assert_eq!(tracking_data.experiments.len(), 1);
+
+        let tracking_data = TrackingDataParser::parse_tracking_data("a|1|T||10|1|1|0|1")?;
+        assert!(!tracking_data.holdout);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,54 @@
+use prometheus::{IntCounterVec, Opts};
+
+use crate::server::METRICS_REGISTRY;
+
+lazy_static! {
+    /// users assigned on run, labels: app_id, project_id, experiment, variation, member_kind
+    pub static ref ASSIGNMENTS: IntCounterVec = register(
+        "abof_experiment_assignments_total",
+        "Number of users assigned to experiments",
+        &["app_id", "project_id", "experiment", "variation", "member_kind"]
+    );
+
+    /// runs of users in the project holdout, who are not assigned to any experiment, labels: app_id, project_id
+    pub static ref HOLDOUTS: IntCounterVec = register(
+        "abof_holdout_runs_total",
+        "Number of runs of users held out of all the experiments of the project",
+        &["app_id", "project_id"]
+    );
+
+    /// failures in evaluating audience or frequency constraint scripts, labels: app_id, project_id, experiment
+    pub static ref SCRIPT_ERRORS: IntCounterVec = register(
+        "abof_script_errors_total",
+        "Number of failures in evaluating experiment scripts",
+        &["app_id", "project_id", "experiment"]
+    );
+
+    /// failures in parsing tracking cookie or tracking data, labels: app_id, project_id
+    pub static ref TRACKING_PARSE_FAILURES: IntCounterVec = register(
+        "abof_tracking_parse_failures_total",
+        "Number of failures in parsing tracking data",
+        &["app_id", "project_id"]
+    );
+
+    /// run contexts not matching the context schema of the project, labels: app_id, project_id
+    pub static ref CONTEXT_VALIDATION_FAILURES: IntCounterVec = register(
+        "abof_context_validation_failures_total",
+        "Number of run contexts not matching the context schema",
+        &["app_id", "project_id"]
+    );
+
+    /// experiments skipped in run on failure, or on their circuit breaker being open, labels: app_id, project_id, experiment, reason
+    pub static ref EXPERIMENT_SKIPS: IntCounterVec = register(
+        "abof_experiment_skips_total",
+        "Number of runs of experiments skipped on failures",
+        &["app_id", "project_id", "experiment", "reason"]
+    );
+}
+
+fn register(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+    METRICS_REGISTRY.register(Box::new(counter.clone())).unwrap();
+
+    counter
+}
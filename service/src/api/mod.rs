@@ -2,12 +2,13 @@ use async_trait::async_trait;
 use http::Response;
 use hyper::Body;
 
-use crate::server::{ApiError, HttpResponse, HttpRoute, Service};
+use crate::server::{ApiError, HttpRoute, Service};
 use crate::service::AbOptimisationService;
 
 mod common;
 mod experiment_runner;
 mod experiment_tracking_data;
+mod metrics;
 
 #[async_trait]
 impl Service for AbOptimisationService {
@@ -20,6 +21,18 @@ impl Service for AbOptimisationService {
 
             ["apps", app_id] if matches!(route.method, &http::Method::POST) => self.update_app(route, app_id, body).await,
 
+            ["apps", app_id] if matches!(route.method, &http::Method::PATCH) => self.patch_app(route, app_id, body).await,
+
+            ["apps", app_id] if matches!(route.method, &http::Method::DELETE) => self.delete_app(route, app_id).await,
+
+            ["apps", app_id, "export"] if matches!(route.method, &http::Method::GET) => self.export_app(route, app_id).await,
+
+            ["apps", app_id, "archive"] if matches!(route.method, &http::Method::POST) => self.archive_app(route, app_id, true).await,
+
+            ["apps", app_id, "unarchive"] if matches!(route.method, &http::Method::POST) => self.archive_app(route, app_id, false).await,
+
+            ["import"] if matches!(route.method, &http::Method::POST) => self.import_app(route, body).await,
+
             ["apps"] if matches!(route.method, &http::Method::POST) => self.add_app(route, body).await,
 
             ["apps"] if matches!(route.method, &http::Method::GET) => self.list_apps(route).await,
@@ -28,6 +41,26 @@ impl Service for AbOptimisationService {
 
             ["projects", app_id, project_id] if matches!(route.method, &http::Method::POST) => self.update_project(route, app_id, project_id, body).await,
 
+            ["projects", app_id, project_id] if matches!(route.method, &http::Method::PATCH) => self.patch_project(route, app_id, project_id, body).await,
+
+            ["projects", app_id, project_id] if matches!(route.method, &http::Method::DELETE) => self.delete_project(route, app_id, project_id).await,
+
+            ["projects", app_id, project_id, "archive"] if matches!(route.method, &http::Method::POST) => {
+                self.archive_project(route, app_id, project_id, true).await
+            }
+
+            ["projects", app_id, project_id, "unarchive"] if matches!(route.method, &http::Method::POST) => {
+                self.archive_project(route, app_id, project_id, false).await
+            }
+
+            ["projects", app_id, project_id, "context-schema", "lint"] if matches!(route.method, &http::Method::POST) => {
+                self.lint_script(route, app_id, project_id, body).await
+            }
+
+            ["projects", app_id, project_id, "context-schema", "completions"] if matches!(route.method, &http::Method::GET) => {
+                self.complete_script(route, app_id, project_id).await
+            }
+
             ["projects", app_id] if matches!(route.method, &http::Method::POST) => self.add_project(route, app_id, body).await,
 
             ["projects", app_id] if matches!(route.method, &http::Method::GET) => self.list_projects(route, app_id).await,
@@ -40,6 +73,42 @@ impl Service for AbOptimisationService {
                 self.update_audience_list(route, app_id, project_id, list_id, body).await
             }
 
+            ["audience-lists", app_id, project_id, list_id] if matches!(route.method, &http::Method::PATCH) => {
+                self.patch_audience_list(route, app_id, project_id, list_id, body).await
+            }
+
+            ["audience-lists", app_id, project_id, list_id] if matches!(route.method, &http::Method::DELETE) => {
+                self.delete_audience_list(route, app_id, project_id, list_id).await
+            }
+
+            ["audience-lists", app_id, project_id, list_id, "archive"] if matches!(route.method, &http::Method::POST) => {
+                self.archive_audience_list(route, app_id, project_id, list_id, true).await
+            }
+
+            ["audience-lists", app_id, project_id, list_id, "unarchive"] if matches!(route.method, &http::Method::POST) => {
+                self.archive_audience_list(route, app_id, project_id, list_id, false).await
+            }
+
+            ["audience-lists", app_id, project_id, list_id, "members", user_id] if matches!(route.method, &http::Method::GET) => {
+                self.get_audience_list_member(route, app_id, project_id, list_id, user_id).await
+            }
+
+            ["audience-lists", app_id, project_id, "members", user_id] if matches!(route.method, &http::Method::GET) => {
+                self.list_member_audience_lists(route, app_id, project_id, user_id).await
+            }
+
+            ["audience-lists", app_id, project_id, list_id, "members:add"] if matches!(route.method, &http::Method::POST) => {
+                self.add_audience_list_members(route, app_id, project_id, list_id, body).await
+            }
+
+            ["audience-lists", app_id, project_id, list_id, "members:remove"] if matches!(route.method, &http::Method::POST) => {
+                self.remove_audience_list_members(route, app_id, project_id, list_id, body).await
+            }
+
+            ["audience-lists", app_id, project_id, list_id, "members:upload"] if matches!(route.method, &http::Method::POST) => {
+                self.upload_audience_list_members(route, app_id, project_id, list_id, body).await
+            }
+
             ["audience-lists", app_id, project_id] if matches!(route.method, &http::Method::POST) => {
                 self.add_audience_list(route, app_id, project_id, body).await
             }
@@ -54,6 +123,50 @@ impl Service for AbOptimisationService {
                 self.update_experiment(route, app_id, project_id, experiment_id, body).await
             }
 
+            ["experiments", app_id, project_id, experiment_id] if matches!(route.method, &http::Method::PATCH) => {
+                self.patch_experiment(route, app_id, project_id, experiment_id, body).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id] if matches!(route.method, &http::Method::DELETE) => {
+                self.delete_experiment(route, app_id, project_id, experiment_id).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id, "submit"] if matches!(route.method, &http::Method::POST) => {
+                self.submit_experiment(route, app_id, project_id, experiment_id, body).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id, "approve"] if matches!(route.method, &http::Method::POST) => {
+                self.approve_experiment(route, app_id, project_id, experiment_id, body).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id, "reject"] if matches!(route.method, &http::Method::POST) => {
+                self.reject_experiment(route, app_id, project_id, experiment_id, body).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id, "comments"] if matches!(route.method, &http::Method::POST) => {
+                self.comment_experiment(route, app_id, project_id, experiment_id, body).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id, "pause"] if matches!(route.method, &http::Method::POST) => {
+                self.pause_experiment(route, app_id, project_id, experiment_id).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id, "resume"] if matches!(route.method, &http::Method::POST) => {
+                self.resume_experiment(route, app_id, project_id, experiment_id).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id, "conclude"] if matches!(route.method, &http::Method::POST) => {
+                self.conclude_experiment(route, app_id, project_id, experiment_id, body).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id, "archive"] if matches!(route.method, &http::Method::POST) => {
+                self.archive_experiment(route, app_id, project_id, experiment_id, true).await
+            }
+
+            ["experiments", app_id, project_id, experiment_id, "unarchive"] if matches!(route.method, &http::Method::POST) => {
+                self.archive_experiment(route, app_id, project_id, experiment_id, false).await
+            }
+
             ["experiments", app_id, project_id] if matches!(route.method, &http::Method::POST) => self.add_experiment(route, app_id, project_id, body).await,
 
             ["experiments", app_id, project_id] if matches!(route.method, &http::Method::GET) => self.list_experiments(route, app_id, project_id).await,
@@ -69,7 +182,56 @@ impl Service for AbOptimisationService {
             // ["search", rest @ ..] if matches!(route.method, &Method::POST) => search_handler(&mut route, body, rest, app).await,
             //
             // ["job-manager", rest @ ..] => HttpResponse::not_found(),
-            _ => HttpResponse::not_found(route.path),
+            _ => Err(ApiError::NotFound(format!("No route for path: {}", route.path))),
         }
     }
+
+    fn metric_path(&self, path: &[&str]) -> Option<&'static str> {
+        let metric_path = match path {
+            ["run"] => "/api/run",
+            ["import"] => "/api/import",
+
+            ["apps"] => "/api/apps",
+            ["apps", _] => "/api/apps/{app_id}",
+            ["apps", _, "export"] => "/api/apps/{app_id}/export",
+            ["apps", _, "archive"] => "/api/apps/{app_id}/archive",
+            ["apps", _, "unarchive"] => "/api/apps/{app_id}/unarchive",
+
+            ["projects", _] => "/api/projects/{app_id}",
+            ["projects", _, _] => "/api/projects/{app_id}/{project_id}",
+            ["projects", _, _, "archive"] => "/api/projects/{app_id}/{project_id}/archive",
+            ["projects", _, _, "unarchive"] => "/api/projects/{app_id}/{project_id}/unarchive",
+            ["projects", _, _, "context-schema", "lint"] => "/api/projects/{app_id}/{project_id}/context-schema/lint",
+            ["projects", _, _, "context-schema", "completions"] => "/api/projects/{app_id}/{project_id}/context-schema/completions",
+
+            ["audience-lists", _, _] => "/api/audience-lists/{app_id}/{project_id}",
+            ["audience-lists", _, _, _] => "/api/audience-lists/{app_id}/{project_id}/{list_id}",
+            ["audience-lists", _, _, _, "archive"] => "/api/audience-lists/{app_id}/{project_id}/{list_id}/archive",
+            ["audience-lists", _, _, _, "unarchive"] => "/api/audience-lists/{app_id}/{project_id}/{list_id}/unarchive",
+            ["audience-lists", _, _, "members", _] => "/api/audience-lists/{app_id}/{project_id}/members/{user_id}",
+            ["audience-lists", _, _, _, "members", _] => "/api/audience-lists/{app_id}/{project_id}/{list_id}/members/{user_id}",
+            ["audience-lists", _, _, _, "members:add"] => "/api/audience-lists/{app_id}/{project_id}/{list_id}/members:add",
+            ["audience-lists", _, _, _, "members:remove"] => "/api/audience-lists/{app_id}/{project_id}/{list_id}/members:remove",
+            ["audience-lists", _, _, _, "members:upload"] => "/api/audience-lists/{app_id}/{project_id}/{list_id}/members:upload",
+
+            ["experiments", _, _] => "/api/experiments/{app_id}/{project_id}",
+            ["experiments", _, _, _] => "/api/experiments/{app_id}/{project_id}/{experiment_id}",
+            ["experiments", _, _, _, action] => match *action {
+                "submit" => "/api/experiments/{app_id}/{project_id}/{experiment_id}/submit",
+                "approve" => "/api/experiments/{app_id}/{project_id}/{experiment_id}/approve",
+                "reject" => "/api/experiments/{app_id}/{project_id}/{experiment_id}/reject",
+                "comments" => "/api/experiments/{app_id}/{project_id}/{experiment_id}/comments",
+                "pause" => "/api/experiments/{app_id}/{project_id}/{experiment_id}/pause",
+                "resume" => "/api/experiments/{app_id}/{project_id}/{experiment_id}/resume",
+                "conclude" => "/api/experiments/{app_id}/{project_id}/{experiment_id}/conclude",
+                "archive" => "/api/experiments/{app_id}/{project_id}/{experiment_id}/archive",
+                "unarchive" => "/api/experiments/{app_id}/{project_id}/{experiment_id}/unarchive",
+                _ => return None,
+            },
+
+            _ => return None,
+        };
+
+        Some(metric_path)
+    }
 }
@@ -1,6 +1,7 @@
+use std::collections::HashSet;
 use std::ops::Deref;
 
-use anyhow::{anyhow, Context};
+use anyhow::anyhow;
 use crossbeam_epoch as epoch;
 use crossbeam_skiplist::SkipList;
 use epoch::Guard;
@@ -30,6 +31,9 @@ pub struct App {
     #[validate(length(min = 1, max = 5))]
     pub short_name: String,
 
+    #[serde(default)]
+    pub archived: bool,
+
     // todo: pub auth_key: String,
     #[serde(skip)]
     #[serde(default = "default_projects")]
@@ -73,16 +77,14 @@ impl AbOptimisationService {
     pub async fn add_app(&self, route: &HttpRoute<'_>, body: Body) -> HttpResult {
         let mut req_data = HttpRequest::value::<App>(route, body).await?;
 
-        let guard = &epoch::pin();
-
         // validate app data
-        self.validate_app_data(&req_data, None, guard)?;
+        self.validate_app_data(&req_data, None, &epoch::pin())?;
 
         let id = nanoid!();
         req_data.id = id.to_string();
-        self.experiment_store.write_app_data(&req_data)?;
+        self.experiment_store.write_app_data(&req_data).await?;
 
-        self.apps.insert(id.to_string(), RwLock::new(req_data), guard);
+        self.apps.insert(id.to_string(), RwLock::new(req_data), &epoch::pin());
 
         HttpResponse::binary_or_json(route, &AddResponse { id })
     }
@@ -90,19 +92,50 @@ impl AbOptimisationService {
     pub async fn update_app(&self, route: &HttpRoute<'_>, app_id: &str, body: Body) -> HttpResult {
         let req = HttpRequest::value::<App>(route, body).await?;
 
-        let guard = &epoch::pin();
+        self.update_app_with(route, app_id, req).await
+    }
 
-        // validate app data
-        self.validate_app_data(&req, Some(app_id), guard)?;
+    pub async fn patch_app(&self, route: &HttpRoute<'_>, app_id: &str, body: Body) -> HttpResult {
+        let patch = HttpRequest::patch(route, body).await?;
 
-        self.visit_app(app_id, guard, |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
-            let mut app_guard = entry.value().write();
-            AbOptimisationService::update_app_data(req, &mut app_guard);
+        let req = {
+            let guard = &epoch::pin();
 
-            self.experiment_store.write_app_data(&app_guard)?;
+            self.visit_app(app_id, guard, |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
+                let app_guard = entry.value().read();
 
-            HttpResponse::str(route, "SUCCESS")
-        })
+                patch.apply(app_guard.deref()).map_err(ApiError::BadRequest)
+            })?
+        };
+
+        self.update_app_with(route, app_id, req).await
+    }
+
+    async fn update_app_with(&self, route: &HttpRoute<'_>, app_id: &str, mut req: App) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            // validate app data
+            self.validate_app_data(&req, Some(app_id), guard)?;
+
+            self.visit_app(app_id, guard, |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
+                let mut app_guard = entry.value().write();
+
+                // archive state is changed only via archive / unarchive apis
+                req.archived = app_guard.archived;
+                AbOptimisationService::update_app_data(req, &mut app_guard);
+
+                batch.write_app_data(&app_guard)?;
+
+                HttpResponse::str(route, "SUCCESS")
+            })?
+        };
+
+        batch.commit().await?;
+
+        Ok(response)
     }
 
     fn update_app_data(req_data: App, app_guard: &mut RwLockWriteGuard<RawRwLock, App>) {
@@ -114,11 +147,15 @@ impl AbOptimisationService {
             app_guard.name = req_data.name
         }
 
+        if app_guard.archived != req_data.archived {
+            app_guard.archived = req_data.archived
+        }
+
         app_guard.modification_time = req_data.modification_time;
     }
 
-    fn validate_app_data(&self, data_to_validate: &App, update_id: Option<&str>, guard: &Guard) -> Result<(), ApiError> {
-        data_to_validate.validate().with_context(|| format!("Error in validating app data"))?;
+    pub(crate) fn validate_app_data(&self, data_to_validate: &App, update_id: Option<&str>, guard: &Guard) -> Result<(), ApiError> {
+        data_to_validate.validate().map_err(ApiError::Invalid)?;
 
         for entry in self.apps.iter(guard) {
             let value = entry.value();
@@ -142,6 +179,83 @@ impl AbOptimisationService {
         Ok(())
     }
 
+    pub async fn archive_app(&self, route: &HttpRoute<'_>, app_id: &str, archived: bool) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            self.visit_app(app_id, guard, |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
+                let mut app_guard = entry.value().write();
+
+                if app_guard.archived != archived {
+                    info!("Setting archived={} for app id: {}", archived, app_id);
+
+                    app_guard.archived = archived;
+                    batch.write_app_data(&app_guard)?;
+                }
+
+                HttpResponse::str(route, "SUCCESS")
+            })?
+        };
+
+        batch.commit().await?;
+
+        Ok(response)
+    }
+
+    pub async fn delete_app(&self, route: &HttpRoute<'_>, app_id: &str) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        {
+            let guard = &epoch::pin();
+
+            self.visit_app(app_id, guard, |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
+                let app_guard = entry.value().read();
+
+                // cascade to all projects of the app
+                for project_entry in app_guard.projects.iter(guard) {
+                    let project = project_entry.value().read();
+                    self.delete_project_from_store(app_id, &project, &mut batch, guard);
+                }
+
+                batch.delete_app_data(app_id);
+
+                Ok(())
+            })?;
+        }
+
+        batch.commit().await?;
+
+        let guard = &epoch::pin();
+        if let Some(entry) = self.apps.remove(app_id, guard) {
+            entry.release(guard);
+        }
+
+        info!("Deleted app for id: {}", app_id);
+
+        HttpResponse::str(route, "SUCCESS")
+    }
+
+    pub(crate) fn prune_apps(&self, loaded_ids: &HashSet<String>) {
+        let guard = &epoch::pin();
+
+        let deleted_ids: Vec<String> = self
+            .apps
+            .iter(guard)
+            .filter(|entry| !loaded_ids.contains(entry.key()))
+            .map(|entry| entry.key().to_string())
+            .collect();
+
+        for app_id in deleted_ids {
+            info!("Removing app for id: {}, as it doesn't exist in store anymore", app_id);
+
+            if let Some(entry) = self.apps.remove(&app_id, guard) {
+                entry.release(guard);
+            }
+        }
+    }
+
     pub async fn get_app(&self, route: &HttpRoute<'_>, app_id: &str) -> HttpResult {
         let guard = &epoch::pin();
 
@@ -174,3 +288,45 @@ impl AbOptimisationService {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_epoch as epoch;
+
+    use crate::core::fixtures::{local_store_config, local_store_path, request, route, write_app};
+    use crate::core::ExperimentState;
+    use crate::experiment_store::ExperimentStore;
+    use crate::service::AbOptimisationService;
+
+    #[tokio::test]
+    async fn delete_cascades_to_replica() -> anyhow::Result<()> {
+        let path = local_store_path("delete-cascade")?;
+        let service = AbOptimisationService::new(ExperimentStore::new_local_store(local_store_config(&path))?);
+        let replica = AbOptimisationService::new(ExperimentStore::new_local_store(local_store_config(&path))?);
+
+        write_app(&service.experiment_store, ExperimentState::Running).await?;
+        service.load_data().await?;
+        replica.load_data().await?;
+
+        let req = request("alice")?;
+        service.delete_project(&route(&req), "a1", "p1").await?;
+        assert!(service.visit_project("a1", "p1", &epoch::pin(), |_| Ok(())).is_err());
+
+        // experiments and lists of the project are deleted from store too
+        for dir in ["projects", "experiments", "audience_lists"].iter() {
+            assert_eq!(std::fs::read_dir(path.join(dir))?.count(), 0, "{} left in store", dir);
+        }
+
+        replica.load_data().await?;
+        assert!(replica.visit_project("a1", "p1", &epoch::pin(), |_| Ok(())).is_err());
+        assert!(replica.visit_app("a1", &epoch::pin(), |_| Ok(())).is_ok());
+
+        service.delete_app(&route(&req), "a1").await?;
+        replica.load_data().await?;
+        assert!(replica.visit_app("a1", &epoch::pin(), |_| Ok(())).is_err());
+
+        std::fs::remove_dir_all(&path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,354 @@
+use anyhow::{anyhow, Context};
+use bytes::Buf;
+use crossbeam_epoch as epoch;
+use hyper::Body;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::core::experiment::{ExperimentState, StateTransition};
+use crate::core::{Experiment, Project};
+use crate::server::{ApiError, ApiResult, HttpRequest, HttpResponse, HttpResult, HttpRoute};
+use crate::service::AbOptimisationService;
+
+/// Header carrying the user on whose behalf the request is made.
+pub const USER_HEADER: &str = "X-abof-user";
+
+/// Pending approval for an experiment.
+///
+/// Without `revision` and `transition` it is the launch approval of a draft experiment, otherwise it holds the pending
+/// edit or state change of a live experiment, which becomes active only after approval.
+#[derive(Serialize, Deserialize)]
+pub struct ApprovalRequest {
+    pub requested_by: String,
+    pub requested_at: chrono::DateTime<chrono::Local>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<Box<Experiment>>,
+
+    /// pending resume or conclusion of the experiment
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transition: Option<StateTransition>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Review {
+    pub user: String,
+    pub time: chrono::DateTime<chrono::Local>,
+    pub action: ReviewAction,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Copy, Clone, Debug)]
+pub enum ReviewAction {
+    Submit,
+    Approve,
+    Reject,
+    Comment,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ReviewRequest {
+    pub comment: Option<String>,
+}
+
+pub fn request_user(route: &HttpRoute<'_>) -> ApiResult<String> {
+    route
+        .req
+        .headers()
+        .get(USER_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string())
+        .ok_or_else(|| ApiError::Forbidden(format!("{} header is required", USER_HEADER)))
+}
+
+impl AbOptimisationService {
+    pub async fn submit_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, body: Body) -> HttpResult {
+        let user = request_user(route)?;
+        let req = Self::review_request(route, body).await?;
+
+        self.review_experiment(route, app_id, project_id, experiment_id, |_, experiment| {
+            if experiment.state != ExperimentState::Draft {
+                return Err(ApiError::BadRequest(anyhow!("Only draft experiment can be submitted for approval")));
+            }
+
+            experiment.state = ExperimentState::PendingApproval;
+            experiment.approval = Some(Box::new(ApprovalRequest {
+                requested_by: user.to_string(),
+                requested_at: chrono::Local::now(),
+                revision: None,
+                transition: None,
+            }));
+
+            Ok(Review {
+                user,
+                time: chrono::Local::now(),
+                action: ReviewAction::Submit,
+                comment: req.comment,
+            })
+        })
+        .await
+    }
+
+    pub async fn approve_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, body: Body) -> HttpResult {
+        let user = self.approver(route)?;
+        let req = Self::review_request(route, body).await?;
+
+        self.review_experiment(route, app_id, project_id, experiment_id, |project, experiment| {
+            let approval = experiment
+                .approval
+                .take()
+                .ok_or_else(|| ApiError::BadRequest(anyhow!("Experiment has nothing pending for approval")))?;
+
+            if approval.requested_by == user {
+                experiment.approval = Some(approval);
+                return Err(ApiError::Forbidden(format!("User {} can't approve own request", user)));
+            }
+
+            // lists, layers, context schema or other experiments may have changed since the request, hence validated again
+            let guard = &epoch::pin();
+            let to_launch = approval.revision.as_deref().unwrap_or(experiment);
+            if let Err(err) = AbOptimisationService::validate_experiment_in_project(project, to_launch, Some(experiment_id), guard) {
+                experiment.approval = Some(approval);
+                return Err(err);
+            }
+
+            // state may have changed since the request, e.g. experiment concluded while its resume was pending
+            if let Some(transition) = approval.transition.as_ref() {
+                if let Err(err) = transition.validate(experiment) {
+                    experiment.approval = Some(approval);
+                    return Err(err);
+                }
+            }
+
+            let ApprovalRequest { revision, transition, .. } = *approval;
+            match (revision, transition) {
+                (None, None) => {
+                    info!("Experiment:{} approved for launch by user:{}", experiment.id, user);
+                    experiment.state = ExperimentState::Running;
+                }
+                (None, Some(transition)) => {
+                    info!(
+                        "State change to {:?} of experiment:{} approved by user:{}",
+                        transition.state, experiment.id, user
+                    );
+                    transition.apply(experiment);
+                }
+                (Some(revision), _) => {
+                    info!("Pending revision of experiment:{} approved by user:{}", experiment.id, user);
+
+                    let mut revision = *revision;
+                    AbOptimisationService::carry_over_managed_fields(&mut revision, experiment);
+                    AbOptimisationService::update_experiment_data(revision, experiment);
+                }
+            }
+
+            Ok(Review {
+                user,
+                time: chrono::Local::now(),
+                action: ReviewAction::Approve,
+                comment: req.comment,
+            })
+        })
+        .await
+    }
+
+    pub async fn reject_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, body: Body) -> HttpResult {
+        let user = self.approver(route)?;
+        let req = Self::review_request(route, body).await?;
+
+        self.review_experiment(route, app_id, project_id, experiment_id, |_, experiment| {
+            let approval = experiment
+                .approval
+                .take()
+                .ok_or_else(|| ApiError::BadRequest(anyhow!("Experiment has nothing pending for approval")))?;
+
+            // rejected launch goes back to draft, while rejected revision or state change is dropped
+            if approval.revision.is_none() && approval.transition.is_none() {
+                experiment.state = ExperimentState::Draft;
+            }
+
+            Ok(Review {
+                user,
+                time: chrono::Local::now(),
+                action: ReviewAction::Reject,
+                comment: req.comment,
+            })
+        })
+        .await
+    }
+
+    pub async fn comment_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, body: Body) -> HttpResult {
+        let user = request_user(route)?;
+        let req = Self::review_request(route, body).await?;
+
+        if req.comment.is_none() {
+            return Err(ApiError::BadRequest(anyhow!("comment is required")));
+        }
+
+        self.review_experiment(route, app_id, project_id, experiment_id, |_, _| {
+            Ok(Review {
+                user,
+                time: chrono::Local::now(),
+                action: ReviewAction::Comment,
+                comment: req.comment,
+            })
+        })
+        .await
+    }
+
+    async fn review_experiment<F>(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, action: F) -> HttpResult
+    where
+        F: FnOnce(&Project, &mut Experiment) -> ApiResult<Review>,
+    {
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let project = entry.value().read();
+
+                let experiment_entry = project.experiments.get(experiment_id, guard).ok_or_else(|| {
+                    ApiError::NotFound(format!(
+                        "Experiment not found for id: {}, project id: {} and app id: {}",
+                        experiment_id, project_id, app_id
+                    ))
+                })?;
+
+                let mut existing_data = experiment_entry.value().write();
+
+                let review = action(&project, &mut existing_data)?;
+                existing_data.reviews.push(review);
+
+                batch.write_experiment_data(app_id, project_id, &existing_data)?;
+
+                HttpResponse::binary_or_json(route, &existing_data.reviews)
+            };
+
+            self.visit_project(app_id, project_id, guard, visitor)?
+        };
+
+        batch.commit().await?;
+
+        Ok(response)
+    }
+
+    fn approver(&self, route: &HttpRoute<'_>) -> ApiResult<String> {
+        let user = request_user(route)?;
+
+        if !self.approval.approvers.contains(&user) {
+            return Err(ApiError::Forbidden(format!("User {} doesn't have approver role", user)));
+        }
+
+        Ok(user)
+    }
+
+    async fn review_request(route: &HttpRoute<'_>, body: Body) -> ApiResult<ReviewRequest> {
+        let body = HttpRequest::bytes(route, body).await?;
+
+        // comment is optional, hence body too
+        if !body.has_remaining() {
+            return Ok(ReviewRequest::default());
+        }
+
+        serde_json::from_reader(body.reader())
+            .with_context(|| "Error in decoding review request")
+            .map_err(ApiError::BadRequest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+    use serde_json::json;
+
+    use crate::core::fixtures::{empty_service, experiment_json, request, route, visit_experiment, write_app};
+    use crate::core::ExperimentState;
+    use crate::server::ApiError;
+    use crate::service::AbOptimisationService;
+    use crate::settings::ApprovalConfig;
+
+    /// Fixture service having experiment `e1` in the given state, with approval workflow enabled for approvers alice and
+    /// bob.
+    async fn service(state: ExperimentState) -> anyhow::Result<AbOptimisationService> {
+        let mut service = empty_service();
+        service.approval = ApprovalConfig {
+            enabled: true,
+            approvers: vec!["alice".to_string(), "bob".to_string()],
+        };
+
+        write_app(&service.experiment_store, state).await?;
+        service.load_data().await?;
+
+        Ok(service)
+    }
+
+    #[tokio::test]
+    async fn self_approval() -> anyhow::Result<()> {
+        let service = service(ExperimentState::Draft).await?;
+
+        let req = request("alice")?;
+        service.submit_experiment(&route(&req), "a1", "p1", "e1", Body::empty()).await?;
+        assert_eq!(visit_experiment(&service, |experiment| experiment.state)?, ExperimentState::PendingApproval);
+
+        // requester can't approve own request, even being an approver, and non approvers can't approve at all
+        let result = service.approve_experiment(&route(&req), "a1", "p1", "e1", Body::empty()).await;
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+
+        let req = request("carol")?;
+        let result = service.approve_experiment(&route(&req), "a1", "p1", "e1", Body::empty()).await;
+        assert!(matches!(result, Err(ApiError::Forbidden(_))));
+
+        assert_eq!(visit_experiment(&service, |experiment| experiment.state)?, ExperimentState::PendingApproval);
+        assert!(visit_experiment(&service, |experiment| experiment.approval.is_some())?);
+
+        let req = request("bob")?;
+        service.approve_experiment(&route(&req), "a1", "p1", "e1", Body::empty()).await?;
+        assert_eq!(visit_experiment(&service, |experiment| experiment.state)?, ExperimentState::Running);
+        assert!(visit_experiment(&service, |experiment| experiment.approval.is_none())?);
+        assert_eq!(visit_experiment(&service, |experiment| experiment.reviews.len())?, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pending_revision() -> anyhow::Result<()> {
+        let service = service(ExperimentState::Running).await?;
+
+        let mut revision = experiment_json();
+        revision["data"] = json!({"color": "blue"});
+
+        // edit of a live experiment is kept pending, without changing the live config
+        let req = request("alice")?;
+        service
+            .update_experiment(&route(&req), "a1", "p1", "e1", Body::from(revision.to_string()))
+            .await?;
+        assert_eq!(
+            visit_experiment(&service, |experiment| (experiment.version, experiment.data.clone()))?,
+            (1, None)
+        );
+
+        // state change waits for the pending revision, as only one change is pending at a time
+        let result = service.conclude_experiment(&route(&req), "a1", "p1", "e1", Body::empty()).await;
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+
+        let req = request("bob")?;
+        service
+            .approve_experiment(&route(&req), "a1", "p1", "e1", Body::from(r#"{"comment": "lgtm"}"#))
+            .await?;
+
+        assert_eq!(
+            visit_experiment(&service, |experiment| (experiment.version, experiment.data.clone()))?,
+            (2, Some(json!({"color": "blue"})))
+        );
+        assert_eq!(visit_experiment(&service, |experiment| experiment.state)?, ExperimentState::Running);
+        assert!(visit_experiment(&service, |experiment| experiment.approval.is_none())?);
+
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 use std::ops::Deref;
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, bail, Context};
 use crossbeam_epoch as epoch;
 use crossbeam_epoch::Guard;
 use hyper::Body;
@@ -13,6 +13,8 @@ use parking_lot::{RawRwLock, RwLock};
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
+use crate::core::membership::{ListEncoding, MemberFormat, Members};
+use crate::core::segment::{SegmentSource, SegmentStatus};
 use crate::core::{skiplist_serde, AddResponse, HasId, Project};
 use crate::server::{ApiError, ApiResult, HttpRequest, HttpResponse, HttpResult, HttpRoute};
 use crate::service::AbOptimisationService;
@@ -25,7 +27,21 @@ pub struct AudienceList {
     #[validate(length(min = 1))]
     pub name: String,
 
-    pub list: HashSet<String>,
+    /// members, along with their encoding
+    #[serde(flatten)]
+    pub members: Members,
+
+    /// for a dynamic list, segment export its members are refreshed from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[validate]
+    pub segment: Option<SegmentSource>,
+
+    /// for a dynamic list, status of its refreshes from segment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub segment_status: Option<SegmentStatus>,
+
+    #[serde(default)]
+    pub archived: bool,
 
     #[serde(skip)]
     #[serde(default)]
@@ -38,6 +54,38 @@ impl HasId for AudienceList {
     }
 }
 
+#[derive(Deserialize)]
+struct MembersRequest {
+    ids: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct MembersResponse {
+    encoding: ListEncoding,
+    size: u64,
+}
+
+#[derive(Serialize)]
+struct MembershipResponse {
+    member: bool,
+
+    /// false for bloom encoded list, as membership may be a false positive
+    exact: bool,
+}
+
+#[derive(Serialize)]
+struct MemberListsResponse {
+    lists: Vec<MemberList>,
+}
+
+#[derive(Serialize)]
+struct MemberList {
+    id: String,
+    name: String,
+    archived: bool,
+    exact: bool,
+}
+
 impl AbOptimisationService {
     pub(crate) fn load_audience_list(
         &self,
@@ -79,18 +127,26 @@ impl AbOptimisationService {
     pub async fn add_audience_list(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, body: Body) -> HttpResult {
         let mut req_data = HttpRequest::value::<AudienceList>(route, body).await?;
 
-        let guard = &epoch::pin();
+        {
+            let guard = &epoch::pin();
 
-        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
-            let project = entry.value().read();
+            let validation_visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let project = entry.value().read();
+
+                self.validate_audience_list_data(&project, &req_data, None, guard)
+            };
 
-            self.validate_audience_list_data(&project, &req_data, None, guard)?;
+            self.visit_project(app_id, project_id, guard, validation_visitor)?;
+        }
 
-            let id = nanoid!();
-            req_data.id = id.to_string();
-            self.experiment_store.write_audience_list_data(app_id, project_id, &req_data)?;
+        let id = nanoid!();
+        req_data.id = id.to_string();
+        self.experiment_store.write_audience_list_data(app_id, project_id, &req_data).await?;
+
+        let guard = &epoch::pin();
 
-            project.audience_lists.insert(id.to_string(), RwLock::new(req_data), guard);
+        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+            entry.value().read().audience_lists.insert(id.to_string(), RwLock::new(req_data), guard);
 
             HttpResponse::binary_or_json(route, &AddResponse { id })
         };
@@ -101,27 +157,184 @@ impl AbOptimisationService {
     pub async fn update_audience_list(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str, body: Body) -> HttpResult {
         let req_data = HttpRequest::value::<AudienceList>(route, body).await?;
 
-        let guard = &epoch::pin();
+        self.update_audience_list_with(route, app_id, project_id, list_id, req_data).await
+    }
 
-        let validation_visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
-            let project = entry.value().read();
+    pub async fn patch_audience_list(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str, body: Body) -> HttpResult {
+        let patch = HttpRequest::patch(route, body).await?;
+
+        let req_data = {
+            let guard = &epoch::pin();
+
+            self.visit_audience_list(app_id, project_id, list_id, guard, |entry: crossbeam_skiplist::base::Entry<String, RwLock<AudienceList>>| {
+                let existing_data = entry.value().read();
 
-            self.validate_audience_list_data(&project, &req_data, Some(list_id), guard)
+                patch.apply(existing_data.deref()).map_err(ApiError::BadRequest)
+            })?
         };
 
-        self.visit_project(app_id, project_id, guard, validation_visitor)?;
+        self.update_audience_list_with(route, app_id, project_id, list_id, req_data).await
+    }
 
-        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<AudienceList>>| {
-            let mut existing_data = entry.value().write();
+    async fn update_audience_list_with(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str, mut req_data: AudienceList) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            let validation_visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let project = entry.value().read();
+
+                self.validate_audience_list_data(&project, &req_data, Some(list_id), guard)
+            };
 
-            AbOptimisationService::update_audience_list_data(req_data, &mut existing_data);
+            self.visit_project(app_id, project_id, guard, validation_visitor)?;
 
-            self.experiment_store.write_audience_list_data(app_id, project_id, &existing_data)?;
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<AudienceList>>| {
+                let mut existing_data = entry.value().write();
 
-            HttpResponse::str(route, "SUCCESS")
+                // archive state is changed only via archive / unarchive apis
+                req_data.archived = existing_data.archived;
+
+                // segment status is changed only on refresh, and is reset on change of segment, to refresh right away
+                if req_data.segment == existing_data.segment {
+                    req_data.segment_status = existing_data.segment_status.take();
+                }
+                AbOptimisationService::update_audience_list_data(req_data, &mut existing_data);
+
+                batch.write_audience_list_data(app_id, project_id, &existing_data)?;
+
+                HttpResponse::str(route, "SUCCESS")
+            };
+
+            self.visit_audience_list(app_id, project_id, list_id, guard, visitor)?
         };
 
-        self.visit_audience_list(app_id, project_id, list_id, guard, visitor)
+        batch.commit().await?;
+
+        Ok(response)
+    }
+
+    /// Adds the members to the list. As the whole list is written to store on every edit, many members are better added
+    /// at once, e.g. by upload with `mode=add`, than by many small edits.
+    pub async fn add_audience_list_members(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str, body: Body) -> HttpResult {
+        let req_data = HttpRequest::value::<MembersRequest>(route, body).await?;
+
+        self.edit_audience_list_members(route, app_id, project_id, list_id, |members| members.add(req_data.ids))
+            .await
+    }
+
+    pub async fn remove_audience_list_members(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str, body: Body) -> HttpResult {
+        let req_data = HttpRequest::value::<MembersRequest>(route, body).await?;
+
+        self.edit_audience_list_members(route, app_id, project_id, list_id, |members| members.remove(req_data.ids))
+            .await
+    }
+
+    /// Uploads members as CSV or NDJSON, streaming the body and building the members as it streams in. With
+    /// `mode=replace`, the default, uploaded members replace the existing ones, keeping the encoding of the list, where a
+    /// bloom filter is sized for the `capacity` param if given, else keeps its size. With `mode=add`, they are added to
+    /// the existing ones.
+    pub async fn upload_audience_list_members(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str, body: Body) -> HttpResult {
+        let format = route
+            .query_param("format")
+            .map_or(Ok(MemberFormat::Csv), MemberFormat::parse)
+            .map_err(ApiError::BadRequest)?;
+        let replace = match route.query_param("mode") {
+            None | Some("replace") => true,
+            Some("add") => false,
+            Some(mode) => return Err(ApiError::BadRequest(anyhow!("Unknown upload mode: {}", mode))),
+        };
+        let capacity = match (route.query_param("capacity"), replace) {
+            (None, _) => None,
+            (Some(capacity), true) => Some(
+                capacity
+                    .parse::<u64>()
+                    .with_context(|| format!("Invalid capacity: {}", capacity))
+                    .map_err(ApiError::BadRequest)?,
+            ),
+            (Some(_), false) => return Err(ApiError::BadRequest(anyhow!("Capacity is given only on upload with mode=replace"))),
+        };
+        let mut skip_header = route.query_param("header") == Some("true");
+
+        let mut builder = self.visit_audience_list(app_id, project_id, list_id, &epoch::pin(), |entry| {
+            let audience_list = entry.value().read();
+
+            audience_list.members.builder_like(capacity).map_err(ApiError::BadRequest)
+        })?;
+
+        HttpRequest::lines(route, body, |line| {
+            if skip_header {
+                skip_header = false;
+            } else {
+                let (id, _) = format.parse_line(line)?;
+                builder.add(id)?;
+            }
+
+            Ok(())
+        })
+        .await?;
+
+        let uploaded = builder.build();
+
+        info!(
+            "Uploading {} members for app:{}, project:{}, audience_list:{}, replace:{}",
+            uploaded.len(),
+            app_id,
+            project_id,
+            list_id,
+            replace
+        );
+
+        self.edit_audience_list_members(route, app_id, project_id, list_id, |members| {
+            if !replace {
+                return members.merge(uploaded);
+            }
+
+            // encoding may have been changed while uploading
+            if members.encoding() != uploaded.encoding() {
+                bail!("Encoding of the list changed during upload, upload the list again");
+            }
+
+            *members = uploaded;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn edit_audience_list_members<F>(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str, edit: F) -> HttpResult
+    where
+        F: FnOnce(&mut Members) -> anyhow::Result<()>,
+    {
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<AudienceList>>| {
+                let mut existing_data = entry.value().write();
+
+                edit(&mut existing_data.members).map_err(ApiError::BadRequest)?;
+
+                // members are encoded for store under read lock, so that run requests aren't blocked meanwhile
+                let existing_data = RwLockWriteGuard::downgrade(existing_data);
+                batch.write_audience_list_data(app_id, project_id, &existing_data)?;
+
+                HttpResponse::binary_or_json(
+                    route,
+                    &MembersResponse {
+                        encoding: existing_data.members.encoding(),
+                        size: existing_data.members.len(),
+                    },
+                )
+            };
+
+            self.visit_audience_list(app_id, project_id, list_id, guard, visitor)?
+        };
+
+        batch.commit().await?;
+
+        Ok(response)
     }
 
     fn update_audience_list_data(req_data: AudienceList, existing_data: &mut RwLockWriteGuard<RawRwLock, AudienceList>) {
@@ -129,13 +342,25 @@ impl AbOptimisationService {
             existing_data.name = req_data.name
         }
 
-        existing_data.list = req_data.list;
+        existing_data.members = req_data.members;
+        existing_data.segment = req_data.segment;
+        existing_data.segment_status = req_data.segment_status;
+
+        if existing_data.archived != req_data.archived {
+            existing_data.archived = req_data.archived
+        }
 
         existing_data.modification_time = req_data.modification_time;
     }
 
-    fn validate_audience_list_data(&self, project: &Project, data_to_validate: &AudienceList, update_id: Option<&str>, guard: &Guard) -> ApiResult<()> {
-        data_to_validate.validate().with_context(|| format!("Error in validating audience list data"))?;
+    pub(crate) fn validate_audience_list_data(
+        &self,
+        project: &Project,
+        data_to_validate: &AudienceList,
+        update_id: Option<&str>,
+        guard: &Guard,
+    ) -> ApiResult<()> {
+        data_to_validate.validate().map_err(ApiError::Invalid)?;
 
         for entry in project.audience_lists.iter(guard) {
             let value = entry.value();
@@ -155,6 +380,131 @@ impl AbOptimisationService {
         Ok(())
     }
 
+    pub async fn archive_audience_list(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str, archived: bool) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<AudienceList>>| {
+                let mut existing_data = entry.value().write();
+
+                if existing_data.archived != archived {
+                    info!(
+                        "Setting archived={} for app:{}, project:{}, audience_list:{}",
+                        archived, app_id, project_id, list_id
+                    );
+
+                    existing_data.archived = archived;
+                    batch.write_audience_list_data(app_id, project_id, &existing_data)?;
+                }
+
+                HttpResponse::str(route, "SUCCESS")
+            };
+
+            self.visit_audience_list(app_id, project_id, list_id, guard, visitor)?
+        };
+
+        batch.commit().await?;
+
+        Ok(response)
+    }
+
+    pub async fn delete_audience_list(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let project = entry.value().read();
+
+                if project.audience_lists.get(list_id, guard).is_none() {
+                    return Err(ApiError::NotFound(format!(
+                        "Audience List not found for list id: {}, project id: {} and app id: {}",
+                        list_id, project_id, app_id
+                    )));
+                }
+
+                // archived experiments are counted too, as they can be unarchived
+                let used_by: Vec<String> = project
+                    .experiments
+                    .iter(guard)
+                    .filter_map(|experiment_entry| {
+                        let experiment = experiment_entry.value().read();
+                        let uses_list = experiment.audiences.iter().any(|audience| audience.list_id.as_deref() == Some(list_id));
+
+                        if uses_list {
+                            Some(experiment.short_name.to_string())
+                        } else {
+                            None
+                        }
+                    })
+                    .collect();
+
+                if !used_by.is_empty() {
+                    return Err(ApiError::Conflict(format!(
+                        "Audience List {} is used by experiments: {}",
+                        list_id,
+                        used_by.join(", ")
+                    )));
+                }
+
+                batch.delete_audience_list_data(app_id, project_id, list_id);
+
+                Ok(())
+            };
+
+            self.visit_project(app_id, project_id, guard, visitor)?;
+        }
+
+        batch.commit().await?;
+
+        // project may have been deleted meanwhile, along with the list
+        let guard = &epoch::pin();
+        let _ = self.visit_project(app_id, project_id, guard, |entry| {
+            if let Some(list_entry) = entry.value().read().audience_lists.remove(list_id, guard) {
+                list_entry.release(guard);
+            }
+
+            Ok(())
+        });
+
+        info!("Deleted audience_list for app:{}, project:{}, list_id:{}", app_id, project_id, list_id);
+
+        HttpResponse::str(route, "SUCCESS")
+    }
+
+    pub(crate) fn prune_audience_lists(&self, loaded_ids: &HashSet<String>) {
+        let guard = &epoch::pin();
+
+        for app_entry in self.apps.iter(guard) {
+            let app = app_entry.value().read();
+
+            for project_entry in app.projects.iter(guard) {
+                let project = project_entry.value().read();
+
+                let deleted_ids: Vec<String> = project
+                    .audience_lists
+                    .iter(guard)
+                    .map(|entry| entry.key().to_string())
+                    .filter(|list_id| !loaded_ids.contains(&format!("{}.{}.{}", app.id, project.id, list_id)))
+                    .collect();
+
+                for list_id in deleted_ids {
+                    info!(
+                        "Removing audience_list for app:{}, project:{}, list_id:{}, as it doesn't exist in store anymore",
+                        app.id, project.id, list_id
+                    );
+
+                    if let Some(entry) = project.audience_lists.remove(&list_id, guard) {
+                        entry.release(guard);
+                    }
+                }
+            }
+        }
+    }
+
     pub async fn get_audience_list(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str) -> HttpResult {
         let guard = &epoch::pin();
 
@@ -168,6 +518,51 @@ impl AbOptimisationService {
         self.visit_audience_list(app_id, project_id, list_id, guard, visitor)
     }
 
+    pub async fn get_audience_list_member(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, list_id: &str, user_id: &str) -> HttpResult {
+        let guard = &epoch::pin();
+
+        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<AudienceList>>| {
+            let audience_list = entry.value().read();
+
+            HttpResponse::binary_or_json(
+                route,
+                &MembershipResponse {
+                    member: audience_list.members.contains(user_id),
+                    exact: audience_list.members.encoding() != ListEncoding::Bloom,
+                },
+            )
+        };
+
+        self.visit_audience_list(app_id, project_id, list_id, guard, visitor)
+    }
+
+    /// Audience lists of the project, archived ones included, which contain the user.
+    pub async fn list_member_audience_lists(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, user_id: &str) -> HttpResult {
+        let guard = &epoch::pin();
+
+        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+            let project = entry.value().read();
+
+            let mut lists = vec![];
+            for entry in project.audience_lists.iter(guard) {
+                let audience_list = entry.value().read();
+
+                if audience_list.members.contains(user_id) {
+                    lists.push(MemberList {
+                        id: audience_list.id.to_string(),
+                        name: audience_list.name.to_string(),
+                        archived: audience_list.archived,
+                        exact: audience_list.members.encoding() != ListEncoding::Bloom,
+                    });
+                }
+            }
+
+            HttpResponse::binary_or_json(route, &MemberListsResponse { lists })
+        };
+
+        self.visit_project(app_id, project_id, guard, visitor)
+    }
+
     pub async fn list_audience_lists(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str) -> HttpResult {
         let guard = &epoch::pin();
 
@@ -205,3 +600,106 @@ impl AbOptimisationService {
         self.visit_project(app_id, project_id, guard, proj_visitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_epoch as epoch;
+    use hyper::Body;
+    use serde_json::json;
+
+    use crate::core::fixtures::{local_store_config, local_store_path, request, response_json, route, service, write_app};
+    use crate::core::{AudienceList, ExperimentState};
+    use crate::experiment_store::ExperimentStore;
+    use crate::server::ApiError;
+    use crate::service::AbOptimisationService;
+
+    fn contains(service: &AbOptimisationService, list_id: &str, user_id: &str) -> anyhow::Result<bool> {
+        let guard = &epoch::pin();
+        let contains = service.visit_audience_list("a1", "p1", list_id, guard, |entry| Ok(entry.value().read().members.contains(user_id)))?;
+
+        Ok(contains)
+    }
+
+    #[tokio::test]
+    async fn delete_used_list() -> anyhow::Result<()> {
+        let service = service().await?;
+        let req = request("alice")?;
+
+        // l1 is used by the experiment, l2 by none
+        let result = service.delete_audience_list(&route(&req), "a1", "p1", "l1").await;
+        assert!(matches!(result, Err(ApiError::Conflict(_))));
+        assert!(service.visit_audience_list("a1", "p1", "l1", &epoch::pin(), |_| Ok(())).is_ok());
+
+        service.delete_audience_list(&route(&req), "a1", "p1", "l2").await?;
+        assert!(service.visit_audience_list("a1", "p1", "l2", &epoch::pin(), |_| Ok(())).is_err());
+
+        service.load_data().await?;
+        assert!(service.visit_audience_list("a1", "p1", "l2", &epoch::pin(), |_| Ok(())).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn member_lookup() -> anyhow::Result<()> {
+        let service = service().await?;
+
+        let mut bloom: AudienceList = serde_json::from_value(json!({"name": "bloom", "encoding": "bloom", "list": ["u1"]}))?;
+        bloom.id = "l3".to_string();
+        service.experiment_store.write_audience_list_data("a1", "p1", &bloom).await?;
+        service.load_data().await?;
+
+        let req = request("alice")?;
+        for (list_id, user_id, expected) in [
+            ("l1", "u1", json!({"member": true, "exact": true})),
+            ("l1", "u3", json!({"member": false, "exact": true})),
+            ("l3", "u1", json!({"member": true, "exact": false})),
+        ]
+        .iter()
+        {
+            let response = service.get_audience_list_member(&route(&req), "a1", "p1", list_id, user_id).await?;
+            assert_eq!(&response_json(response).await?, expected, "{} in {}", user_id, list_id);
+        }
+
+        let response = service.list_member_audience_lists(&route(&req), "a1", "p1", "u1").await?;
+        let member_lists = response_json(response).await?;
+        let mut lists: Vec<(&str, bool)> = member_lists["lists"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|list| (list["id"].as_str().unwrap_or_default(), list["exact"] == json!(true)))
+            .collect();
+        lists.sort_unstable();
+        assert_eq!(lists, vec![("l1", true), ("l3", false)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn add_and_remove_members() -> anyhow::Result<()> {
+        let path = local_store_path("members")?;
+        let service = AbOptimisationService::new(ExperimentStore::new_local_store(local_store_config(&path))?);
+        write_app(&service.experiment_store, ExperimentState::Running).await?;
+        service.load_data().await?;
+
+        let req = request("alice")?;
+        let response = service
+            .add_audience_list_members(&route(&req), "a1", "p1", "l2", Body::from(r#"{"ids": ["u4", "u5"]}"#))
+            .await?;
+        assert_eq!(response_json(response).await?["size"], json!(3));
+
+        let response = service
+            .remove_audience_list_members(&route(&req), "a1", "p1", "l2", Body::from(r#"{"ids": ["u3"]}"#))
+            .await?;
+        assert_eq!(response_json(response).await?["size"], json!(2));
+        assert!(contains(&service, "l2", "u4")? && !contains(&service, "l2", "u3")?);
+
+        // edits are persisted, and so loaded by other replicas
+        let replica = AbOptimisationService::new(ExperimentStore::new_local_store(local_store_config(&path))?);
+        replica.load_data().await?;
+        assert!(contains(&replica, "l2", "u4")? && contains(&replica, "l2", "u5")? && !contains(&replica, "l2", "u3")?);
+
+        std::fs::remove_dir_all(&path)?;
+
+        Ok(())
+    }
+}
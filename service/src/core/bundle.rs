@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+
+use anyhow::{anyhow, Context};
+use crossbeam_epoch as epoch;
+use crossbeam_epoch::Guard;
+use hyper::Body;
+use itertools::Itertools;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use nanoid::nanoid;
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use validator::Validate;
+
+use crate::core::evaluation_plan::EvaluationPlan;
+use crate::core::experiment::ExperimentState;
+use crate::core::{App, AudienceList, Experiment, Project};
+use crate::server::{ApiError, ApiResult, HttpRequest, HttpResponse, HttpResult, HttpRoute};
+use crate::service::AbOptimisationService;
+
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+
+lazy_static! {
+    /// ids as generated by the apis
+    static ref ID_RE: Regex = Regex::new(r"^[A-Za-z0-9_~-]+$").unwrap();
+}
+
+/// Snapshot of an app with all its projects, experiments and audience lists, for moving it between environments.
+///
+/// Ids are kept alongside the data, as entities don't deserialise their ids.
+#[derive(Serialize, Deserialize)]
+pub struct AppBundle {
+    pub format_version: u32,
+    pub id: String,
+    pub app: JsonValue,
+    pub projects: Vec<ProjectBundle>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProjectBundle {
+    pub id: String,
+    pub project: JsonValue,
+    pub experiments: Vec<BundleEntry>,
+    pub audience_lists: Vec<BundleEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BundleEntry {
+    pub id: String,
+    pub data: JsonValue,
+}
+
+#[derive(Serialize, Deserialize, Eq, PartialEq, Copy, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// keep ids as in the bundle, app must not exist already
+    Preserve,
+
+    /// assign new ids, references to audience lists are remapped
+    Remap,
+}
+
+impl ImportMode {
+    pub fn parse(value: &str) -> ApiResult<ImportMode> {
+        match value {
+            "preserve" => Ok(ImportMode::Preserve),
+            "remap" => Ok(ImportMode::Remap),
+            _ => Err(ApiError::BadRequest(anyhow!("Unknown import mode: {}", value))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportResponse {
+    pub app_id: String,
+
+    /// bundle id to imported id, for all imported entities
+    pub ids: HashMap<String, String>,
+}
+
+/// Typed and validated form of the bundle, ready to be written. Projects hold their experiments and audience lists, so
+/// that each of them is validated against the rest of the project, same as when added by the apis.
+struct ImportedApp {
+    id: String,
+    app: App,
+    projects: Vec<Project>,
+}
+
+impl AbOptimisationService {
+    pub async fn export_app(&self, route: &HttpRoute<'_>, app_id: &str) -> HttpResult {
+        let bundle = self.export_app_bundle(app_id)?;
+
+        HttpResponse::binary_or_json(route, &bundle)
+    }
+
+    pub async fn import_app(&self, route: &HttpRoute<'_>, body: Body) -> HttpResult {
+        let mode = route.query_param("mode").map_or(Ok(ImportMode::Preserve), ImportMode::parse)?;
+
+        let bundle = HttpRequest::value::<AppBundle>(route, body).await?;
+        let response = self.import_app_bundle(bundle, mode).await?;
+
+        HttpResponse::binary_or_json(route, &response)
+    }
+
+    pub fn export_app_bundle(&self, app_id: &str) -> ApiResult<AppBundle> {
+        let guard = &epoch::pin();
+
+        self.visit_app(app_id, guard, |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
+            let app = entry.value().read();
+
+            let mut projects = vec![];
+            for project_entry in app.projects.iter(guard) {
+                let project = project_entry.value().read();
+
+                let mut experiments = vec![];
+                for experiment_entry in project.experiments.iter(guard) {
+                    let experiment = experiment_entry.value().read();
+                    experiments.push(Self::bundle_entry(&experiment.id, experiment.deref())?);
+                }
+
+                let mut audience_lists = vec![];
+                for list_entry in project.audience_lists.iter(guard) {
+                    let audience_list = list_entry.value().read();
+                    audience_lists.push(Self::bundle_entry(&audience_list.id, audience_list.deref())?);
+                }
+
+                projects.push(ProjectBundle {
+                    id: project.id.to_string(),
+                    project: serde_json::to_value(project.deref()).with_context(|| "Error in serialising project")?,
+                    experiments,
+                    audience_lists,
+                });
+            }
+
+            Ok(AppBundle {
+                format_version: BUNDLE_FORMAT_VERSION,
+                id: app.id.to_string(),
+                app: serde_json::to_value(app.deref()).with_context(|| "Error in serialising app")?,
+                projects,
+            })
+        })
+    }
+
+    fn bundle_entry<T: Serialize>(id: &str, data: &T) -> ApiResult<BundleEntry> {
+        Ok(BundleEntry {
+            id: id.to_string(),
+            data: serde_json::to_value(data).with_context(|| format!("Error in serialising entity: {}", id))?,
+        })
+    }
+
+    /// Validates the whole bundle before writing anything, and then writes it to the store and loads it.
+    pub async fn import_app_bundle(&self, bundle: AppBundle, mode: ImportMode) -> ApiResult<ImportResponse> {
+        if bundle.format_version != BUNDLE_FORMAT_VERSION {
+            return Err(ApiError::BadRequest(anyhow!("Unsupported bundle format version: {}", bundle.format_version)));
+        }
+
+        let mut ids = HashMap::new();
+
+        let mut batch = self.experiment_store.batch();
+
+        let imported = {
+            let guard = &epoch::pin();
+
+            let imported = self.decode_bundle(bundle, mode, &mut ids, guard)?;
+
+            if mode == ImportMode::Preserve && self.apps.get(&imported.id, guard).is_some() {
+                return Err(ApiError::BadRequest(anyhow!("App with id={} already exists", imported.id)));
+            }
+
+            self.validate_app_data(&imported.app, None, guard)?;
+
+            // whole app is written as one batch, which is atomic with sqlite store
+            batch.write_app_data(&imported.app)?;
+            for project in imported.projects.iter() {
+                batch.write_project_data(&imported.id, project)?;
+
+                for entry in project.audience_lists.iter(guard) {
+                    batch.write_audience_list_data(&imported.id, &project.id, entry.value().read().deref())?;
+                }
+
+                for entry in project.experiments.iter(guard) {
+                    batch.write_experiment_data(&imported.id, &project.id, entry.value().read().deref())?;
+                }
+            }
+
+            imported
+        };
+
+        let app_id = imported.id.to_string();
+
+        info!("Importing app:{} with {} projects", app_id, imported.projects.len());
+
+        batch.commit().await?;
+
+        self.load_app(&app_id, imported.app, 0)?;
+
+        // app is new, hence projects are loaded as is, along with their experiments and audience lists
+        for project in imported.projects {
+            let project_id = project.id.to_string();
+            self.load_project("import", &app_id, &project_id, project, 0)?;
+        }
+
+        Ok(ImportResponse { app_id, ids })
+    }
+
+    fn decode_bundle(&self, bundle: AppBundle, mode: ImportMode, ids: &mut HashMap<String, String>, guard: &Guard) -> ApiResult<ImportedApp> {
+        let mut map_id = |id: &str| -> ApiResult<String> {
+            // ids are part of the store paths, hence only the ones the apis generate are accepted
+            if !ID_RE.is_match(id) {
+                return Err(ApiError::BadRequest(anyhow!("Invalid id: {} in bundle", id)));
+            }
+
+            let new_id = match mode {
+                ImportMode::Preserve => id.to_string(),
+                ImportMode::Remap => nanoid!(),
+            };
+
+            ids.insert(id.to_string(), new_id.to_string());
+            Ok(new_id)
+        };
+
+        let mut app: App = serde_json::from_value(bundle.app)
+            .with_context(|| "Error in decoding app in bundle")
+            .map_err(ApiError::BadRequest)?;
+        app.validate().with_context(|| "Error in validating app data").map_err(ApiError::BadRequest)?;
+        app.id = map_id(&bundle.id)?;
+
+        if !bundle.projects.iter().map(|project| &project.id).all_unique() {
+            return Err(ApiError::BadRequest(anyhow!("Duplicate project id found in bundle")));
+        }
+
+        let mut projects = vec![];
+        let mut short_names = HashSet::new();
+        for ProjectBundle {
+            id: project_id,
+            project,
+            experiments: experiment_entries,
+            audience_lists: list_entries,
+        } in bundle.projects
+        {
+            let mut project: Project = serde_json::from_value(project)
+                .with_context(|| format!("Error in decoding project: {}", project_id))
+                .map_err(ApiError::BadRequest)?;
+            project
+                .validate()
+                .with_context(|| format!("Error in validating project: {}", project_id))
+                .map_err(ApiError::BadRequest)?;
+            project.id = map_id(&project_id)?;
+
+            if !short_names.insert(project.short_name.to_string()) {
+                return Err(ApiError::BadRequest(anyhow!("Project with same short_name={} in bundle", project.short_name)));
+            }
+
+            // lists first, as experiments refer to them
+            let mut list_ids = HashMap::new();
+            for BundleEntry { id, data } in list_entries {
+                if list_ids.contains_key(&id) {
+                    return Err(ApiError::BadRequest(anyhow!("Duplicate audience list id: {} in bundle", id)));
+                }
+
+                let mut audience_list: AudienceList = serde_json::from_value(data)
+                    .with_context(|| format!("Error in decoding audience list: {}", id))
+                    .map_err(ApiError::BadRequest)?;
+                audience_list.id = map_id(&id)?;
+
+                self.validate_audience_list_data(&project, &audience_list, None, guard)?;
+
+                list_ids.insert(id, audience_list.id.to_string());
+                project.audience_lists.insert(audience_list.id.to_string(), RwLock::new(audience_list), guard);
+            }
+
+            let mut experiment_ids = HashSet::new();
+            for BundleEntry { id, data } in experiment_entries {
+                if !experiment_ids.insert(id.to_string()) {
+                    return Err(ApiError::BadRequest(anyhow!("Duplicate experiment id: {} in bundle", id)));
+                }
+
+                let mut experiment: Experiment = serde_json::from_value(data)
+                    .with_context(|| format!("Error in decoding experiment: {}", id))
+                    .map_err(ApiError::BadRequest)?;
+                experiment.id = map_id(&id)?;
+
+                for audience in experiment.audiences.iter_mut() {
+                    if let Some(list_id) = audience.list_id.as_mut() {
+                        *list_id = list_ids
+                            .get(list_id.as_str())
+                            .ok_or_else(|| ApiError::BadRequest(anyhow!("Audience list {} of experiment {} not in bundle", list_id, id)))?
+                            .to_string();
+                    }
+                }
+
+                // same validations as of an added experiment, against the lists and experiments of the project so far
+                Self::validate_experiment_in_project(&project, &experiment, None, guard)?;
+
+                // imported experiment goes through approval, same as a newly added one
+                if self.approval.enabled {
+                    experiment.state = ExperimentState::Draft;
+                    experiment.approval = None;
+                    experiment.reviews = vec![];
+                }
+
+                experiment.plan = EvaluationPlan::compile(&project.id, &experiment);
+                project.experiments.insert(experiment.id.to_string(), RwLock::new(experiment), guard);
+            }
+
+            projects.push(project);
+        }
+
+        Ok(ImportedApp {
+            id: app.id.to_string(),
+            app,
+            projects,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_epoch as epoch;
+    use serde_json::json;
+
+    use crate::core::bundle::ImportMode;
+    use crate::core::fixtures::{empty_service, service};
+    use crate::server::ApiError;
+
+    #[tokio::test]
+    async fn preserve_round_trip() -> anyhow::Result<()> {
+        let bundle = service().await?.export_app_bundle("a1")?;
+        let exported = serde_json::to_value(&bundle)?;
+
+        let target = empty_service();
+        let response = target.import_app_bundle(bundle, ImportMode::Preserve).await?;
+        assert_eq!(response.app_id, "a1");
+        assert_eq!(response.ids.len(), 5);
+        assert!(response.ids.iter().all(|(id, imported_id)| id == imported_id));
+
+        let imported = serde_json::to_value(&target.export_app_bundle("a1")?)?;
+        assert_eq!(imported["app"], exported["app"]);
+        assert_eq!(imported["projects"][0]["project"], exported["projects"][0]["project"]);
+        assert_eq!(imported["projects"][0]["audience_lists"], exported["projects"][0]["audience_lists"]);
+        assert_eq!(imported["projects"][0]["experiments"], exported["projects"][0]["experiments"]);
+
+        // existing app isn't overwritten
+        let result = target.import_app_bundle(target.export_app_bundle("a1")?, ImportMode::Preserve).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remap() -> anyhow::Result<()> {
+        let service = service().await?;
+
+        let mut bundle = service.export_app_bundle("a1")?;
+        bundle.app["name"] = json!("app copy");
+        bundle.app["short_name"] = json!("b");
+
+        let response = service.import_app_bundle(bundle, ImportMode::Remap).await?;
+        assert_ne!(response.app_id, "a1");
+        assert_eq!(response.ids["a1"], response.app_id);
+        assert!(response.ids.iter().all(|(id, imported_id)| id != imported_id));
+
+        // experiment refers to the imported list
+        let guard = &epoch::pin();
+        let list_id = service.visit_experiment(&response.app_id, &response.ids["p1"], &response.ids["e1"], guard, |entry| {
+            Ok(entry.value().read().audiences[0].list_id.clone())
+        })?;
+        assert_eq!(list_id.as_ref(), Some(&response.ids["l1"]));
+
+        // source app is kept as is
+        assert!(service.apps.get("a1", guard).is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_bundle() -> anyhow::Result<()> {
+        let mut bundle = service().await?.export_app_bundle("a1")?;
+        bundle.projects[0].experiments[0].id = "../e1".to_string();
+
+        let target = empty_service();
+        let result = target.import_app_bundle(bundle, ImportMode::Preserve).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+
+        let mut bundle = service().await?.export_app_bundle("a1")?;
+        bundle.projects[0].audience_lists.clear();
+
+        let result = target.import_app_bundle(bundle, ImportMode::Preserve).await;
+        assert!(matches!(result, Err(ApiError::BadRequest(_))));
+
+        // nothing is imported of an invalid bundle
+        assert!(target.apps.get("a1", &epoch::pin()).is_none());
+
+        Ok(())
+    }
+}
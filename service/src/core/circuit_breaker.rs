@@ -0,0 +1,109 @@
+use std::sync::atomic::{AtomicI64, AtomicU32, Ordering};
+
+use crate::settings;
+
+/// Breaker of an experiment failing in run, e.g. on script errors or a missing audience list.
+///
+/// After repeated failures the experiment is skipped in run for a while, instead of evaluating its broken scripts on
+/// every request. Once the open duration is over the experiment is tried again, and it is skipped again on the first
+/// failure, until it succeeds or is updated.
+#[derive(Default, Debug)]
+pub struct CircuitBreaker {
+    /// consecutive failures
+    failures: AtomicU32,
+
+    /// time in millis till which the experiment is skipped
+    open_until: AtomicI64,
+}
+
+impl CircuitBreaker {
+    pub fn is_open(&self, now: i64) -> bool {
+        now < self.open_until.load(Ordering::Relaxed)
+    }
+
+    pub fn record_success(&self) {
+        if self.failures.load(Ordering::Relaxed) != 0 {
+            self.failures.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the failure, and returns true if the breaker is opened by it.
+    pub fn record_failure(&self, now: i64) -> bool {
+        let config = settings::circuit_breaker_config();
+
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures < config.failure_threshold {
+            return false;
+        }
+
+        self.open_until.store(now + config.open_duration.as_millis() as i64, Ordering::Relaxed);
+        true
+    }
+
+    pub fn reset(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        self.open_until.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::circuit_breaker::CircuitBreaker;
+    use crate::settings;
+
+    #[test]
+    fn opens_at_threshold() {
+        let config = settings::circuit_breaker_config();
+        let open_duration = config.open_duration.as_millis() as i64;
+        let breaker = CircuitBreaker::default();
+
+        for _ in 1..config.failure_threshold {
+            assert!(!breaker.record_failure(1000));
+        }
+        assert!(!breaker.is_open(1000));
+
+        assert!(breaker.record_failure(1000));
+        assert!(breaker.is_open(1000));
+        assert!(breaker.is_open(1000 + open_duration - 1));
+        assert!(!breaker.is_open(1000 + open_duration));
+
+        // half open after the open duration, so opened again on the first failure
+        let now = 1000 + open_duration;
+        assert!(breaker.record_failure(now));
+        assert!(breaker.is_open(now));
+    }
+
+    #[test]
+    fn success_resets_failures() {
+        let config = settings::circuit_breaker_config();
+        let breaker = CircuitBreaker::default();
+
+        for _ in 1..config.failure_threshold {
+            breaker.record_failure(1000);
+        }
+        breaker.record_success();
+
+        for _ in 1..config.failure_threshold {
+            assert!(!breaker.record_failure(1000));
+        }
+        assert!(!breaker.is_open(1000));
+    }
+
+    #[test]
+    fn reset() {
+        let config = settings::circuit_breaker_config();
+        let breaker = CircuitBreaker::default();
+
+        for _ in 0..config.failure_threshold {
+            breaker.record_failure(1000);
+        }
+        assert!(breaker.is_open(1000));
+
+        breaker.reset();
+        assert!(!breaker.is_open(1000));
+
+        for _ in 1..config.failure_threshold {
+            assert!(!breaker.record_failure(1000));
+        }
+    }
+}
@@ -0,0 +1,543 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crossbeam_epoch as epoch;
+use hyper::Body;
+use parking_lot::RwLock;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::core::{Experiment, Project, Script};
+use crate::server::{ApiError, HttpRequest, HttpResponse, HttpResult, HttpRoute};
+use crate::service::AbOptimisationService;
+
+lazy_static! {
+    /// `ctx.field`, `ctx['field']` and `ctx.get('field', ...)` references of the context fields in a script
+    static ref CONTEXT_FIELD_REGEX: Regex = Regex::new(
+        r#"\bctx\s*(?:\.\s*get\s*\(\s*['"]([^'"]+)['"]|\[\s*['"]([^'"]+)['"]\s*\]|\.\s*([A-Za-z_][A-Za-z0-9_]*))"#
+    )
+    .unwrap();
+}
+
+/// Schema of the run context of a project, i.e. the fields that scripts of its experiments can refer as `ctx`.
+///
+/// It is a subset of JSON Schema of an object: types and enums of the fields, and the required fields.
+#[derive(Serialize, Deserialize, Validate, Clone, PartialEq, Debug, Default)]
+#[validate(schema(function = "validate_required_fields"))]
+pub struct ContextSchema {
+    #[serde(default)]
+    pub properties: BTreeMap<String, ContextField>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub required: Vec<String>,
+
+    /// whether the context can have fields other than the properties
+    #[serde(default = "default_additional_properties")]
+    #[serde(rename = "additionalProperties")]
+    pub additional_properties: bool,
+
+    /// how the contexts of run requests are validated against the schema
+    #[serde(default)]
+    pub validation: ContextValidation,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ContextField {
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+
+    /// allowed values of the field
+    #[serde(default)]
+    #[serde(rename = "enum")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<JsonValue>>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    String,
+    Number,
+    Integer,
+    Boolean,
+    Array,
+    Object,
+}
+
+/// Strictness of validating the contexts of run requests.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextValidation {
+    /// contexts are not validated
+    Off,
+
+    /// invalid contexts are logged and counted, and run as usual
+    Warn,
+
+    /// run requests with invalid contexts are rejected
+    Reject,
+}
+
+impl Default for ContextValidation {
+    fn default() -> Self {
+        ContextValidation::Warn
+    }
+}
+
+fn default_additional_properties() -> bool {
+    true
+}
+
+fn validate_required_fields(schema: &ContextSchema) -> Result<(), ValidationError> {
+    if schema.required.iter().any(|field| !schema.properties.contains_key(field)) {
+        return Err(ValidationError::new("Required field not found in properties"));
+    }
+
+    for field in schema.properties.values() {
+        if let Some(values) = field.values.as_ref() {
+            if values.is_empty() || values.iter().any(|value| !field.field_type.matches(value)) {
+                return Err(ValidationError::new("Enum values should be non empty and of the field type"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl FieldType {
+    fn matches(&self, value: &JsonValue) -> bool {
+        match self {
+            FieldType::String => value.is_string(),
+            FieldType::Number => value.is_number(),
+            FieldType::Integer => value.is_i64() || value.is_u64(),
+            FieldType::Boolean => value.is_boolean(),
+            FieldType::Array => value.is_array(),
+            FieldType::Object => value.is_object(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LintRequest {
+    script: String,
+}
+
+#[derive(Serialize)]
+struct LintResponse {
+    valid: bool,
+    errors: Vec<LintError>,
+}
+
+#[derive(Serialize)]
+struct LintError {
+    code: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct CompletionsResponse<'a> {
+    completions: Vec<Completion<'a>>,
+}
+
+#[derive(Serialize)]
+struct Completion<'a> {
+    /// text to insert, e.g. `ctx.app_version`
+    label: String,
+
+    field: &'a str,
+
+    #[serde(rename = "type")]
+    field_type: FieldType,
+
+    #[serde(rename = "enum")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    values: Option<&'a Vec<JsonValue>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+
+    required: bool,
+}
+
+impl ContextSchema {
+    /// Errors for the context fields referred in the script, which are not in the schema.
+    pub fn lint(&self, script: &Script) -> Vec<ValidationError> {
+        let mut errors = vec![];
+
+        for field in context_fields(&script.src) {
+            if self.properties.contains_key(field) {
+                continue;
+            }
+
+            let mut message = format!("Unknown context field: {} in script: {}", field, script.src);
+            if let Some(suggestion) = self.suggestion(field) {
+                message.push_str(&format!(", did you mean: {}?", suggestion));
+            }
+
+            let mut error = ValidationError::new("unknown_context_field");
+            error.message = Some(Cow::from(message));
+            error.add_param(Cow::from("field"), &field);
+            errors.push(error);
+        }
+
+        errors
+    }
+
+    /// Lint errors of the audience and frequency constraint scripts of the experiment.
+    pub fn lint_experiment(&self, experiment: &Experiment) -> Vec<ValidationError> {
+        experiment
+            .audiences
+            .iter()
+            .filter_map(|audience| audience.script_src.as_ref())
+            .chain(experiment.frequency_constraint.as_ref())
+            .flat_map(|script| self.lint(script))
+            .collect()
+    }
+
+    /// Errors by context field of the run request, for missing required fields, and fields not matching their type or
+    /// enum. Null fields are taken as missing.
+    pub fn validate_context(&self, context: Option<&JsonValue>) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        let empty = serde_json::Map::new();
+        let context = match context {
+            None | Some(JsonValue::Null) => &empty,
+            Some(JsonValue::Object(context)) => context,
+            Some(_) => {
+                errors.add("context", context_error("type", "Context should be an object".to_string(), "ctx"));
+                return Err(errors);
+            }
+        };
+
+        for field in self.required.iter() {
+            if context.get(field).map_or(true, JsonValue::is_null) {
+                errors.add(
+                    "context",
+                    context_error("required", format!("Required context field: {} is missing", field), field),
+                );
+            }
+        }
+
+        for (name, value) in context.iter().filter(|(_, value)| !value.is_null()) {
+            match self.properties.get(name) {
+                None if !self.additional_properties => {
+                    errors.add("context", context_error("unknown", format!("Unknown context field: {}", name), name));
+                }
+                None => {}
+                Some(field) if !field.field_type.matches(value) => {
+                    let message = format!("Context field: {} should be of type: {:?}", name, field.field_type);
+                    errors.add("context", context_error("type", message, name));
+                }
+                Some(field) if field.values.as_ref().map_or(false, |values| !values.contains(value)) => {
+                    errors.add("context", context_error("enum", format!("Context field: {} has value not in enum", name), name));
+                }
+                Some(_) => {}
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Field of the schema closest to the unknown field, if it is likely a typo of it.
+    fn suggestion(&self, field: &str) -> Option<&str> {
+        self.properties
+            .keys()
+            .map(|name| (edit_distance(field, name), name))
+            .filter(|(distance, name)| *distance <= 2.max(name.len() / 4))
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+/// Context fields referred in the script source, in the order of their first reference. Text inside string literals,
+/// e.g. `'ctx.name'`, is not a reference.
+pub fn context_fields(src: &str) -> Vec<&str> {
+    let literals = string_literals(src);
+    let mut fields: Vec<&str> = vec![];
+
+    for captures in CONTEXT_FIELD_REGEX.captures_iter(src) {
+        let start = captures.get(0).map_or(0, |reference| reference.start());
+        if literals.iter().any(|literal| literal.contains(&start)) {
+            continue;
+        }
+
+        let field = captures
+            .get(1)
+            .or_else(|| captures.get(2))
+            .or_else(|| captures.get(3))
+            .map(|field| field.as_str());
+
+        if let Some(field) = field {
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+    }
+
+    fields
+}
+
+/// Byte ranges of the single or double quoted string literals in the script source, quotes included. An unterminated
+/// literal runs till the end of the source.
+fn string_literals(src: &str) -> Vec<Range<usize>> {
+    let mut literals = vec![];
+    let mut open: Option<(char, usize)> = None;
+    let mut escaped = false;
+
+    for (index, c) in src.char_indices() {
+        match open {
+            None if c == '\'' || c == '"' => open = Some((c, index)),
+            None => {}
+            Some(_) if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some((quote, start)) if c == quote => {
+                literals.push(start..index + 1);
+                open = None;
+            }
+            Some(_) => {}
+        }
+    }
+
+    if let Some((_, start)) = open {
+        literals.push(start..src.len());
+    }
+
+    literals
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+fn context_error(code: &'static str, message: String, field: &str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::from(message));
+    error.add_param(Cow::from("field"), &field);
+
+    error
+}
+
+impl AbOptimisationService {
+    /// Lints the script against the context schema of the project, for editors of the audience scripts.
+    pub async fn lint_script(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, body: Body) -> HttpResult {
+        let req = HttpRequest::value::<LintRequest>(route, body).await?;
+
+        let guard = &epoch::pin();
+
+        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+            let project = entry.value().read();
+
+            let errors = match jexl_parser::Parser::parse(&req.script) {
+                Err(err) => vec![LintError {
+                    code: "syntax_error".to_string(),
+                    message: format!("Error in parsing script ==> {:?}", err),
+                }],
+                Ok(expression) => {
+                    let script = Script {
+                        src: req.script.to_string(),
+                        expression,
+                    };
+
+                    project
+                        .context_schema
+                        .as_ref()
+                        .map(|schema| schema.lint(&script))
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|error| LintError {
+                            code: error.code.to_string(),
+                            message: error.message.map(|message| message.to_string()).unwrap_or_default(),
+                        })
+                        .collect()
+                }
+            };
+
+            HttpResponse::json(
+                route,
+                &LintResponse {
+                    valid: errors.is_empty(),
+                    errors,
+                },
+            )
+        };
+
+        self.visit_project(app_id, project_id, guard, visitor)
+    }
+
+    /// Context fields of the project starting with the `prefix` query param, for autocomplete in editors of the audience
+    /// scripts.
+    pub async fn complete_script(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str) -> HttpResult {
+        let prefix = route.query_param("prefix").unwrap_or_default();
+        let prefix = prefix.strip_prefix("ctx.").unwrap_or(prefix);
+
+        let guard = &epoch::pin();
+
+        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+            let project = entry.value().read();
+
+            let schema = project
+                .context_schema
+                .as_ref()
+                .ok_or_else(|| ApiError::NotFound(format!("No context schema for project: {}", project_id)))?;
+
+            let completions = schema
+                .properties
+                .iter()
+                .filter(|(name, _)| name.starts_with(prefix))
+                .map(|(name, field)| Completion {
+                    label: format!("ctx.{}", name),
+                    field: name,
+                    field_type: field.field_type,
+                    values: field.values.as_ref(),
+                    description: field.description.as_deref(),
+                    required: schema.required.contains(name),
+                })
+                .collect();
+
+            HttpResponse::json(route, &CompletionsResponse { completions })
+        };
+
+        self.visit_project(app_id, project_id, guard, visitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use validator::Validate;
+
+    use crate::core::context_schema::{context_fields, edit_distance, ContextSchema};
+    use crate::core::Script;
+
+    fn schema() -> anyhow::Result<ContextSchema> {
+        let schema = serde_json::from_value(json!({
+            "properties": {
+                "app_version": {"type": "integer"},
+                "country": {"type": "string", "enum": ["IN", "US"]},
+                "new_user": {"type": "boolean"}
+            },
+            "required": ["app_version"],
+            "additionalProperties": false
+        }))?;
+
+        Ok(schema)
+    }
+
+    fn errors(schema: &ContextSchema, context: serde_json::Value) -> Vec<String> {
+        match schema.validate_context(Some(&context)) {
+            Ok(_) => vec![],
+            Err(errors) => errors.field_errors()["context"].iter().map(|error| error.code.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            context_fields("ctx.app_version > 10 && ctx['country'] == 'IN' && ctx.get(\"new_user\", false) && ctx.app_version < 20"),
+            vec!["app_version", "country", "new_user"]
+        );
+
+        // references inside string literals are not fields, including after escaped quotes
+        assert_eq!(
+            context_fields("ctx.name == 'ctx.other' && ctx.note == \"say \\\"ctx.quoted\\\"\""),
+            vec!["name", "note"]
+        );
+        assert!(context_fields("'unterminated ctx.field").is_empty());
+    }
+
+    #[test]
+    fn validate_context() -> anyhow::Result<()> {
+        let schema = schema()?;
+
+        assert!(errors(&schema, json!({"app_version": 12, "country": "IN"})).is_empty());
+
+        // null is taken as missing
+        assert!(errors(&schema, json!({"app_version": 12, "country": null})).is_empty());
+        assert_eq!(errors(&schema, json!({"app_version": null})), vec!["required"]);
+        assert!(schema.validate_context(None).is_err());
+
+        assert_eq!(errors(&schema, json!({"app_version": "12"})), vec!["type"]);
+        assert_eq!(errors(&schema, json!({"app_version": 12.5})), vec!["type"]);
+        assert_eq!(errors(&schema, json!({"app_version": 12, "country": "UK"})), vec!["enum"]);
+        assert_eq!(errors(&schema, json!({"app_version": 12, "city": "Pune"})), vec!["unknown"]);
+        assert_eq!(errors(&schema, json!([12])), vec!["type"]);
+
+        let schema = ContextSchema {
+            additional_properties: true,
+            ..schema
+        };
+        assert!(errors(&schema, json!({"app_version": 12, "city": "Pune"})).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_schema() -> anyhow::Result<()> {
+        assert!(schema()?.validate().is_ok());
+
+        let mut schema = schema()?;
+        schema.required.push("city".to_string());
+        assert!(schema.validate().is_err());
+
+        let schema: ContextSchema = serde_json::from_value(json!({
+            "properties": {"country": {"type": "string", "enum": ["IN", 1]}}
+        }))?;
+        assert!(schema.validate().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lint() -> anyhow::Result<()> {
+        let schema = schema()?;
+
+        let script: Script = serde_json::from_value(json!("ctx.app_versoin > 10 && ctx.country == 'IN' && ctx.city == 'Pune'"))?;
+        let errors = schema.lint(&script);
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].params["field"], json!("app_versoin"));
+        assert!(errors[0].message.as_ref().unwrap().ends_with("did you mean: app_version?"));
+        assert_eq!(errors[1].params["field"], json!("city"));
+        assert!(!errors[1].message.as_ref().unwrap().contains("did you mean"));
+
+        let script: Script = serde_json::from_value(json!("ctx.app_version > 10 && ctx.country == 'ctx.city'"))?;
+        assert!(schema.lint(&script).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("country", "country"), 0);
+        assert_eq!(edit_distance("contry", "country"), 1);
+        assert_eq!(edit_distance("app_versoin", "app_version"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}
@@ -0,0 +1,356 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Datelike, Local, Timelike};
+use http::header;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value as JsonValue};
+
+use crate::server::HttpRoute;
+use crate::settings;
+
+const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+lazy_static! {
+    /// GeoIP database, as configured by `geoip_database` setting
+    static ref GEOIP_READER: Option<maxminddb::Reader<Vec<u8>>> = open_geoip_database();
+}
+
+/// Attributes of the run request, derived from its metadata, which are made available to scripts as `req`, e.g.
+/// `req.user_agent.os == 'android'`. Each of the enrichers is enabled by its flag.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+pub struct RequestEnrichment {
+    /// `req.user_agent`: platform, os and browser parsed from `User-Agent` header
+    #[serde(default)]
+    pub user_agent: bool,
+
+    /// `req.languages`: languages of `Accept-Language` header in the order of preference, and `req.language`
+    #[serde(default)]
+    pub accept_language: bool,
+
+    /// `req.ip`: remote ip of the request
+    #[serde(default)]
+    pub ip: bool,
+
+    /// take remote ip from `X-Forwarded-For` header, when the service is behind proxies. The address is taken from the
+    /// right, as appended by the trusted proxies, and not from the left, which can be forged by the client
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+
+    /// number of trusted proxies in front of the service, each appending to `X-Forwarded-For`, 1 if not given. Remote ip
+    /// is the address this many from the right, as appended by the outermost of them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted_proxies: Option<u32>,
+
+    /// `req.geo`: country, region, city and time zone of the remote ip, as per the GeoIP database
+    #[serde(default)]
+    pub geo: bool,
+
+    /// `req.time`: time of the request, its hour and day of week, in the time zone of the service
+    #[serde(default)]
+    pub time: bool,
+}
+
+impl RequestEnrichment {
+    pub fn enrich(&self, route: &HttpRoute<'_>, time: &DateTime<Local>) -> JsonValue {
+        let mut attributes = Map::new();
+
+        if self.user_agent {
+            let user_agent = route.req.headers().get(header::USER_AGENT).and_then(|value| value.to_str().ok());
+            attributes.insert("user_agent".to_string(), user_agent.map_or(JsonValue::Null, parse_user_agent));
+        }
+
+        if self.accept_language {
+            let languages = route
+                .req
+                .headers()
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|value| value.to_str().ok())
+                .map_or_else(Vec::new, parse_accept_language);
+
+            attributes.insert("language".to_string(), languages.first().map_or(JsonValue::Null, |language| json!(language)));
+            attributes.insert("languages".to_string(), json!(languages));
+        }
+
+        if self.ip || self.geo {
+            let ip = self.remote_ip(route);
+
+            if self.ip {
+                attributes.insert("ip".to_string(), json!(ip.to_string()));
+            }
+
+            if self.geo {
+                attributes.insert("geo".to_string(), lookup_geo(ip));
+            }
+        }
+
+        if self.time {
+            attributes.insert(
+                "time".to_string(),
+                json!({
+                    "timestamp": time.timestamp_millis(),
+                    "date": time.format("%Y-%m-%d").to_string(),
+                    "hour": time.hour(),
+                    "day_of_week": time.format("%A").to_string().to_lowercase(),
+                    "weekend": time.weekday().number_from_monday() > 5,
+                }),
+            );
+        }
+
+        JsonValue::Object(attributes)
+    }
+
+    /// Remote ip as per `X-Forwarded-For` header if trusted, else the peer address, which is also the fallback when the
+    /// header has fewer addresses than the trusted proxies.
+    fn remote_ip(&self, route: &HttpRoute<'_>) -> IpAddr {
+        let forwarded_for = if self.trust_forwarded_for {
+            let addresses: Vec<&str> = route
+                .req
+                .headers()
+                .get_all(FORWARDED_FOR_HEADER)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .collect();
+
+            forwarded_address(&addresses, self.trusted_proxies.unwrap_or(1))
+        } else {
+            None
+        };
+
+        forwarded_for.unwrap_or_else(|| route.remote_addr.ip())
+    }
+}
+
+/// Address appended by the outermost of the trusted proxies, i.e. the one as many from the right as the proxies.
+fn forwarded_address(addresses: &[&str], trusted_proxies: u32) -> Option<IpAddr> {
+    let hops = (trusted_proxies as usize).max(1);
+
+    addresses.iter().rev().nth(hops - 1).and_then(|ip| ip.trim().parse::<IpAddr>().ok())
+}
+
+/// Platform, os and browser of the user agent, detected from the well known tokens in it.
+fn parse_user_agent(user_agent: &str) -> JsonValue {
+    let ua = user_agent.to_ascii_lowercase();
+
+    let os = if ua.contains("android") {
+        "android"
+    } else if ua.contains("iphone") || ua.contains("ipad") || ua.contains("ipod") {
+        "ios"
+    } else if ua.contains("windows") {
+        "windows"
+    } else if ua.contains("cros ") {
+        "chrome_os"
+    } else if ua.contains("mac os x") || ua.contains("macintosh") {
+        "mac_os"
+    } else if ua.contains("linux") {
+        "linux"
+    } else {
+        "other"
+    };
+
+    // order matters, as e.g. edge and opera mention chrome and safari too
+    let browser = if ua.contains("edg/") || ua.contains("edge/") {
+        "edge"
+    } else if ua.contains("opr/") || ua.contains("opera") {
+        "opera"
+    } else if ua.contains("samsungbrowser") {
+        "samsung"
+    } else if ua.contains("firefox/") || ua.contains("fxios/") {
+        "firefox"
+    } else if ua.contains("chrome/") || ua.contains("crios/") {
+        "chrome"
+    } else if ua.contains("safari/") {
+        "safari"
+    } else {
+        "other"
+    };
+
+    let platform = if ua.contains("bot") || ua.contains("crawler") || ua.contains("spider") {
+        "bot"
+    } else if ua.contains("ipad") || ua.contains("tablet") || (os == "android" && !ua.contains("mobile")) {
+        "tablet"
+    } else if ua.contains("mobi") || os == "ios" || os == "android" {
+        "mobile"
+    } else {
+        "desktop"
+    };
+
+    json!({
+        "raw": user_agent,
+        "platform": platform,
+        "os": os,
+        "browser": browser,
+    })
+}
+
+/// Languages of `Accept-Language` header, sorted by their quality.
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut parts = part.split(';');
+            let language = parts.next()?.trim();
+
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if language.is_empty() || language == "*" || quality <= 0.0 {
+                None
+            } else {
+                Some((language.to_string(), quality))
+            }
+        })
+        .collect();
+
+    // stable sort keeps the order of the header for the same quality
+    languages.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+    languages.into_iter().map(|(language, _)| language).collect()
+}
+
+fn open_geoip_database() -> Option<maxminddb::Reader<Vec<u8>>> {
+    let path = settings::geoip_database()?;
+
+    match maxminddb::Reader::open_readfile(&path) {
+        Ok(reader) => {
+            info!("Opened GeoIP database: {}", path);
+            Some(reader)
+        }
+        Err(err) => {
+            error!("Error in opening GeoIP database: {} ==> {:?}", path, err);
+            None
+        }
+    }
+}
+
+fn lookup_geo(ip: IpAddr) -> JsonValue {
+    let reader = match GEOIP_READER.as_ref() {
+        Some(reader) => reader,
+        None => return JsonValue::Null,
+    };
+
+    // private and unknown ips are not in the database
+    let city = match reader.lookup::<maxminddb::geoip2::City>(ip) {
+        Ok(city) => city,
+        Err(_) => return JsonValue::Null,
+    };
+
+    let country = city.country.as_ref().and_then(|country| country.iso_code);
+    let region = city
+        .subdivisions
+        .as_ref()
+        .and_then(|subdivisions| subdivisions.first())
+        .and_then(|subdivision| subdivision.iso_code);
+    let name = city
+        .city
+        .as_ref()
+        .and_then(|city| city.names.as_ref())
+        .and_then(|names| names.get("en").copied());
+    let time_zone = city.location.as_ref().and_then(|location| location.time_zone);
+
+    json!({
+        "country": country,
+        "region": region,
+        "city": name,
+        "time_zone": time_zone,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use hyper::{Body, Request};
+    use serde_json::json;
+
+    use crate::core::enrichment::{forwarded_address, parse_accept_language, parse_user_agent, RequestEnrichment};
+    use crate::server::HttpRoute;
+
+    #[test]
+    fn forwarded_for() {
+        let addresses = ["1.1.1.1", " 10.0.0.1", " 10.0.0.2"];
+
+        assert_eq!(forwarded_address(&addresses, 1), Some("10.0.0.2".parse().unwrap()));
+        assert_eq!(forwarded_address(&addresses, 0), Some("10.0.0.2".parse().unwrap()));
+        assert_eq!(forwarded_address(&addresses, 3), Some("1.1.1.1".parse().unwrap()));
+        assert_eq!(forwarded_address(&addresses, 4), None);
+        assert_eq!(forwarded_address(&["unknown"], 1), None);
+    }
+
+    #[test]
+    fn remote_ip() -> anyhow::Result<()> {
+        // client forged address on the left is not taken, and addresses are taken across the repeated headers
+        let req = Request::builder()
+            .header("X-Forwarded-For", "6.6.6.6, 1.1.1.1")
+            .header("X-Forwarded-For", "10.0.0.1")
+            .body(Body::empty())?;
+        let route = HttpRoute::new(&req, chrono::Local::now(), Instant::now(), "127.0.0.1:8080".parse()?);
+
+        let mut enrichment = RequestEnrichment {
+            ip: true,
+            ..RequestEnrichment::default()
+        };
+        assert_eq!(enrichment.enrich(&route, &route.req_time), json!({"ip": "127.0.0.1"}));
+
+        enrichment.trust_forwarded_for = true;
+        assert_eq!(enrichment.enrich(&route, &route.req_time), json!({"ip": "10.0.0.1"}));
+
+        enrichment.trusted_proxies = Some(2);
+        assert_eq!(enrichment.enrich(&route, &route.req_time), json!({"ip": "1.1.1.1"}));
+
+        enrichment.trusted_proxies = Some(4);
+        assert_eq!(enrichment.enrich(&route, &route.req_time), json!({"ip": "127.0.0.1"}));
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_agent() {
+        let attributes = |user_agent: &str| {
+            let parsed = parse_user_agent(user_agent);
+            (parsed["platform"].clone(), parsed["os"].clone(), parsed["browser"].clone())
+        };
+
+        assert_eq!(
+            attributes("Mozilla/5.0 (Linux; Android 11; Pixel 5) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/93.0.4577.62 Mobile Safari/537.36"),
+            (json!("mobile"), json!("android"), json!("chrome"))
+        );
+        assert_eq!(
+            attributes("Mozilla/5.0 (iPad; CPU OS 14_7 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/14.1.2 Mobile/15E148 Safari/604.1"),
+            (json!("tablet"), json!("ios"), json!("safari"))
+        );
+        assert_eq!(
+            attributes("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/93.0.4577.63 Safari/537.36 Edg/93.0.961.38"),
+            (json!("desktop"), json!("windows"), json!("edge"))
+        );
+        assert_eq!(
+            attributes("Mozilla/5.0 (X11; CrOS x86_64 14092.77.0) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/93.0.4577.85 Safari/537.36"),
+            (json!("desktop"), json!("chrome_os"), json!("chrome"))
+        );
+        assert_eq!(
+            attributes("Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:92.0) Gecko/20100101 Firefox/92.0"),
+            (json!("desktop"), json!("mac_os"), json!("firefox"))
+        );
+        assert_eq!(
+            attributes("Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"),
+            (json!("bot"), json!("other"), json!("other"))
+        );
+
+        // "cros" in other tokens isn't taken as chrome os, e.g. in "Macros"
+        assert_eq!(attributes("Macros/1.0 (X11; Linux x86_64)").1, json!("linux"));
+    }
+
+    #[test]
+    fn accept_language() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+            vec!["fr-CH", "fr", "en", "de"]
+        );
+        assert_eq!(parse_accept_language("en;q=0.5, hi, mr;q=0.5"), vec!["hi", "en", "mr"]);
+        assert_eq!(parse_accept_language("en;q=0, hi;q=abc"), vec!["hi"]);
+        assert!(parse_accept_language("").is_empty());
+    }
+}
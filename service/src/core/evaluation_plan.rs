@@ -0,0 +1,113 @@
+use anyhow::ensure;
+
+use crate::core::experiment::{Audience, Experiment};
+
+/// Values derived from the config of an experiment, which are needed to evaluate it in run.
+///
+/// They are computed once when the experiment is loaded, added or updated, instead of on every run request. Plan has
+/// only the hash seeds for now. Compiled scripts and resolved list handles are follow-up work:
+/// - scripts are parsed into their AST on deserializing the experiment, but are still cloned on every evaluation, until
+///   the jexl evaluator can evaluate an AST by reference
+/// - audience lists are still looked up by id in run, as a handle resolved on compile needs re-resolving on every add,
+///   update, reload and prune of the lists, to not keep serving a replaced list
+#[derive(Default, Debug)]
+pub struct EvaluationPlan {
+    /// seed of the user hash in the experiment, as per its project and id
+    pub experiment_seed: u64,
+
+    /// seeds of the user hash in the audiences, in the order of the audiences of the experiment
+    audience_seeds: Vec<u64>,
+}
+
+impl EvaluationPlan {
+    pub fn compile(project_id: &str, experiment: &Experiment) -> Self {
+        EvaluationPlan {
+            experiment_seed: seahash::hash(format!("{}/{}", project_id, experiment.id).as_bytes()),
+            audience_seeds: audience_seeds(&experiment.audiences),
+        }
+    }
+
+    /// Plan for the updated config of the experiment, keeping the experiment seed, as its project and id don't change.
+    pub fn recompile(&self, experiment: &Experiment) -> Self {
+        EvaluationPlan {
+            experiment_seed: self.experiment_seed,
+            audience_seeds: audience_seeds(&experiment.audiences),
+        }
+    }
+
+    /// Audiences of the experiment along with their seeds.
+    ///
+    /// Fails if the plan wasn't recompiled on change of the audiences, instead of skipping the audiences without seed.
+    pub fn audiences<'a>(&'a self, audiences: &'a [Audience]) -> anyhow::Result<impl Iterator<Item = (&'a Audience, u64)>> {
+        ensure!(
+            audiences.len() == self.audience_seeds.len(),
+            "Stale evaluation plan, having {} audience seeds for {} audiences",
+            self.audience_seeds.len(),
+            audiences.len()
+        );
+
+        Ok(audiences.iter().zip(self.audience_seeds.iter().copied()))
+    }
+}
+
+fn audience_seeds(audiences: &[Audience]) -> Vec<u64> {
+    audiences.iter().map(|audience| seahash::hash(audience.name.as_bytes())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::core::evaluation_plan::EvaluationPlan;
+    use crate::core::Experiment;
+
+    fn experiment(audiences: &[&str]) -> anyhow::Result<Experiment> {
+        let audiences = audiences
+            .iter()
+            .map(|name| json!({"name": name, "size_kind": "Percent", "value": 10}))
+            .collect::<Vec<_>>();
+
+        let mut experiment: Experiment = serde_json::from_value(json!({
+            "name": "experiment",
+            "short_name": "e",
+            "audiences": audiences,
+        }))?;
+        experiment.id = "e1".to_string();
+
+        Ok(experiment)
+    }
+
+    #[test]
+    fn seeds() -> anyhow::Result<()> {
+        let experiment = experiment(&["all", "new users"])?;
+        let plan = EvaluationPlan::compile("p1", &experiment);
+
+        // same as the seeds computed per run request, before they were precomputed, so that users stay in their buckets
+        assert_eq!(plan.experiment_seed, seahash::hash("p1/e1".as_bytes()));
+
+        let seeds = plan
+            .audiences(&experiment.audiences)?
+            .map(|(audience, seed)| (audience.name.as_str(), seed))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            seeds,
+            vec![("all", seahash::hash("all".as_bytes())), ("new users", seahash::hash("new users".as_bytes()))]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn recompile() -> anyhow::Result<()> {
+        let plan = EvaluationPlan::compile("p1", &experiment(&["all"])?);
+
+        let updated = experiment(&["all", "new users"])?;
+        assert!(plan.audiences(&updated.audiences).is_err());
+
+        let plan = plan.recompile(&updated);
+        assert_eq!(plan.experiment_seed, seahash::hash("p1/e1".as_bytes()));
+        assert_eq!(plan.audiences(&updated.audiences)?.count(), 2);
+
+        Ok(())
+    }
+}
@@ -1,7 +1,10 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::ops::Deref;
 use std::sync::atomic::{AtomicI64, AtomicU64};
 
 use anyhow::{anyhow, Context};
+use bytes::Buf;
 use crossbeam_epoch as epoch;
 use crossbeam_epoch::Guard;
 use hyper::Body;
@@ -9,12 +12,15 @@ use itertools::Itertools;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 use nanoid::nanoid;
-use parking_lot::lock_api::RwLockWriteGuard;
-use parking_lot::{RawRwLock, RwLock};
+use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use validator::{Validate, ValidationError};
+use validator::{Validate, ValidationError, ValidationErrors};
 
+use crate::core::approval::{request_user, ApprovalRequest, Review};
+use crate::core::circuit_breaker::CircuitBreaker;
+use crate::core::evaluation_plan::EvaluationPlan;
+use crate::core::layer::LayerAllocation;
 use crate::core::script::Script;
 use crate::core::{skiplist_serde, AddResponse, HasId, Project};
 use crate::server::{ApiError, ApiResult, HttpRequest, HttpResponse, HttpResult, HttpRoute};
@@ -42,6 +48,24 @@ pub struct Experiment {
     #[serde(default)]
     pub inactive: bool,
 
+    #[serde(default)]
+    pub archived: bool,
+
+    #[serde(default)]
+    pub state: ExperimentState,
+
+    /// Short name of the winning variation of a concluded experiment.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub winner: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub approval: Option<Box<ApprovalRequest>>,
+
+    #[serde(default)]
+    pub reviews: Vec<Review>,
+
     pub start_time: Option<String>,
     pub end_time: Option<String>,
 
@@ -58,6 +82,11 @@ pub struct Experiment {
 
     pub data: Option<JsonValue>,
 
+    #[validate]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<LayerAllocation>,
+
     #[serde(default = "default_sampler")]
     #[serde(skip)]
     pub variation_sampler: rand::distributions::Uniform<u64>,
@@ -73,6 +102,13 @@ pub struct Experiment {
     #[serde(skip)]
     #[serde(default)]
     pub modification_time: i64,
+
+    #[serde(skip)]
+    pub circuit_breaker: CircuitBreaker,
+
+    /// precomputed values for evaluating the experiment in run
+    #[serde(skip)]
+    pub plan: EvaluationPlan,
 }
 
 impl PartialEq for Experiment {
@@ -89,6 +125,7 @@ impl PartialEq for Experiment {
             &self.audiences,
             &self.variations,
             &self.data,
+            &self.layer,
         ) == (
             &other.id,
             &other.name,
@@ -101,6 +138,7 @@ impl PartialEq for Experiment {
             &other.audiences,
             &other.variations,
             &other.data,
+            &other.layer,
         )
     }
 }
@@ -123,6 +161,83 @@ impl Default for ExperimentKind {
     }
 }
 
+/// Lifecycle state of the experiment.
+///
+/// Only `Running` experiments assign new users. `Paused` experiments keep the existing assignments as is,
+/// while `Concluded` experiments serve the winner to all targeted users.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Copy, Clone, Debug)]
+pub enum ExperimentState {
+    Draft,
+    PendingApproval,
+    Running,
+    Paused,
+    Concluded,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ConcludeRequest {
+    pub winner: Option<String>,
+}
+
+/// Change of state of the experiment by its lifecycle apis, which is kept pending till approved, when it makes the
+/// experiment live again or rolls out its winner.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StateTransition {
+    pub state: ExperimentState,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub winner: Option<String>,
+}
+
+impl StateTransition {
+    /// Validates the transition from the current state of the experiment.
+    pub fn validate(&self, experiment: &Experiment) -> ApiResult<()> {
+        match self.state {
+            ExperimentState::Paused if experiment.state != ExperimentState::Running => {
+                Err(ApiError::BadRequest(anyhow!("Only running experiment can be paused")))
+            }
+            ExperimentState::Running if experiment.state != ExperimentState::Paused => {
+                Err(ApiError::BadRequest(anyhow!("Only paused experiment can be resumed")))
+            }
+            ExperimentState::Concluded if experiment.state != ExperimentState::Running && experiment.state != ExperimentState::Paused => {
+                Err(ApiError::BadRequest(anyhow!("Only running or paused experiment can be concluded")))
+            }
+            ExperimentState::Concluded => {
+                let variations = experiment.variations.as_ref().filter(|variations| !variations.is_empty());
+
+                match (variations, self.winner.as_ref()) {
+                    (Some(_), None) => Err(ApiError::BadRequest(anyhow!("winner is required to conclude experiment with variations"))),
+                    (Some(variations), Some(winner)) if !variations.iter().any(|variation| &variation.short_name == winner) => {
+                        Err(ApiError::BadRequest(anyhow!("winner {} is not a variation of the experiment", winner)))
+                    }
+                    (None, Some(_)) => Err(ApiError::BadRequest(anyhow!("winner is not allowed for experiment without variations"))),
+                    _ => Ok(()),
+                }
+            }
+            ExperimentState::Paused | ExperimentState::Running => Ok(()),
+            ExperimentState::Draft | ExperimentState::PendingApproval => {
+                Err(ApiError::BadRequest(anyhow!("Experiment can't be moved to {:?} state", self.state)))
+            }
+        }
+    }
+
+    pub fn apply(self, experiment: &mut Experiment) {
+        if self.state == ExperimentState::Concluded {
+            experiment.winner = self.winner;
+        }
+
+        experiment.state = self.state;
+    }
+}
+
+impl Default for ExperimentState {
+    // experiments created before approval workflow are live
+    fn default() -> Self {
+        Self::Running
+    }
+}
+
 #[derive(Serialize, Deserialize, Validate)]
 pub struct Audience {
     #[validate(length(min = 1))]
@@ -143,7 +258,7 @@ pub struct Audience {
 
 impl PartialEq for Audience {
     fn eq(&self, other: &Self) -> bool {
-        (&self.name, &self.script_src, &self.list_id, &self.size) == (&other.name, &other.script_src, &self.list_id, &other.size)
+        (&self.name, &self.script_src, &self.list_id, &self.size) == (&other.name, &other.script_src, &other.list_id, &other.size)
     }
 }
 
@@ -196,6 +311,7 @@ impl AbOptimisationService {
         self.visit_project(app_id, project_id, guard, |entry| {
             experiment.id = experiment_id.to_string();
             experiment.modification_time = modification_time;
+            experiment.plan = EvaluationPlan::compile(project_id, &experiment);
 
             let experiments = &entry.value().read().experiments;
 
@@ -222,20 +338,40 @@ impl AbOptimisationService {
     pub async fn add_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, body: Body) -> HttpResult {
         let mut req_data = HttpRequest::value::<Experiment>(route, body).await?;
 
-        let guard = &epoch::pin();
+        {
+            let guard = &epoch::pin();
 
-        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
-            let project = entry.value().read();
+            let validation_visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let project = entry.value().read();
+
+                self.validate_experiment_data(&project, &req_data, None, guard)
+            };
+
+            self.visit_project(app_id, project_id, guard, validation_visitor)?;
+        }
+
+        let id = nanoid!();
 
-            self.validate_experiment_data(&project, &req_data, None, guard)?;
+        req_data.id = id.to_string();
+        req_data.version = 1; // start with version # 1
+        req_data.plan = EvaluationPlan::compile(project_id, &req_data);
+
+        // with approval workflow, experiment starts as draft and goes live only when approved
+        req_data.state = if self.approval.enabled {
+            ExperimentState::Draft
+        } else {
+            ExperimentState::Running
+        };
+        req_data.winner = None;
+        req_data.approval = None;
+        req_data.reviews = vec![];
 
-            let id = nanoid!();
+        self.experiment_store.write_experiment_data(app_id, project_id, &req_data).await?;
 
-            req_data.id = id.to_string();
-            req_data.version = 1; // start with version # 1
-            self.experiment_store.write_experiment_data(app_id, project_id, &req_data)?;
+        let guard = &epoch::pin();
 
-            project.experiments.insert(id.to_string(), RwLock::new(req_data), guard);
+        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+            entry.value().read().experiments.insert(id.to_string(), RwLock::new(req_data), guard);
 
             HttpResponse::binary_or_json(route, &AddResponse { id })
         };
@@ -246,30 +382,115 @@ impl AbOptimisationService {
     pub async fn update_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, body: Body) -> HttpResult {
         let req_data = HttpRequest::value::<Experiment>(route, body).await?;
 
-        let guard = &epoch::pin();
+        self.update_experiment_with(route, app_id, project_id, experiment_id, req_data).await
+    }
 
-        let validation_visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
-            let project = entry.value().read();
+    pub async fn patch_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, body: Body) -> HttpResult {
+        let patch = HttpRequest::patch(route, body).await?;
 
-            self.validate_experiment_data(&project, &req_data, Some(experiment_id), guard)
+        let mut req_data = {
+            let guard = &epoch::pin();
+
+            self.visit_experiment(app_id, project_id, experiment_id, guard, |entry: crossbeam_skiplist::base::Entry<String, RwLock<Experiment>>| {
+                let existing_data = entry.value().read();
+
+                patch.apply(existing_data.deref()).map_err(ApiError::BadRequest)
+            })?
         };
 
-        self.visit_project(app_id, project_id, guard, validation_visitor)?;
+        // version # is automatically calculated on update
+        req_data.version = 0;
 
-        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Experiment>>| {
-            let mut existing_data = entry.value().write();
+        self.update_experiment_with(route, app_id, project_id, experiment_id, req_data).await
+    }
+
+    async fn update_experiment_with(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, mut req_data: Experiment) -> HttpResult {
+        let user = if self.approval.enabled { Some(request_user(route)?) } else { None };
+
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            let validation_visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let project = entry.value().read();
 
-            AbOptimisationService::update_experiment_data(req_data, &mut existing_data);
+                self.validate_experiment_data(&project, &req_data, Some(experiment_id), guard)
+            };
 
-            self.experiment_store.write_experiment_data(app_id, project_id, &existing_data)?;
+            self.visit_project(app_id, project_id, guard, validation_visitor)?;
 
-            HttpResponse::str(route, "SUCCESS")
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Experiment>>| {
+                let mut existing_data = entry.value().write();
+
+                // edits to an experiment which is or can become live again are kept as pending revision, till approved
+                let live = matches!(
+                    existing_data.state,
+                    ExperimentState::Running | ExperimentState::Paused | ExperimentState::Concluded
+                );
+
+                if let (Some(user), true) = (user.as_ref(), live) {
+                    info!(
+                        "Adding pending revision by user:{} for app:{}, project:{}, experiment:{}",
+                        user, app_id, project_id, experiment_id
+                    );
+
+                    req_data.state = existing_data.state;
+                    req_data.approval = None;
+                    req_data.reviews = vec![];
+
+                    // a pending state change would be applied on the revision's approval otherwise
+                    if existing_data.approval.as_ref().map_or(false, |approval| approval.transition.is_some()) {
+                        return Err(ApiError::Conflict(format!(
+                            "Experiment {} already has a change pending for approval",
+                            experiment_id
+                        )));
+                    }
+
+                    existing_data.approval = Some(Box::new(ApprovalRequest {
+                        requested_by: user.to_string(),
+                        requested_at: chrono::Local::now(),
+                        revision: Some(Box::new(req_data)),
+                        transition: None,
+                    }));
+
+                    batch.write_experiment_data(app_id, project_id, &existing_data)?;
+
+                    return HttpResponse::str(route, "PENDING_APPROVAL");
+                }
+
+                AbOptimisationService::carry_over_managed_fields(&mut req_data, &mut existing_data);
+                AbOptimisationService::update_experiment_data(req_data, &mut existing_data);
+
+                // edit of an experiment under approval needs to be submitted again
+                if existing_data.state == ExperimentState::PendingApproval {
+                    existing_data.state = ExperimentState::Draft;
+                    existing_data.approval = None;
+                }
+
+                batch.write_experiment_data(app_id, project_id, &existing_data)?;
+
+                HttpResponse::str(route, "SUCCESS")
+            };
+
+            self.visit_experiment(app_id, project_id, experiment_id, guard, visitor)?
         };
 
-        self.visit_experiment(app_id, project_id, experiment_id, guard, visitor)
+        batch.commit().await?;
+
+        Ok(response)
+    }
+
+    /// Fields managed by their dedicated apis (archive, approval workflow) are not changed by an update.
+    pub(crate) fn carry_over_managed_fields(req_data: &mut Experiment, existing_data: &mut Experiment) {
+        req_data.archived = existing_data.archived;
+        req_data.state = existing_data.state;
+        req_data.winner = existing_data.winner.take();
+        req_data.approval = existing_data.approval.take();
+        req_data.reviews = std::mem::take(&mut existing_data.reviews);
     }
 
-    fn update_experiment_data(req_data: Experiment, existing_data: &mut RwLockWriteGuard<RawRwLock, Experiment>) {
+    pub(crate) fn update_experiment_data(req_data: Experiment, existing_data: &mut Experiment) {
         let mut changed = false;
         if existing_data.name != req_data.name {
             existing_data.name = req_data.name;
@@ -303,6 +524,7 @@ impl AbOptimisationService {
 
         if existing_data.audiences != req_data.audiences {
             existing_data.audiences = req_data.audiences;
+            existing_data.plan = existing_data.plan.recompile(existing_data);
             changed = true;
         }
 
@@ -321,6 +543,27 @@ impl AbOptimisationService {
             changed = true;
         }
 
+        if existing_data.layer != req_data.layer {
+            existing_data.layer = req_data.layer;
+            changed = true;
+        }
+
+        // archiving and approval workflow don't change the experiment config, hence no version change
+        if existing_data.archived != req_data.archived {
+            existing_data.archived = req_data.archived;
+        }
+
+        if existing_data.state != req_data.state {
+            existing_data.state = req_data.state;
+        }
+
+        if existing_data.winner != req_data.winner {
+            existing_data.winner = req_data.winner;
+        }
+
+        existing_data.approval = req_data.approval;
+        existing_data.reviews = req_data.reviews;
+
         // version change can be because of
         //      - change in audience spec or size
         //      - change in variations or size
@@ -333,13 +576,27 @@ impl AbOptimisationService {
         if changed {
             // increment the version #
             existing_data.version += 1;
+
+            // changed scripts or lists may have fixed the failures
+            existing_data.circuit_breaker.reset();
         }
 
         existing_data.modification_time = req_data.modification_time;
     }
 
     fn validate_experiment_data(&self, project: &Project, data_to_validate: &Experiment, update_id: Option<&str>, guard: &Guard) -> ApiResult<()> {
-        data_to_validate.validate().with_context(|| format!("Error in validating experiment data"))?;
+        if data_to_validate.version > 0 {
+            return Err(ApiError::BadRequest(anyhow!("Version # is automatically calculated and is not allowed")));
+        }
+
+        Self::validate_experiment_in_project(project, data_to_validate, update_id, guard)
+    }
+
+    /// Validates the experiment, its references in the project, and its name, short_name and layer allocation against the
+    /// other experiments of the project.
+    pub(crate) fn validate_experiment_in_project(project: &Project, data_to_validate: &Experiment, update_id: Option<&str>, guard: &Guard) -> ApiResult<()> {
+        data_to_validate.validate().map_err(ApiError::Invalid)?;
+        validate_references(project, data_to_validate, guard).map_err(ApiError::Invalid)?;
 
         if let Some(variations) = data_to_validate.variations.as_ref() {
             if variations.len() > 0 && data_to_validate.kind == ExperimentKind::Feature {
@@ -347,20 +604,21 @@ impl AbOptimisationService {
             }
         }
 
-        if data_to_validate.version > 0 {
-            return Err(ApiError::BadRequest(anyhow!("Version # is automatically calculated and is not allowed")));
+        if let Some(allocation) = data_to_validate.layer.as_ref() {
+            if project.layer(&allocation.layer).is_none() {
+                return Err(ApiError::BadRequest(anyhow!("Layer not found for short_name={}", allocation.layer)));
+            }
         }
 
         for entry in project.experiments.iter(guard) {
+            // checked by key, as the experiment being updated may be locked by the caller
+            if update_id == Some(entry.key().as_str()) {
+                continue;
+            }
+
             let value = entry.value();
             let experiment = value.read();
 
-            if let Some(update_id) = update_id {
-                if experiment.id.eq(update_id) {
-                    continue;
-                }
-            }
-
             if experiment.short_name.eq(&data_to_validate.short_name) {
                 return Err(ApiError::BadRequest(anyhow!(
                     "Experiment with same short_name={} already exists",
@@ -371,11 +629,234 @@ impl AbOptimisationService {
             if experiment.name.eq(&data_to_validate.name) {
                 return Err(ApiError::BadRequest(anyhow!("Experiment with same name={} already exists", experiment.name)));
             }
+
+            // experiments within a layer partition its traffic, hence can't over allocate it.
+            // archived experiment keeps its allocation, so that it can be unarchived safely
+            if let (Some(allocation), Some(other_allocation)) = (data_to_validate.layer.as_ref(), experiment.layer.as_ref()) {
+                if allocation.overlaps(other_allocation) {
+                    return Err(ApiError::BadRequest(anyhow!(
+                        "Layer {} allocation overlaps with experiment {}",
+                        allocation.layer,
+                        experiment.short_name
+                    )));
+                }
+            }
         }
 
         Ok(())
     }
 
+    pub async fn archive_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, archived: bool) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Experiment>>| {
+                let mut existing_data = entry.value().write();
+
+                if existing_data.archived != archived {
+                    info!(
+                        "Setting archived={} for app:{}, project:{}, experiment:{}",
+                        archived, app_id, project_id, experiment_id
+                    );
+
+                    existing_data.archived = archived;
+                    batch.write_experiment_data(app_id, project_id, &existing_data)?;
+                }
+
+                HttpResponse::str(route, "SUCCESS")
+            };
+
+            self.visit_experiment(app_id, project_id, experiment_id, guard, visitor)?
+        };
+
+        batch.commit().await?;
+
+        Ok(response)
+    }
+
+    pub async fn pause_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str) -> HttpResult {
+        let transition = StateTransition {
+            state: ExperimentState::Paused,
+            winner: None,
+        };
+
+        self.change_experiment_state(route, app_id, project_id, experiment_id, transition).await
+    }
+
+    pub async fn resume_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str) -> HttpResult {
+        let transition = StateTransition {
+            state: ExperimentState::Running,
+            winner: None,
+        };
+
+        self.change_experiment_state(route, app_id, project_id, experiment_id, transition).await
+    }
+
+    pub async fn conclude_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str, body: Body) -> HttpResult {
+        let body = HttpRequest::bytes(route, body).await?;
+
+        let req_data: ConcludeRequest = if body.has_remaining() {
+            serde_json::from_reader(body.reader())
+                .with_context(|| "Error in decoding conclude request")
+                .map_err(ApiError::BadRequest)?
+        } else {
+            ConcludeRequest::default()
+        };
+
+        let transition = StateTransition {
+            state: ExperimentState::Concluded,
+            winner: req_data.winner,
+        };
+
+        self.change_experiment_state(route, app_id, project_id, experiment_id, transition).await
+    }
+
+    /// Changes the state of the experiment. With approval workflow, resume and conclude are kept pending till approved,
+    /// while pause is applied right away, as it only stops new assignments.
+    async fn change_experiment_state(
+        &self,
+        route: &HttpRoute<'_>,
+        app_id: &str,
+        project_id: &str,
+        experiment_id: &str,
+        transition: StateTransition,
+    ) -> HttpResult {
+        let user = if self.approval.enabled { Some(request_user(route)?) } else { None };
+
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Experiment>>| {
+                let mut existing_data = entry.value().write();
+
+                transition.validate(&existing_data)?;
+
+                let needs_approval = transition.state != ExperimentState::Paused;
+
+                if let (Some(user), true) = (user.as_ref(), needs_approval) {
+                    if existing_data.approval.is_some() {
+                        return Err(ApiError::Conflict(format!(
+                            "Experiment {} already has a change pending for approval",
+                            experiment_id
+                        )));
+                    }
+
+                    info!(
+                        "Adding pending state change to {:?} by user:{} for app:{}, project:{}, experiment:{}",
+                        transition.state, user, app_id, project_id, experiment_id
+                    );
+
+                    existing_data.approval = Some(Box::new(ApprovalRequest {
+                        requested_by: user.to_string(),
+                        requested_at: chrono::Local::now(),
+                        revision: None,
+                        transition: Some(transition),
+                    }));
+
+                    batch.write_experiment_data(app_id, project_id, &existing_data)?;
+
+                    return HttpResponse::str(route, "PENDING_APPROVAL");
+                }
+
+                transition.apply(&mut existing_data);
+
+                info!(
+                    "Changed state to {:?} by user:{} for app:{}, project:{}, experiment:{}",
+                    existing_data.state,
+                    user.as_deref().unwrap_or("-"),
+                    app_id,
+                    project_id,
+                    experiment_id
+                );
+
+                batch.write_experiment_data(app_id, project_id, &existing_data)?;
+
+                HttpResponse::str(route, "SUCCESS")
+            };
+
+            self.visit_experiment(app_id, project_id, experiment_id, guard, visitor)?
+        };
+
+        batch.commit().await?;
+
+        Ok(response)
+    }
+
+    pub async fn delete_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let project = entry.value().read();
+
+                if project.experiments.get(experiment_id, guard).is_none() {
+                    return Err(ApiError::NotFound(format!(
+                        "Experiment not found for id: {}, project id: {} and app id: {}",
+                        experiment_id, project_id, app_id
+                    )));
+                }
+
+                batch.delete_experiment_data(app_id, project_id, experiment_id);
+
+                Ok(())
+            };
+
+            self.visit_project(app_id, project_id, guard, visitor)?;
+        }
+
+        batch.commit().await?;
+
+        // project may have been deleted meanwhile, along with the experiment
+        let guard = &epoch::pin();
+        let _ = self.visit_project(app_id, project_id, guard, |entry| {
+            if let Some(experiment_entry) = entry.value().read().experiments.remove(experiment_id, guard) {
+                experiment_entry.release(guard);
+            }
+
+            Ok(())
+        });
+
+        info!("Deleted experiment for app:{}, project:{}, id:{}", app_id, project_id, experiment_id);
+
+        HttpResponse::str(route, "SUCCESS")
+    }
+
+    pub(crate) fn prune_experiments(&self, loaded_ids: &HashSet<String>) {
+        let guard = &epoch::pin();
+
+        for app_entry in self.apps.iter(guard) {
+            let app = app_entry.value().read();
+
+            for project_entry in app.projects.iter(guard) {
+                let project = project_entry.value().read();
+
+                let deleted_ids: Vec<String> = project
+                    .experiments
+                    .iter(guard)
+                    .map(|entry| entry.key().to_string())
+                    .filter(|experiment_id| !loaded_ids.contains(&format!("{}.{}.{}", app.id, project.id, experiment_id)))
+                    .collect();
+
+                for experiment_id in deleted_ids {
+                    info!(
+                        "Removing experiment for app:{}, project:{}, id:{}, as it doesn't exist in store anymore",
+                        app.id, project.id, experiment_id
+                    );
+
+                    if let Some(entry) = project.experiments.remove(&experiment_id, guard) {
+                        entry.release(guard);
+                    }
+                }
+            }
+        }
+    }
+
     pub async fn get_experiment(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, experiment_id: &str) -> HttpResult {
         let guard = &epoch::pin();
 
@@ -452,5 +933,185 @@ fn validate_audiences(audiences: &Vec<Audience>) -> Result<(), ValidationError>
         return Err(ValidationError::new("Duplicate audience name found"));
     }
 
+    // audience without script and list targets everyone, hence any audience after it is unreachable
+    let catch_all = audiences
+        .iter()
+        .position(|audience| audience.script_src.is_none() && audience.list_id.is_none());
+    if let Some(position) = catch_all {
+        if position + 1 < audiences.len() {
+            return Err(ValidationError::new(
+                "Audience without script_src and list_id matches everyone, hence is allowed only as the last audience",
+            ));
+        }
+    }
+
     Ok(())
 }
+
+/// Validates the references of the experiment in its project, i.e. the audience lists of audiences, and the context fields
+/// of scripts, if the project has a context schema.
+fn validate_references(project: &Project, experiment: &Experiment, guard: &Guard) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+
+    for audience in experiment.audiences.iter() {
+        if let Some(list_id) = audience.list_id.as_ref() {
+            let list_error = match project.audience_lists.get(list_id, guard) {
+                None => Some(("unknown_audience_list", format!("Audience list not found for id: {}", list_id))),
+                Some(entry) if entry.value().read().archived => Some(("archived_audience_list", format!("Audience list {} is archived", list_id))),
+                Some(_) => None,
+            };
+
+            if let Some((code, message)) = list_error {
+                let mut error = ValidationError::new(code);
+                error.message = Some(Cow::from(message));
+                error.add_param(Cow::from("audience"), &audience.name);
+                error.add_param(Cow::from("list_id"), list_id);
+                errors.add("audiences", error);
+            }
+        }
+    }
+
+    if let Some(schema) = project.context_schema.as_ref() {
+        for script in experiment.audiences.iter().filter_map(|audience| audience.script_src.as_ref()) {
+            for error in schema.lint(script) {
+                errors.add("audiences", error);
+            }
+        }
+
+        if let Some(script) = experiment.frequency_constraint.as_ref() {
+            for error in schema.lint(script) {
+                errors.add("frequency_constraint", error);
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Body;
+    use serde_json::json;
+
+    use crate::core::experiment::{Experiment, ExperimentState, StateTransition};
+    use crate::core::fixtures::{request, route, service, visit_experiment};
+    use crate::server::ApiError;
+
+    fn experiment(state: ExperimentState, variations: bool) -> anyhow::Result<Experiment> {
+        let variations = if variations {
+            json!([
+                {"name": "control", "short_name": "c", "size": 50},
+                {"name": "test", "short_name": "t", "size": 50}
+            ])
+        } else {
+            json!(null)
+        };
+
+        let mut experiment: Experiment = serde_json::from_value(json!({
+            "name": "experiment",
+            "short_name": "e",
+            "audiences": [{"name": "all", "size_kind": "Percent", "value": 100}],
+            "variations": variations,
+        }))?;
+        experiment.state = state;
+
+        Ok(experiment)
+    }
+
+    fn transition(state: ExperimentState, winner: Option<&str>) -> StateTransition {
+        StateTransition {
+            state,
+            winner: winner.map(|winner| winner.to_string()),
+        }
+    }
+
+    fn allowed(from: ExperimentState, to: ExperimentState) -> anyhow::Result<bool> {
+        Ok(transition(to, None).validate(&experiment(from, false)?).is_ok())
+    }
+
+    #[test]
+    fn allowed_transitions() -> anyhow::Result<()> {
+        use ExperimentState::*;
+
+        for &from in [Draft, PendingApproval, Running, Paused, Concluded].iter() {
+            assert_eq!(allowed(from, Paused)?, from == Running, "pause from {:?}", from);
+            assert_eq!(allowed(from, Running)?, from == Paused, "resume from {:?}", from);
+            assert_eq!(allowed(from, Concluded)?, from == Running || from == Paused, "conclude from {:?}", from);
+            assert!(!allowed(from, Draft)?);
+            assert!(!allowed(from, PendingApproval)?);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn conclude_winner() -> anyhow::Result<()> {
+        let with_variations = experiment(ExperimentState::Running, true)?;
+        assert!(transition(ExperimentState::Concluded, Some("t")).validate(&with_variations).is_ok());
+        assert!(transition(ExperimentState::Concluded, Some("x")).validate(&with_variations).is_err());
+        assert!(transition(ExperimentState::Concluded, None).validate(&with_variations).is_err());
+
+        let without_variations = experiment(ExperimentState::Running, false)?;
+        assert!(transition(ExperimentState::Concluded, None).validate(&without_variations).is_ok());
+        assert!(transition(ExperimentState::Concluded, Some("t")).validate(&without_variations).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn apply() -> anyhow::Result<()> {
+        let mut experiment = experiment(ExperimentState::Running, true)?;
+
+        transition(ExperimentState::Paused, None).apply(&mut experiment);
+        assert_eq!(experiment.state, ExperimentState::Paused);
+        assert_eq!(experiment.winner, None);
+
+        transition(ExperimentState::Concluded, Some("t")).apply(&mut experiment);
+        assert_eq!(experiment.state, ExperimentState::Concluded);
+        assert_eq!(experiment.winner.as_deref(), Some("t"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn patch_audience_list() -> anyhow::Result<()> {
+        let service = service().await?;
+
+        let req = request("alice")?;
+        let patch = json!([{"op": "replace", "path": "/audiences/0/list_id", "value": "l2"}]);
+        service.patch_experiment(&route(&req), "a1", "p1", "e1", Body::from(patch.to_string())).await?;
+
+        assert_eq!(
+            visit_experiment(&service, |experiment| (experiment.version, experiment.audiences[0].list_id.clone()))?,
+            (2, Some("l2".to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn archive_and_delete() -> anyhow::Result<()> {
+        let service = service().await?;
+        let req = request("alice")?;
+
+        // archiving doesn't change the experiment config
+        service.archive_experiment(&route(&req), "a1", "p1", "e1", true).await?;
+        assert_eq!(visit_experiment(&service, |experiment| (experiment.archived, experiment.version))?, (true, 1));
+
+        service.delete_experiment(&route(&req), "a1", "p1", "e1").await?;
+        assert!(visit_experiment(&service, |_| ()).is_err());
+
+        // deleted experiment stays deleted on reload
+        service.load_data().await?;
+        assert!(visit_experiment(&service, |_| ()).is_err());
+
+        let result = service.delete_experiment(&route(&req), "a1", "p1", "e1").await;
+        assert!(matches!(result, Err(ApiError::NotFound(_))));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! Fixtures shared by the tests of the service apis.
+
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crossbeam_epoch as epoch;
+use hyper::{Body, Request, Response};
+use serde_json::{json, Value as JsonValue};
+
+use crate::core::{App, AudienceList, Experiment, ExperimentState, Project};
+use crate::experiment_store::{ExperimentStore, MemoryStore};
+use crate::server::HttpRoute;
+use crate::service::AbOptimisationService;
+use crate::settings;
+
+/// Experiment `e1` of the fixture, targeting all members of audience list `l1`.
+pub fn experiment_json() -> JsonValue {
+    json!({
+        "name": "experiment",
+        "short_name": "e",
+        "audiences": [{"name": "beta", "list_id": "l1", "size_kind": "Percent", "value": 100}],
+    })
+}
+
+/// Writes app `a1` with project `p1`, its audience lists `l1` and `l2`, and experiment `e1` in the given state.
+pub async fn write_app(experiment_store: &ExperimentStore, state: ExperimentState) -> anyhow::Result<()> {
+    let mut app: App = serde_json::from_value(json!({"name": "app", "short_name": "a"}))?;
+    app.id = "a1".to_string();
+    experiment_store.write_app_data(&app).await?;
+
+    let mut project: Project = serde_json::from_value(json!({"name": "project", "short_name": "p"}))?;
+    project.id = "p1".to_string();
+    experiment_store.write_project_data("a1", &project).await?;
+
+    for (list_id, members) in [("l1", json!(["u1", "u2"])), ("l2", json!(["u3"]))].iter() {
+        let mut audience_list: AudienceList = serde_json::from_value(json!({"name": list_id, "list": members}))?;
+        audience_list.id = list_id.to_string();
+        experiment_store.write_audience_list_data("a1", "p1", &audience_list).await?;
+    }
+
+    let mut experiment: Experiment = serde_json::from_value(experiment_json())?;
+    experiment.id = "e1".to_string();
+    experiment.version = 1;
+    experiment.state = state;
+    experiment_store.write_experiment_data("a1", "p1", &experiment).await?;
+
+    Ok(())
+}
+
+/// Empty directory of a local store for the test, with the directories of all the object kinds.
+pub fn local_store_path(test: &str) -> anyhow::Result<PathBuf> {
+    let path = std::env::temp_dir().join(format!("local-store-{}-{}", test, std::process::id()));
+    if path.exists() {
+        std::fs::remove_dir_all(&path)?;
+    }
+
+    for dir in ["apps", "projects", "experiments", "audience_lists"].iter() {
+        std::fs::create_dir_all(path.join(dir))?;
+    }
+
+    Ok(path)
+}
+
+/// Config of a local store over the directory, same for all the replicas sharing it.
+pub fn local_store_config(path: &Path) -> settings::LocalStoreConfig {
+    settings::LocalStoreConfig {
+        path: path.to_string_lossy().to_string(),
+        refresh_rate: 10,
+        watch: false,
+    }
+}
+
+/// Service over a memory store, having no apps.
+pub fn empty_service() -> AbOptimisationService {
+    AbOptimisationService::new(ExperimentStore::new(Box::new(MemoryStore::new("data"))))
+}
+
+/// Service over a memory store, loaded with the fixture app having a running experiment.
+pub async fn service() -> anyhow::Result<AbOptimisationService> {
+    let service = empty_service();
+
+    write_app(&service.experiment_store, ExperimentState::Running).await?;
+    service.load_data().await?;
+
+    Ok(service)
+}
+
+/// Request on behalf of the user.
+pub fn request(user: &str) -> anyhow::Result<Request<Body>> {
+    Ok(Request::builder().header(crate::core::approval::USER_HEADER, user).body(Body::empty())?)
+}
+
+pub fn route(req: &Request<Body>) -> HttpRoute<'_> {
+    HttpRoute::new(req, chrono::Local::now(), Instant::now(), ([127, 0, 0, 1], 8080).into())
+}
+
+/// Body of the uncompressed JSON response.
+pub async fn response_json(response: Response<Body>) -> anyhow::Result<JsonValue> {
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Visits experiment `e1` of the fixture app, as loaded in the service.
+pub fn visit_experiment<F, R>(service: &AbOptimisationService, visitor: F) -> anyhow::Result<R>
+where
+    F: FnOnce(&Experiment) -> R,
+{
+    let guard = &epoch::pin();
+    let result = service.visit_experiment("a1", "p1", "e1", guard, |entry| Ok(visitor(&entry.value().read())))?;
+
+    Ok(result)
+}
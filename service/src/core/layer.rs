@@ -0,0 +1,138 @@
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+/// Independent hashing layer of a project.
+///
+/// Each layer has its own traffic space, which experiments in the layer partition between them. Experiments in
+/// different layers overlap orthogonally, as users are bucketed independently in each layer.
+#[derive(Serialize, Deserialize, Validate, PartialEq, Clone, Debug)]
+pub struct Layer {
+    #[validate(length(min = 1))]
+    pub name: String,
+
+    #[validate(length(min = 1, max = 5))]
+    pub short_name: String,
+
+    /// Salt for bucketing users in the layer, defaults to `{project id}/{layer short_name}`.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+}
+
+/// Slice of a layer's traffic space taken by an experiment, in percent.
+#[derive(Serialize, Deserialize, Validate, PartialEq, Clone, Debug)]
+#[validate(schema(function = "validate_layer_allocation"))]
+pub struct LayerAllocation {
+    /// short_name of the layer
+    #[validate(length(min = 1))]
+    pub layer: String,
+
+    #[serde(default)]
+    #[validate(range(min = 0, max = 99))]
+    pub offset: i64,
+
+    #[validate(range(min = 1, max = 100))]
+    pub size: i64,
+}
+
+impl LayerAllocation {
+    pub fn end(&self) -> i64 {
+        self.offset + self.size
+    }
+
+    pub fn overlaps(&self, other: &LayerAllocation) -> bool {
+        self.layer == other.layer && self.offset < other.end() && other.offset < self.end()
+    }
+
+    /// Checks if user bucket in [0, 10000) of the layer falls in this allocation.
+    pub fn contains(&self, user_bucket: u64) -> bool {
+        let user_bucket = user_bucket as i64;
+
+        user_bucket >= self.offset * 100 && user_bucket < self.end() * 100
+    }
+}
+
+fn validate_layer_allocation(allocation: &LayerAllocation) -> Result<(), ValidationError> {
+    if allocation.end() > 100 {
+        return Err(ValidationError::new("Layer allocation can't go beyond 100 percent of the layer"));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn validate_layers(layers: &Vec<Layer>) -> Result<(), ValidationError> {
+    if !layers.iter().map(|layer| &layer.name).all_unique() {
+        return Err(ValidationError::new("Duplicate layer name found"));
+    }
+
+    if !layers.iter().map(|layer| &layer.short_name).all_unique() {
+        return Err(ValidationError::new("Duplicate layer short_name found"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::Validate;
+
+    use crate::core::layer::{validate_layers, Layer, LayerAllocation};
+
+    fn allocation(layer: &str, offset: i64, size: i64) -> LayerAllocation {
+        LayerAllocation {
+            layer: layer.to_string(),
+            offset,
+            size,
+        }
+    }
+
+    fn layer(name: &str, short_name: &str) -> Layer {
+        Layer {
+            name: name.to_string(),
+            short_name: short_name.to_string(),
+            salt: None,
+        }
+    }
+
+    #[test]
+    fn overlaps() {
+        let allocation1 = allocation("l1", 0, 30);
+
+        assert!(allocation1.overlaps(&allocation("l1", 20, 30)));
+        assert!(allocation1.overlaps(&allocation("l1", 0, 100)));
+        assert!(!allocation1.overlaps(&allocation("l1", 30, 70)));
+        assert!(!allocation1.overlaps(&allocation("l2", 0, 30)));
+    }
+
+    #[test]
+    fn contains() {
+        let allocation1 = allocation("l1", 0, 30);
+        let allocation2 = allocation("l1", 30, 70);
+
+        // adjacent allocations partition the buckets of the layer
+        for user_bucket in 0..10000 {
+            assert_ne!(allocation1.contains(user_bucket), allocation2.contains(user_bucket));
+        }
+
+        assert!(allocation1.contains(2999));
+        assert!(allocation2.contains(3000));
+        assert!(allocation2.contains(9999));
+    }
+
+    #[test]
+    fn validate_allocation() {
+        assert!(allocation("l1", 0, 100).validate().is_ok());
+        assert!(allocation("l1", 50, 50).validate().is_ok());
+        assert!(allocation("l1", 50, 60).validate().is_err());
+        assert!(allocation("l1", 0, 0).validate().is_err());
+        assert!(allocation("l1", 100, 1).validate().is_err());
+    }
+
+    #[test]
+    fn unique_layers() {
+        assert!(validate_layers(&vec![layer("layer 1", "l1"), layer("layer 2", "l2")]).is_ok());
+        assert!(validate_layers(&vec![layer("layer 1", "l1"), layer("layer 1", "l2")]).is_err());
+        assert!(validate_layers(&vec![layer("layer 1", "l1"), layer("layer 2", "l1")]).is_err());
+    }
+}
@@ -0,0 +1,582 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, bail, Context};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use roaring::RoaringTreemap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// False positive rate of bloom encoded lists, when not given.
+const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.001;
+
+/// Minimum number of ids a bloom filter is sized for, so that small lists don't saturate on a few adds.
+const MIN_BLOOM_CAPACITY: u64 = 1024;
+
+/// How the members of an audience list are kept, in memory and in store.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ListEncoding {
+    /// plain set of ids, stored as JSON array in `list` field
+    Set,
+
+    /// sorted ids, stored front coded and gzipped
+    Sorted,
+
+    /// bloom filter over the ids, answering membership with a false positive rate, and not supporting removal
+    Bloom,
+
+    /// roaring bitmap of numeric ids
+    Bitmap,
+}
+
+impl Default for ListEncoding {
+    fn default() -> Self {
+        ListEncoding::Set
+    }
+}
+
+/// Format of members given one per line, as in uploads and segment exports.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MemberFormat {
+    /// id is the first column, and optional time the second one
+    Csv,
+
+    /// each line is either the id as JSON string or number, or an object with `user_id` and optional `time` fields
+    Ndjson,
+}
+
+impl Default for MemberFormat {
+    fn default() -> Self {
+        MemberFormat::Csv
+    }
+}
+
+impl MemberFormat {
+    pub fn parse(value: &str) -> anyhow::Result<MemberFormat> {
+        match value {
+            "csv" => Ok(MemberFormat::Csv),
+            "ndjson" => Ok(MemberFormat::Ndjson),
+            _ => bail!("Unknown member format: {}", value),
+        }
+    }
+
+    /// Id of the member in the line, along with its time in millis if given.
+    pub fn parse_line(&self, line: &str) -> anyhow::Result<(String, Option<i64>)> {
+        match self {
+            MemberFormat::Csv => {
+                let mut columns = line.split(',').map(|column| column.trim().trim_matches('"'));
+
+                let id = columns.next().unwrap_or_default().to_string();
+                let time = columns.next().filter(|time| !time.is_empty()).map(parse_time).transpose()?;
+
+                Ok((id, time))
+            }
+            MemberFormat::Ndjson => match serde_json::from_str(line).with_context(|| format!("Error in parsing line: {}", line))? {
+                Value::String(id) => Ok((id, None)),
+                Value::Number(id) => Ok((id.to_string(), None)),
+                Value::Object(mut object) => {
+                    let id = match object.remove("user_id") {
+                        Some(Value::String(id)) => id,
+                        Some(Value::Number(id)) => id.to_string(),
+                        _ => bail!("No user_id in line: {}", line),
+                    };
+
+                    let time = match object.remove("time") {
+                        Some(Value::String(time)) => Some(parse_time(&time)?),
+                        Some(Value::Number(time)) => Some(time.as_i64().ok_or_else(|| anyhow!("Invalid time in line: {}", line))?),
+                        _ => None,
+                    };
+
+                    Ok((id, time))
+                }
+                _ => bail!("No id in line: {}", line),
+            },
+        }
+    }
+}
+
+/// Time in millis, given either as millis or as RFC 3339 date time.
+fn parse_time(value: &str) -> anyhow::Result<i64> {
+    match value.parse::<i64>() {
+        Ok(millis) => Ok(millis),
+        Err(_) => Ok(chrono::DateTime::parse_from_rfc3339(value)
+            .with_context(|| format!("Invalid time: {}", value))?
+            .timestamp_millis()),
+    }
+}
+
+/// Members of an audience list.
+pub enum Members {
+    Set(HashSet<String>),
+    Sorted(Vec<String>),
+    Bloom(BloomFilter),
+    Bitmap(RoaringTreemap),
+}
+
+impl Default for Members {
+    fn default() -> Self {
+        Members::Set(HashSet::new())
+    }
+}
+
+impl Members {
+    /// Members with the given encoding, built from the ids.
+    pub fn build(encoding: ListEncoding, ids: Vec<String>, false_positive_rate: Option<f64>) -> anyhow::Result<Members> {
+        let mut builder = MembersBuilder::new(encoding, ids.len() as u64, false_positive_rate)?;
+        for id in ids {
+            builder.add(id)?;
+        }
+
+        Ok(builder.build())
+    }
+
+    /// Builder of members with the same encoding, and for bloom encoding with the same false positive rate, sized for
+    /// the capacity if given, else with the same size, so that the built members can be merged into these.
+    pub fn builder_like(&self, capacity: Option<u64>) -> anyhow::Result<MembersBuilder> {
+        match (self, capacity) {
+            (Members::Bloom(filter), None) => Ok(MembersBuilder {
+                members: Members::Bloom(filter.empty_like()),
+            }),
+            (_, capacity) => MembersBuilder::new(self.encoding(), capacity.unwrap_or(0), self.false_positive_rate()),
+        }
+    }
+
+    pub fn encoding(&self) -> ListEncoding {
+        match self {
+            Members::Set(_) => ListEncoding::Set,
+            Members::Sorted(_) => ListEncoding::Sorted,
+            Members::Bloom(_) => ListEncoding::Bloom,
+            Members::Bitmap(_) => ListEncoding::Bitmap,
+        }
+    }
+
+    pub fn false_positive_rate(&self) -> Option<f64> {
+        match self {
+            Members::Bloom(filter) => Some(filter.false_positive_rate),
+            _ => None,
+        }
+    }
+
+    /// Whether id is a member, for bloom encoding may be true for a non member too.
+    pub fn contains(&self, id: &str) -> bool {
+        match self {
+            Members::Set(ids) => ids.contains(id),
+            Members::Sorted(ids) => ids.binary_search_by(|member| member.as_str().cmp(id)).is_ok(),
+            Members::Bloom(filter) => filter.contains(id),
+            Members::Bitmap(bitmap) => id.parse::<u64>().map_or(false, |id| bitmap.contains(id)),
+        }
+    }
+
+    /// Number of members, for bloom encoding it is the number of ids added, including the duplicate ones.
+    pub fn len(&self) -> u64 {
+        match self {
+            Members::Set(ids) => ids.len() as u64,
+            Members::Sorted(ids) => ids.len() as u64,
+            Members::Bloom(filter) => filter.count,
+            Members::Bitmap(bitmap) => bitmap.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn add<I>(&mut self, ids: I) -> anyhow::Result<()>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        match self {
+            Members::Set(members) => members.extend(ids),
+            Members::Sorted(members) => {
+                members.extend(ids);
+                members.sort_unstable();
+                members.dedup();
+            }
+            Members::Bloom(filter) => {
+                // capacity is checked upfront, so that the filter isn't left with part of the ids
+                let ids: Vec<String> = ids.into_iter().collect();
+                filter.ensure_capacity(ids.len() as u64)?;
+
+                for id in ids {
+                    filter.insert(&id)?;
+                }
+            }
+            Members::Bitmap(bitmap) => {
+                for id in ids {
+                    bitmap.insert(parse_numeric_id(&id)?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the other members, which must have the same encoding, as built by `builder_like`.
+    pub fn merge(&mut self, other: Members) -> anyhow::Result<()> {
+        match (self, other) {
+            (Members::Set(members), Members::Set(other)) => members.extend(other),
+            (Members::Sorted(members), Members::Sorted(other)) => {
+                members.extend(other);
+                members.sort_unstable();
+                members.dedup();
+            }
+            (Members::Bloom(filter), Members::Bloom(other)) => filter.merge(&other)?,
+            (Members::Bitmap(bitmap), Members::Bitmap(other)) => *bitmap |= other,
+            (members, other) => bail!("Can't merge {:?} encoded members into {:?} encoded ones", other.encoding(), members.encoding()),
+        }
+
+        Ok(())
+    }
+
+    pub fn remove<I>(&mut self, ids: I) -> anyhow::Result<()>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        match self {
+            Members::Set(members) => ids.into_iter().for_each(|id| {
+                members.remove(&id);
+            }),
+            Members::Sorted(members) => {
+                let ids: HashSet<String> = ids.into_iter().collect();
+                members.retain(|member| !ids.contains(member));
+            }
+            Members::Bloom(_) => bail!("Members can't be removed from bloom encoded list, upload the list again instead"),
+            Members::Bitmap(bitmap) => {
+                for id in ids {
+                    bitmap.remove(parse_numeric_id(&id)?);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn from_data(data: MembersData) -> anyhow::Result<Members> {
+        let members = match (data.encoding, data.data) {
+            (ListEncoding::Set, _) => Members::Set(data.list.unwrap_or_default().into_iter().collect()),
+            // ids given as is, e.g. on add or update via api, are encoded here
+            (encoding, None) => Members::build(encoding, data.list.unwrap_or_default(), data.false_positive_rate)?,
+            (ListEncoding::Sorted, Some(encoded)) => Members::Sorted(decode_sorted(&base64::decode(encoded)?)?),
+            (ListEncoding::Bloom, Some(encoded)) => {
+                let filter: BloomFilter = bincode::deserialize(&base64::decode(encoded)?)?;
+                if filter.bits.is_empty() {
+                    bail!("Corrupt bloom filter data");
+                }
+
+                Members::Bloom(filter)
+            }
+            (ListEncoding::Bitmap, Some(encoded)) => Members::Bitmap(RoaringTreemap::deserialize_from(&base64::decode(encoded)?[..])?),
+        };
+
+        Ok(members)
+    }
+
+    fn to_data(&self) -> anyhow::Result<MembersData> {
+        let mut data = MembersData {
+            encoding: self.encoding(),
+            list: None,
+            false_positive_rate: self.false_positive_rate(),
+            data: None,
+        };
+
+        match self {
+            Members::Set(ids) => data.list = Some(ids.iter().cloned().collect()),
+            Members::Sorted(ids) => data.data = Some(base64::encode(encode_sorted(ids)?)),
+            Members::Bloom(filter) => data.data = Some(base64::encode(bincode::serialize(filter)?)),
+            Members::Bitmap(bitmap) => {
+                let mut buffer = Vec::with_capacity(bitmap.serialized_size());
+                bitmap.serialize_into(&mut buffer)?;
+                data.data = Some(base64::encode(buffer));
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+/// Builds members id by id, e.g. as they stream in, without gathering the ids first.
+pub struct MembersBuilder {
+    members: Members,
+}
+
+impl MembersBuilder {
+    /// Builder of members with the given encoding, where a bloom filter is sized for the capacity.
+    pub fn new(encoding: ListEncoding, capacity: u64, false_positive_rate: Option<f64>) -> anyhow::Result<MembersBuilder> {
+        let members = match encoding {
+            ListEncoding::Set => Members::Set(HashSet::new()),
+            ListEncoding::Sorted => Members::Sorted(Vec::new()),
+            ListEncoding::Bloom => Members::Bloom(BloomFilter::with_capacity(
+                capacity,
+                false_positive_rate.unwrap_or(DEFAULT_FALSE_POSITIVE_RATE),
+            )?),
+            ListEncoding::Bitmap => Members::Bitmap(RoaringTreemap::new()),
+        };
+
+        Ok(MembersBuilder { members })
+    }
+
+    pub fn add(&mut self, id: String) -> anyhow::Result<()> {
+        match &mut self.members {
+            Members::Set(members) => {
+                members.insert(id);
+            }
+            // sorted once on build
+            Members::Sorted(members) => members.push(id),
+            Members::Bloom(filter) => filter.insert(&id)?,
+            Members::Bitmap(bitmap) => {
+                bitmap.insert(parse_numeric_id(&id)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn build(mut self) -> Members {
+        if let Members::Sorted(members) = &mut self.members {
+            members.sort_unstable();
+            members.dedup();
+        }
+
+        self.members
+    }
+}
+
+/// Serialised form of members, flattened into the audience list.
+///
+/// Lists written before encodings were supported have only the `list` field, and are read as set encoded.
+#[derive(Serialize, Deserialize)]
+struct MembersData {
+    #[serde(default)]
+    encoding: ListEncoding,
+
+    /// ids as is, for set encoding, or to be encoded for the other encodings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    list: Option<Vec<String>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    false_positive_rate: Option<f64>,
+
+    /// base64 of the encoded members
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+}
+
+impl Serialize for Members {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.to_data().map_err(serde::ser::Error::custom)?.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Members {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Members::from_data(MembersData::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Bloom filter over string ids, using double hashing of seahash.
+#[derive(Serialize, Deserialize)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_hashes: u32,
+
+    /// number of ids added
+    count: u64,
+    false_positive_rate: f64,
+}
+
+impl BloomFilter {
+    fn with_capacity(capacity: u64, false_positive_rate: f64) -> anyhow::Result<BloomFilter> {
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            bail!("False positive rate must be between 0 and 1, got: {}", false_positive_rate);
+        }
+
+        let ln2 = std::f64::consts::LN_2;
+        let capacity = capacity.max(MIN_BLOOM_CAPACITY) as f64;
+
+        let num_bits = (-capacity * false_positive_rate.ln() / (ln2 * ln2)).ceil() as u64;
+        let num_words = (num_bits + 63) / 64;
+        let num_hashes = ((num_words * 64) as f64 / capacity * ln2).round().max(1.0) as u32;
+
+        Ok(BloomFilter {
+            bits: vec![0; num_words as usize],
+            num_hashes,
+            count: 0,
+            false_positive_rate,
+        })
+    }
+
+    /// Empty filter of the same size, which can be merged into this one.
+    fn empty_like(&self) -> BloomFilter {
+        BloomFilter {
+            bits: vec![0; self.bits.len()],
+            num_hashes: self.num_hashes,
+            count: 0,
+            false_positive_rate: self.false_positive_rate,
+        }
+    }
+
+    /// Number of ids the filter is sized for, at its false positive rate.
+    ///
+    /// It is derived from the size, as filters are stored without it. It is at least the capacity the filter was
+    /// created with, as the size is rounded up to whole words.
+    fn capacity(&self) -> u64 {
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = self.bits.len() as f64 * 64.0;
+
+        (num_bits * ln2 * ln2 / -self.false_positive_rate.ln()).floor() as u64
+    }
+
+    /// Adds the id, failing beyond the capacity, as the false positive rate would rise past the configured one.
+    fn insert(&mut self, id: &str) -> anyhow::Result<()> {
+        self.ensure_capacity(1)?;
+
+        for index in self.indexes(id) {
+            self.bits[(index / 64) as usize] |= 1 << (index % 64);
+        }
+
+        self.count += 1;
+
+        Ok(())
+    }
+
+    fn merge(&mut self, other: &BloomFilter) -> anyhow::Result<()> {
+        if self.bits.len() != other.bits.len() || self.num_hashes != other.num_hashes {
+            bail!("Can't merge bloom filters of different sizes");
+        }
+        self.ensure_capacity(other.count)?;
+
+        for (word, other_word) in self.bits.iter_mut().zip(other.bits.iter()) {
+            *word |= other_word;
+        }
+        self.count += other.count;
+
+        Ok(())
+    }
+
+    fn ensure_capacity(&self, additional: u64) -> anyhow::Result<()> {
+        let capacity = self.capacity();
+        if self.count + additional > capacity {
+            bail!(
+                "Bloom encoded list is sized for {} members, upload the list again with a larger capacity instead",
+                capacity
+            );
+        }
+
+        Ok(())
+    }
+
+    fn contains(&self, id: &str) -> bool {
+        self.indexes(id).all(|index| self.bits[(index / 64) as usize] & (1 << (index % 64)) != 0)
+    }
+
+    fn indexes(&self, id: &str) -> impl Iterator<Item = u64> {
+        let num_bits = self.bits.len() as u64 * 64;
+        let hash1 = seahash::hash(id.as_bytes());
+        // odd, so that it never cycles back to the same bit
+        let hash2 = seahash::hash_seeded(id.as_bytes(), 1, 2, 3, 4) | 1;
+
+        (0..self.num_hashes as u64).map(move |i| hash1.wrapping_add(i.wrapping_mul(hash2)) % num_bits)
+    }
+}
+
+fn parse_numeric_id(id: &str) -> anyhow::Result<u64> {
+    id.parse::<u64>()
+        .with_context(|| format!("Id: {} is not numeric, as needed by bitmap encoded list", id))
+}
+
+/// Front codes the sorted ids -- each id is written as length of prefix shared with previous id, followed by rest of
+/// the id -- and gzips the result.
+fn encode_sorted(ids: &[String]) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let mut previous = "";
+
+    for id in ids {
+        let mut shared = previous.bytes().zip(id.bytes()).take_while(|(a, b)| a == b).count();
+        while !id.is_char_boundary(shared) {
+            shared -= 1;
+        }
+
+        let suffix = &id.as_bytes()[shared..];
+        encoder.write_all(&(shared as u32).to_le_bytes())?;
+        encoder.write_all(&(suffix.len() as u32).to_le_bytes())?;
+        encoder.write_all(suffix)?;
+
+        previous = id;
+    }
+
+    Ok(encoder.finish()?)
+}
+
+fn decode_sorted(data: &[u8]) -> anyhow::Result<Vec<String>> {
+    let mut buffer = vec![];
+    GzDecoder::new(data).read_to_end(&mut buffer)?;
+
+    let mut ids: Vec<String> = vec![];
+    let mut rest = &buffer[..];
+
+    while !rest.is_empty() {
+        if rest.len() < 8 {
+            bail!("Truncated sorted list data");
+        }
+
+        let shared = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let suffix_len = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        rest = &rest[8..];
+
+        let previous = ids.last().map_or("", |id| id.as_str());
+        let prefix = previous.get(..shared).ok_or_else(|| anyhow!("Corrupt sorted list data"))?;
+        if suffix_len > rest.len() {
+            bail!("Truncated sorted list data");
+        }
+
+        let suffix = std::str::from_utf8(&rest[..suffix_len]).map_err(|err| anyhow!("Corrupt sorted list data: {}", err))?;
+        let id = format!("{}{}", prefix, suffix);
+        rest = &rest[suffix_len..];
+
+        ids.push(id);
+    }
+
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ListEncoding, Members};
+
+    #[test]
+    fn encoded_members_round_trip() {
+        for encoding in &["set", "sorted", "bloom", "bitmap"] {
+            let json = format!(r#"{{"encoding": "{}", "list": ["1001", "1002", "2001"]}}"#, encoding);
+            let members: Members = serde_json::from_str(&json).unwrap();
+
+            let members: Members = serde_json::from_str(&serde_json::to_string(&members).unwrap()).unwrap();
+            assert_eq!(members.len(), 3);
+            assert!(members.contains("1002") && members.contains("2001"));
+            assert!(!members.contains("1003") || members.encoding() == ListEncoding::Bloom);
+        }
+    }
+
+    #[test]
+    fn bloom_encoded_members_are_limited_to_capacity() {
+        let mut members = Members::build(ListEncoding::Bloom, vec!["1".to_string()], None).unwrap();
+        let ids: Vec<String> = (0..5000).map(|id| id.to_string()).collect();
+
+        assert!(members.add(ids).is_err());
+        assert_eq!(members.len(), 1);
+
+        let mut builder = members.builder_like(None).unwrap();
+        builder.add("2".to_string()).unwrap();
+        members.merge(builder.build()).unwrap();
+        assert!(members.contains("1") && members.contains("2"));
+    }
+}
@@ -2,21 +2,39 @@ use serde::{Deserialize, Serialize};
 
 pub use app::App;
 pub use audience_list::AudienceList;
+pub use bundle::{AppBundle, ImportMode};
+pub use context_schema::ContextValidation;
 pub use experiment::Audience;
 pub use experiment::Experiment;
+pub use experiment::ExperimentState;
 pub use experiment::SizeSpec;
+pub use layer::Layer;
+pub use layer::LayerAllocation;
+pub use membership::{ListEncoding, MemberFormat, Members};
 pub use project::Project;
 pub use project::TrackingMethod;
 pub use script::Script;
 pub use script::ScriptEvaluator;
+pub use segment::{SegmentExpiries, SegmentSource, SegmentStatus};
 pub use variation::Variation;
 
 pub mod app;
+mod approval;
 mod audience_list;
+mod bundle;
+mod circuit_breaker;
+mod context_schema;
+mod enrichment;
+mod evaluation_plan;
 mod experiment;
 mod experiment_group;
+#[cfg(test)]
+pub(crate) mod fixtures;
+mod layer;
+mod membership;
 mod project;
 mod script;
+mod segment;
 mod skiplist_serde;
 mod variation;
 
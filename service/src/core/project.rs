@@ -1,3 +1,5 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::ops::Deref;
 
 use anyhow::{anyhow, Context};
@@ -11,10 +13,14 @@ use nanoid::nanoid;
 use parking_lot::lock_api::RwLockWriteGuard;
 use parking_lot::{RawRwLock, RwLock};
 use serde::{Deserialize, Serialize};
-use validator::Validate;
+use validator::{Validate, ValidationErrors};
 
 use crate::core::audience_list::AudienceList;
+use crate::core::context_schema::ContextSchema;
+use crate::core::enrichment::RequestEnrichment;
+use crate::core::layer::{validate_layers, Layer};
 use crate::core::{skiplist_serde, AddResponse, App, HasId};
+use crate::experiment_store::StoreBatch;
 use crate::server::{ApiError, ApiResult, HttpRequest, HttpResponse, HttpResult, HttpRoute};
 use crate::service::AbOptimisationService;
 
@@ -34,6 +40,30 @@ pub struct Project {
     #[serde(default = "default_tracking_method")]
     pub tracking_method: TrackingMethod,
 
+    #[serde(default)]
+    pub archived: bool,
+
+    #[validate]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub holdout: Option<Holdout>,
+
+    #[validate]
+    #[validate(custom = "validate_layers")]
+    #[serde(default)]
+    pub layers: Vec<Layer>,
+
+    /// Schema of the run context, which scripts of the experiments are linted against
+    #[validate]
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_schema: Option<ContextSchema>,
+
+    /// Attributes derived from the run request, which are made available to scripts as `req`
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_enrichment: Option<RequestEnrichment>,
+
     #[serde(skip)]
     #[serde(with = "skiplist_serde")]
     #[serde(default = "default_experiments")]
@@ -49,6 +79,19 @@ pub struct Project {
     pub modification_time: i64,
 }
 
+/// Users held out of all experiments of the project, to measure the cumulative impact of experimentation.
+#[derive(Serialize, Deserialize, Validate, PartialEq, Clone, Debug)]
+pub struct Holdout {
+    /// Percent of the users held out.
+    #[validate(range(min = 0, max = 100))]
+    pub size: i64,
+
+    /// Salt for bucketing users, defaults to project id. Changing it reshuffles the holdout group.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub salt: Option<String>,
+}
+
 fn default_experiments() -> SkipList<String, RwLock<Experiment>> {
     SkipList::new(epoch::default_collector().clone())
 }
@@ -57,6 +100,12 @@ fn default_audience_lists() -> SkipList<String, RwLock<AudienceList>> {
     SkipList::new(epoch::default_collector().clone())
 }
 
+impl Project {
+    pub fn layer(&self, short_name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.short_name == short_name)
+    }
+}
+
 impl HasId for Project {
     fn id(&self) -> &str {
         &self.id
@@ -107,19 +156,27 @@ impl AbOptimisationService {
     pub async fn add_project(&self, route: &HttpRoute<'_>, app_id: &str, body: Body) -> HttpResult {
         let mut req_data = HttpRequest::value::<Project>(route, body).await?;
 
-        let guard = &epoch::pin();
+        {
+            let guard = &epoch::pin();
 
-        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
-            let app_lock = entry.value();
-            let app_guard = app_lock.read();
+            let validation_visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
+                let app_lock = entry.value();
+                let app_guard = app_lock.read();
 
-            self.validate_project_data(&app_guard, &req_data, None, guard)?;
+                self.validate_project_data(&app_guard, &req_data, None, guard)
+            };
 
-            let id = nanoid!();
-            req_data.id = id.to_string();
-            self.experiment_store.write_project_data(app_id, &req_data)?;
+            self.visit_app(app_id, guard, validation_visitor)?;
+        }
+
+        let id = nanoid!();
+        req_data.id = id.to_string();
+        self.experiment_store.write_project_data(app_id, &req_data).await?;
 
-            app_guard.projects.insert(id.to_string(), RwLock::new(req_data), guard);
+        let guard = &epoch::pin();
+
+        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
+            entry.value().read().projects.insert(id.to_string(), RwLock::new(req_data), guard);
 
             HttpResponse::binary_or_json(route, &AddResponse { id })
         };
@@ -130,28 +187,91 @@ impl AbOptimisationService {
     pub async fn update_project(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, body: Body) -> HttpResult {
         let req_data = HttpRequest::value::<Project>(route, body).await?;
 
-        let guard = &epoch::pin();
+        self.update_project_with(route, app_id, project_id, req_data).await
+    }
 
-        let validation_visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
-            let app_lock = entry.value();
-            let app_guard = app_lock.read();
+    pub async fn patch_project(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, body: Body) -> HttpResult {
+        let patch = HttpRequest::patch(route, body).await?;
+
+        let req_data = {
+            let guard = &epoch::pin();
+
+            self.visit_project(app_id, project_id, guard, |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let existing_data = entry.value().read();
 
-            self.validate_project_data(&app_guard, &req_data, Some(project_id), guard)
+                patch.apply(existing_data.deref()).map_err(ApiError::BadRequest)
+            })?
         };
 
-        self.visit_app(app_id, guard, validation_visitor)?;
+        self.update_project_with(route, app_id, project_id, req_data).await
+    }
 
-        let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
-            let mut existing_data = entry.value().write();
+    async fn update_project_with(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, mut req_data: Project) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
 
-            AbOptimisationService::update_project_data(req_data, &mut existing_data);
+            let validation_visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
+                let app_lock = entry.value();
+                let app_guard = app_lock.read();
 
-            self.experiment_store.write_project_data(app_id, &existing_data)?;
+                self.validate_project_data(&app_guard, &req_data, Some(project_id), guard)
+            };
 
-            HttpResponse::str(route, "SUCCESS")
+            self.visit_app(app_id, guard, validation_visitor)?;
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let mut existing_data = entry.value().write();
+
+                // scripts of the experiments are linted again against a changed schema, except of the archived ones, which don't run
+                let schema = req_data
+                    .context_schema
+                    .as_ref()
+                    .filter(|_| req_data.context_schema != existing_data.context_schema);
+                let mut lint_errors = ValidationErrors::new();
+
+                // a layer can't be removed while an experiment is allocated in it
+                for experiment_entry in existing_data.experiments.iter(guard) {
+                    let experiment = experiment_entry.value().read();
+
+                    if let Some(allocation) = experiment.layer.as_ref() {
+                        if req_data.layer(&allocation.layer).is_none() {
+                            return Err(ApiError::BadRequest(anyhow!(
+                                "Layer {} is in use by experiment {}",
+                                allocation.layer,
+                                experiment.short_name
+                            )));
+                        }
+                    }
+
+                    if let (Some(schema), false) = (schema, experiment.archived) {
+                        for mut error in schema.lint_experiment(&experiment) {
+                            error.add_param(Cow::from("experiment"), &experiment.short_name);
+                            lint_errors.add("context_schema", error);
+                        }
+                    }
+                }
+
+                if !lint_errors.is_empty() {
+                    return Err(ApiError::Invalid(lint_errors));
+                }
+
+                // archive state is changed only via archive / unarchive apis
+                req_data.archived = existing_data.archived;
+                AbOptimisationService::update_project_data(req_data, &mut existing_data);
+
+                batch.write_project_data(app_id, &existing_data)?;
+
+                HttpResponse::str(route, "SUCCESS")
+            };
+
+            self.visit_project(app_id, project_id, guard, visitor)?
         };
 
-        self.visit_project(app_id, project_id, guard, visitor)
+        batch.commit().await?;
+
+        Ok(response)
     }
 
     fn update_project_data(req_data: Project, existing_data: &mut RwLockWriteGuard<RawRwLock, Project>) {
@@ -167,11 +287,31 @@ impl AbOptimisationService {
             existing_data.tracking_method = req_data.tracking_method
         }
 
+        if existing_data.holdout != req_data.holdout {
+            existing_data.holdout = req_data.holdout
+        }
+
+        if existing_data.layers != req_data.layers {
+            existing_data.layers = req_data.layers
+        }
+
+        if existing_data.context_schema != req_data.context_schema {
+            existing_data.context_schema = req_data.context_schema
+        }
+
+        if existing_data.request_enrichment != req_data.request_enrichment {
+            existing_data.request_enrichment = req_data.request_enrichment
+        }
+
+        if existing_data.archived != req_data.archived {
+            existing_data.archived = req_data.archived
+        }
+
         existing_data.modification_time = req_data.modification_time;
     }
 
     fn validate_project_data(&self, app: &App, data_to_validate: &Project, update_id: Option<&str>, guard: &Guard) -> ApiResult<()> {
-        data_to_validate.validate().with_context(|| format!("Error in validating project data"))?;
+        data_to_validate.validate().map_err(ApiError::Invalid)?;
 
         for entry in app.projects.iter(guard) {
             let value = entry.value();
@@ -195,6 +335,112 @@ impl AbOptimisationService {
         Ok(())
     }
 
+    pub async fn archive_project(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str, archived: bool) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        let response = {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<Project>>| {
+                let mut existing_data = entry.value().write();
+
+                if existing_data.archived != archived {
+                    info!("Setting archived={} for app:{}, project:{}", archived, app_id, project_id);
+
+                    existing_data.archived = archived;
+                    batch.write_project_data(app_id, &existing_data)?;
+                }
+
+                HttpResponse::str(route, "SUCCESS")
+            };
+
+            self.visit_project(app_id, project_id, guard, visitor)?
+        };
+
+        batch.commit().await?;
+
+        Ok(response)
+    }
+
+    pub async fn delete_project(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str) -> HttpResult {
+        let mut batch = self.experiment_store.batch();
+
+        {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<App>>| {
+                let app_guard = entry.value().read();
+
+                match app_guard.projects.get(project_id, guard) {
+                    None => Err(ApiError::NotFound(format!(
+                        "Project not found for project id: {} and app id: {}",
+                        project_id, app_id
+                    ))),
+                    Some(project_entry) => {
+                        let project = project_entry.value().read();
+                        self.delete_project_from_store(app_id, &project, &mut batch, guard);
+
+                        Ok(())
+                    }
+                }
+            };
+
+            self.visit_app(app_id, guard, visitor)?;
+        }
+
+        batch.commit().await?;
+
+        // app may have been deleted meanwhile, along with the project
+        let guard = &epoch::pin();
+        let _ = self.visit_app(app_id, guard, |entry| {
+            if let Some(project_entry) = entry.value().read().projects.remove(project_id, guard) {
+                project_entry.release(guard);
+            }
+
+            Ok(())
+        });
+
+        info!("Deleted project for app:{}, id:{}", app_id, project_id);
+
+        HttpResponse::str(route, "SUCCESS")
+    }
+
+    /// Adds deletes of project along with all its experiments and audience lists to the batch.
+    pub(crate) fn delete_project_from_store(&self, app_id: &str, project: &Project, batch: &mut StoreBatch<'_>, guard: &Guard) {
+        for entry in project.experiments.iter(guard) {
+            batch.delete_experiment_data(app_id, &project.id, entry.key());
+        }
+
+        for entry in project.audience_lists.iter(guard) {
+            batch.delete_audience_list_data(app_id, &project.id, entry.key());
+        }
+
+        batch.delete_project_data(app_id, &project.id);
+    }
+
+    pub(crate) fn prune_projects(&self, loaded_ids: &HashSet<String>) {
+        let guard = &epoch::pin();
+
+        for app_entry in self.apps.iter(guard) {
+            let app = app_entry.value().read();
+
+            let deleted_ids: Vec<String> = app
+                .projects
+                .iter(guard)
+                .map(|entry| entry.key().to_string())
+                .filter(|project_id| !loaded_ids.contains(&format!("{}.{}", app.id, project_id)))
+                .collect();
+
+            for project_id in deleted_ids {
+                info!("Removing project for app:{}, id:{}, as it doesn't exist in store anymore", app.id, project_id);
+
+                if let Some(entry) = app.projects.remove(&project_id, guard) {
+                    entry.release(guard);
+                }
+            }
+        }
+    }
+
     pub async fn get_project(&self, route: &HttpRoute<'_>, app_id: &str, project_id: &str) -> HttpResult {
         let guard = &epoch::pin();
 
@@ -42,10 +42,12 @@ impl ScriptEvaluator {
         ScriptEvaluator { evaluator }
     }
 
+    #[tracing::instrument(level = "debug", skip(self, script, ctx), fields(script = script.map_or("", |script| script.src.as_str())))]
     pub fn evaluate(&self, script: Option<&Script>, ctx: &jexl_eval::Value) -> anyhow::Result<bool> {
         match script {
             None => Ok(true),
             Some(script) => {
+                // TODO: evaluate by reference, evaluator takes the ast by value
                 let value = self
                     .evaluator
                     .eval_ast(script.expression.clone(), ctx)
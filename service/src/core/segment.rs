@@ -0,0 +1,395 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use crossbeam_epoch as epoch;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parking_lot::{Mutex, RwLock};
+use s3::Bucket;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::core::membership::{MemberFormat, Members};
+use crate::core::AudienceList;
+use crate::experiment_store::S3Store;
+use crate::service::AbOptimisationService;
+use crate::settings;
+
+lazy_static! {
+    /// buckets of the segment exports in S3, by bucket name
+    static ref SEGMENT_BUCKETS: Mutex<HashMap<String, Bucket>> = Mutex::new(HashMap::new());
+}
+
+/// Segment export, which members of a dynamic audience list are periodically refreshed from.
+#[derive(Serialize, Deserialize, Validate, Clone, PartialEq, Debug)]
+pub struct SegmentSource {
+    /// local file path, or `s3://{bucket}/{key}` for an S3 object
+    #[validate(length(min = 1))]
+    pub path: String,
+
+    #[serde(default)]
+    pub format: MemberFormat,
+
+    /// skip the first line of export
+    #[serde(default)]
+    pub header: bool,
+
+    #[serde(with = "humantime_serde")]
+    pub refresh_interval: Duration,
+
+    /// members age out after ttl since their time in export, or since they were last seen in export if it has no time.
+    /// Without ttl, members are replaced by the ones in the latest export.
+    #[serde(default, with = "humantime_serde")]
+    pub ttl: Option<Duration>,
+}
+
+/// Status of the refreshes of a dynamic audience list.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SegmentStatus {
+    /// time of last refresh in millis, successful or not
+    pub refresh_time: i64,
+
+    /// time of last successful refresh in millis
+    #[serde(default)]
+    pub success_time: i64,
+
+    /// number of members as of last successful refresh
+    #[serde(default)]
+    pub size: u64,
+
+    /// error of last refresh, if it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    /// for a list with ttl, whether member expiries are written to store alongside the list
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub expiries_stored: bool,
+}
+
+/// Expiry times of the members of a dynamic list with ttl, written to store alongside the list instead of in it, as
+/// they are needed only for refreshing it.
+#[derive(Serialize, Deserialize, Default)]
+pub struct SegmentExpiries {
+    /// members by their expiry time in millis, as members of an export without times share the same expiry
+    expiries: BTreeMap<i64, Vec<String>>,
+}
+
+impl SegmentExpiries {
+    /// Merges the exported members into the member expiries, and returns the members yet to expire.
+    fn merge(&mut self, ttl: Duration, exported: Vec<(String, Option<i64>)>, now: i64) -> Vec<String> {
+        let ttl = ttl.as_millis() as i64;
+        let mut by_member: HashMap<String, i64> = HashMap::new();
+
+        let existing = std::mem::take(&mut self.expiries)
+            .into_iter()
+            .flat_map(|(expiry, ids)| ids.into_iter().map(move |id| (id, expiry)));
+        let exported = exported.into_iter().map(|(id, time)| (id, time.unwrap_or(now) + ttl));
+
+        for (id, expiry) in existing.chain(exported) {
+            let latest = by_member.entry(id).or_insert(expiry);
+            if *latest < expiry {
+                *latest = expiry;
+            }
+        }
+
+        let mut live = Vec::with_capacity(by_member.len());
+        for (id, expiry) in by_member {
+            if expiry > now {
+                self.expiries.entry(expiry).or_insert_with(Vec::new).push(id.to_string());
+                live.push(id);
+            }
+        }
+
+        live
+    }
+}
+
+impl AbOptimisationService {
+    /// Refreshes members of the dynamic audience lists, which are due for refresh as per their refresh interval.
+    ///
+    /// Refreshed lists are written to store, from where other replicas load them. Only the replica with
+    /// `segment_refresh` setting enabled refreshes them, so that replicas don't race to write the same lists.
+    pub(crate) async fn refresh_segments(&self) {
+        if !settings::segment_refresh() {
+            return;
+        }
+
+        let now = chrono::Local::now().timestamp_millis();
+
+        for (app_id, project_id, list_id, source) in self.due_segments(now) {
+            info!(
+                "Refreshing audience_list for app:{}, project:{}, list_id:{} from segment: {}",
+                app_id, project_id, list_id, source.path
+            );
+
+            let exported = read_segment(&source).await;
+
+            if let Err(err) = self.apply_segment(&app_id, &project_id, &list_id, &source, exported, now).await {
+                error!(
+                    "Error in refreshing audience_list for app:{}, project:{}, list_id:{} ==> {:?}",
+                    app_id, project_id, list_id, err
+                );
+            }
+        }
+    }
+
+    fn due_segments(&self, now: i64) -> Vec<(String, String, String, SegmentSource)> {
+        let guard = &epoch::pin();
+        let mut due = vec![];
+
+        for app_entry in self.apps.iter(guard) {
+            let app = app_entry.value().read();
+
+            for project_entry in app.projects.iter(guard) {
+                let project = project_entry.value().read();
+
+                for list_entry in project.audience_lists.iter(guard) {
+                    let audience_list = list_entry.value().read();
+
+                    let source = match (&audience_list.segment, audience_list.archived) {
+                        (Some(source), false) => source,
+                        _ => continue,
+                    };
+
+                    let refresh_time = audience_list.segment_status.as_ref().map_or(0, |status| status.refresh_time);
+                    if now >= refresh_time + source.refresh_interval.as_millis() as i64 {
+                        due.push((app.id.to_string(), project.id.to_string(), audience_list.id.to_string(), source.clone()));
+                    }
+                }
+            }
+        }
+
+        due
+    }
+
+    /// Builds the members from the export, without holding the lock of the list, and swaps them in, unless the segment
+    /// or the encoding of the list was changed meanwhile.
+    async fn apply_segment(
+        &self,
+        app_id: &str,
+        project_id: &str,
+        list_id: &str,
+        source: &SegmentSource,
+        exported: anyhow::Result<Vec<(String, Option<i64>)>>,
+        now: i64,
+    ) -> anyhow::Result<()> {
+        let (encoding, false_positive_rate, expiries_stored) = self.visit_audience_list(app_id, project_id, list_id, &epoch::pin(), |entry| {
+            let audience_list = entry.value().read();
+
+            Ok((
+                audience_list.members.encoding(),
+                audience_list.members.false_positive_rate(),
+                audience_list.segment_status.as_ref().map_or(false, |status| status.expiries_stored),
+            ))
+        })?;
+
+        let mut expiries = None;
+        let members = match (exported, source.ttl) {
+            (Err(err), _) => Err(err),
+            (Ok(exported), None) => Members::build(encoding, exported.into_iter().map(|(id, _)| id).collect(), false_positive_rate),
+            (Ok(exported), Some(ttl)) => {
+                let existing = if expiries_stored {
+                    self.experiment_store.read_segment_expiries(app_id, project_id, list_id).await
+                } else {
+                    Ok(SegmentExpiries::default())
+                };
+
+                existing.and_then(|mut existing| {
+                    let ids = existing.merge(ttl, exported, now);
+                    expiries = Some(existing);
+                    Members::build(encoding, ids, false_positive_rate)
+                })
+            }
+        };
+
+        let mut batch = self.experiment_store.batch();
+
+        {
+            let guard = &epoch::pin();
+
+            let visitor = |entry: crossbeam_skiplist::base::Entry<String, RwLock<AudienceList>>| {
+                let mut audience_list = entry.value().write();
+
+                // segment or encoding may have been changed while reading the export
+                if audience_list.segment.as_ref() != Some(source) || audience_list.members.encoding() != encoding {
+                    return Ok(());
+                }
+
+                let mut status = audience_list.segment_status.take().unwrap_or_default();
+                status.refresh_time = now;
+
+                match members {
+                    Ok(members) => {
+                        status.success_time = now;
+                        status.size = members.len();
+                        status.error = None;
+                        audience_list.members = members;
+
+                        if let Some(expiries) = expiries.as_ref() {
+                            batch.write_segment_expiries(app_id, project_id, list_id, expiries)?;
+                            status.expiries_stored = true;
+                        }
+                    }
+                    Err(err) => {
+                        warn!(
+                            "Error in reading segment: {} for app:{}, project:{}, list_id:{} ==> {:?}",
+                            source.path, app_id, project_id, list_id, err
+                        );
+                        status.error = Some(format!("{:#}", err));
+                    }
+                }
+
+                audience_list.segment_status = Some(status);
+                batch.write_audience_list_data(app_id, project_id, &audience_list)?;
+
+                Ok(())
+            };
+
+            self.visit_audience_list(app_id, project_id, list_id, guard, visitor)?;
+        }
+
+        batch.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Reads the members in segment export, along with their times if given.
+async fn read_segment(source: &SegmentSource) -> anyhow::Result<Vec<(String, Option<i64>)>> {
+    let data = match source.path.strip_prefix("s3://") {
+        Some(location) => {
+            let (bucket, key) = location.split_once('/').ok_or_else(|| anyhow!("No key in segment path: {}", source.path))?;
+
+            // region and endpoint are same as of S3 store
+            let config = settings::S3StoreConfig {
+                bucket: bucket.to_string(),
+                ..settings::s3_store_config()
+            };
+
+            let (data, _) = segment_bucket(&config)?
+                .get_object(key)
+                .await
+                .with_context(|| format!("Error in reading segment: {}", source.path))?;
+
+            data
+        }
+        None => tokio::fs::read(&source.path)
+            .await
+            .with_context(|| format!("Error in reading segment: {}", source.path))?,
+    };
+
+    let data = std::str::from_utf8(&data).with_context(|| format!("Error in reading non utf-8 segment: {}", source.path))?;
+
+    data.lines()
+        .skip(if source.header { 1 } else { 0 })
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| source.format.parse_line(line))
+        .collect()
+}
+
+/// Bucket of the segment exports, connected on first read of the bucket and reused for the later refreshes.
+fn segment_bucket(config: &settings::S3StoreConfig) -> anyhow::Result<Bucket> {
+    let mut buckets = SEGMENT_BUCKETS.lock();
+
+    if let Some(bucket) = buckets.get(&config.bucket) {
+        return Ok(bucket.clone());
+    }
+
+    let bucket = S3Store::connect_bucket(config)?;
+    buckets.insert(config.bucket.to_string(), bucket.clone());
+
+    Ok(bucket)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use crate::core::fixtures::service;
+    use crate::core::segment::{read_segment, SegmentExpiries, SegmentSource};
+    use crate::core::AudienceList;
+
+    fn source(path: &str, header: bool) -> SegmentSource {
+        SegmentSource {
+            path: path.to_string(),
+            format: Default::default(),
+            header,
+            refresh_interval: Duration::from_secs(60),
+            ttl: None,
+        }
+    }
+
+    fn merge(expiries: &mut SegmentExpiries, exported: &[(&str, Option<i64>)], now: i64) -> Vec<String> {
+        let exported = exported.iter().map(|(id, time)| (id.to_string(), *time)).collect();
+        let mut live = expiries.merge(Duration::from_millis(1000), exported, now);
+        live.sort();
+        live
+    }
+
+    #[test]
+    fn ttl_expiry() {
+        let mut expiries = SegmentExpiries::default();
+
+        // members age out after ttl since their time in export, or since the refresh if exported without time
+        assert_eq!(merge(&mut expiries, &[("u1", Some(100)), ("u2", None)], 500), vec!["u1", "u2"]);
+        assert_eq!(merge(&mut expiries, &[], 1099), vec!["u1", "u2"]);
+        assert_eq!(merge(&mut expiries, &[("u2", None)], 1100), vec!["u2"]);
+
+        // an older time in export doesn't bring the expiry forward
+        assert_eq!(merge(&mut expiries, &[("u2", Some(0))], 2000), vec!["u2"]);
+        assert!(merge(&mut expiries, &[], 2100).is_empty());
+    }
+
+    #[tokio::test]
+    async fn due_segments() -> anyhow::Result<()> {
+        let service = service().await?;
+
+        for (list_id, archived) in [("s1", false), ("s2", true)].iter() {
+            let mut audience_list: AudienceList = serde_json::from_value(json!({
+                "name": list_id,
+                "list": [],
+                "segment": {"path": "segment.csv", "refresh_interval": "1m"},
+                "archived": archived,
+            }))?;
+            audience_list.id = list_id.to_string();
+            service.experiment_store.write_audience_list_data("a1", "p1", &audience_list).await?;
+        }
+        service.load_data().await?;
+
+        // static and archived lists are never due
+        let due = service.due_segments(1000);
+        assert_eq!(due.len(), 1);
+        assert_eq!((due[0].0.as_str(), due[0].1.as_str(), due[0].2.as_str()), ("a1", "p1", "s1"));
+        assert_eq!(due[0].3, source("segment.csv", false));
+
+        service
+            .apply_segment("a1", "p1", "s1", &due[0].3, Ok(vec![("u1".to_string(), None)]), 1000)
+            .await?;
+        assert!(service.due_segments(60_999).is_empty());
+        assert_eq!(service.due_segments(61_000).len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_local_segment() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("segment-{}.csv", std::process::id()));
+        tokio::fs::write(&path, "user_id,time\r\nu1,1000\r\n\r\n\"u2\",\nu3,1970-01-01T00:00:02Z\n").await?;
+
+        let exported = read_segment(&source(&path.to_string_lossy(), true)).await;
+        tokio::fs::remove_file(&path).await?;
+
+        assert_eq!(
+            exported?,
+            vec![("u1".to_string(), Some(1000)), ("u2".to_string(), None), ("u3".to_string(), Some(2000))]
+        );
+
+        assert!(read_segment(&source("missing/segment.csv", true)).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::Context;
+use async_trait::async_trait;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+use tokio::sync::Notify;
+use walkdir::WalkDir;
+
+use crate::experiment_store::store::{ObjectVersion, Store};
+use crate::settings;
+
+/// Sequence of the writes of this process, which keeps the temp files of concurrent writes of the same object apart.
+static WRITE_SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// Store keeping each object as a file under a local directory.
+pub struct LocalStore {
+    config: settings::LocalStoreConfig,
+}
+
+impl LocalStore {
+    pub fn new(config: settings::LocalStoreConfig) -> LocalStore {
+        LocalStore { config }
+    }
+
+    fn write_file(data: &[u8], path: &str) -> anyhow::Result<()> {
+        let mut file = File::create(path)?;
+
+        file.write_all(data).with_context(|| format!("Error in writing data to file: {}", path))?;
+        file.sync_all().with_context(|| format!("Error in syncing file: {}", path))?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Store for LocalStore {
+    fn path(&self) -> &str {
+        &self.config.path
+    }
+
+    fn refresh_rate(&self) -> u64 {
+        self.config.refresh_rate
+    }
+
+    fn watch(&self, changed: Arc<Notify>) -> anyhow::Result<()> {
+        if !self.config.watch {
+            return Ok(());
+        }
+
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        // debounce, so that a burst of edits (like git pull) results in a single reload
+        let mut watcher = notify::watcher(tx, Duration::from_millis(500)).context("Error in creating file watcher")?;
+        watcher
+            .watch(&self.config.path, RecursiveMode::Recursive)
+            .with_context(|| format!("Error in watching path: {}", self.config.path))?;
+
+        info!("Watching path: {} for changes", self.config.path);
+
+        std::thread::Builder::new().name("local-store-watcher".to_string()).spawn(move || {
+            // watcher is kept alive as long as the thread is running
+            let _watcher = watcher;
+
+            for event in rx {
+                match event {
+                    DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) | DebouncedEvent::Chmod(_) => {}
+                    DebouncedEvent::Error(err, path) => {
+                        warn!("Error in watching path: {:?} ==> {:?}", path, err);
+                    }
+                    _ => {
+                        debug!("Got file event: {:?}", event);
+                        changed.notify_one();
+                    }
+                }
+            }
+        })?;
+
+        Ok(())
+    }
+
+    async fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        std::fs::read(path).with_context(|| format!("Error in reading file: {}", path.to_string_lossy()))
+    }
+
+    async fn write(&self, path: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        // write to a temp file and then rename over the target, so that a crash or a full disk never leaves a truncated
+        // data file behind
+        let temp_path = format!("{}.{}.{}.tmp", path, std::process::id(), WRITE_SEQUENCE.fetch_add(1, Ordering::Relaxed));
+
+        let result = Self::write_file(&data, &temp_path).and_then(|_| {
+            std::fs::rename(&temp_path, path).with_context(|| format!("Error in renaming file: {} to {}", temp_path, path))?;
+
+            // persist the rename too
+            if let Some(parent) = Path::new(path).parent() {
+                File::open(parent)?.sync_all()?;
+            }
+
+            Ok(())
+        });
+
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+
+        result
+    }
+
+    async fn delete(&self, path: &str) -> anyhow::Result<()> {
+        match std::fs::remove_file(path) {
+            Ok(_) => Ok(()),
+            // already deleted, may be by another replica
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("Error in deleting file: {}", path)),
+        }
+    }
+
+    async fn list(&self, path: &str) -> anyhow::Result<Vec<(PathBuf, ObjectVersion)>> {
+        let mut objects = vec![];
+
+        for entry in WalkDir::new(path).follow_links(true).into_iter().filter_map(|e| e.ok()) {
+            // file may have been deleted while walking
+            let metadata = match entry.metadata() {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            let modification_time = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+
+            let version = ObjectVersion {
+                modification_time: modification_time.as_millis() as i64,
+                tag: format!("{}.{}", modification_time.as_nanos(), metadata.len()),
+            };
+
+            objects.push((entry.into_path(), version));
+        }
+
+        Ok(objects)
+    }
+
+    fn quarantine(&self, path: &Path) -> anyhow::Result<()> {
+        let mut quarantine_path = path.as_os_str().to_owned();
+        quarantine_path.push(".corrupt");
+
+        std::fs::rename(path, &quarantine_path).with_context(|| format!("Error in quarantining file: {}", path.to_string_lossy()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::core::fixtures::{local_store_config, local_store_path, visit_experiment, write_app};
+    use crate::core::ExperimentState;
+    use crate::experiment_store::local_store::{LocalStore, WRITE_SEQUENCE};
+    use crate::experiment_store::{ExperimentStore, Store};
+    use crate::service::AbOptimisationService;
+
+    #[tokio::test]
+    async fn corrupt_file_is_quarantined() -> anyhow::Result<()> {
+        let path = local_store_path("corrupt")?;
+        let service = AbOptimisationService::new(ExperimentStore::new_local_store(local_store_config(&path))?);
+
+        write_app(&service.experiment_store, ExperimentState::Running).await?;
+        service.load_data().await?;
+
+        let experiment_path = path.join("experiments/a1.p1.e1.experiment.data.json");
+        let data = std::fs::read(&experiment_path)?;
+        std::fs::write(&experiment_path, &data[..data.len() / 2])?;
+
+        // last good version keeps serving, also on the loads after the file is moved aside
+        for _ in 0..2 {
+            service.load_data().await?;
+            assert_eq!(visit_experiment(&service, |experiment| experiment.version)?, 1);
+        }
+
+        let quarantine_path = path.join("experiments/a1.p1.e1.experiment.data.json.corrupt");
+        assert!(!experiment_path.exists());
+        assert_eq!(std::fs::read(quarantine_path)?.len(), data.len() / 2);
+
+        std::fs::remove_dir_all(&path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_write_keeps_target() -> anyhow::Result<()> {
+        let path = local_store_path("failed-write")?;
+        let store = LocalStore::new(local_store_config(&path));
+
+        let target = path.join("apps/a1.app.data.json").to_string_lossy().to_string();
+        store.write(&target, b"good".to_vec()).await?;
+
+        // temp files of the next writes are blocked by directories, so that writing them fails
+        let sequence = WRITE_SEQUENCE.load(Ordering::Relaxed);
+        for n in sequence..sequence + 16 {
+            std::fs::create_dir(format!("{}.{}.{}.tmp", target, std::process::id(), n))?;
+        }
+
+        assert!(store.write(&target, b"bad".to_vec()).await.is_err());
+        assert_eq!(std::fs::read(&target)?, b"good");
+
+        std::fs::remove_dir_all(&path)?;
+
+        Ok(())
+    }
+}
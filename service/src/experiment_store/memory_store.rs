@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use parking_lot::RwLock;
+
+use crate::experiment_store::store::{ObjectVersion, Store};
+
+/// Store keeping the objects in memory, for tests and for trying out the service without any setup.
+///
+/// Nothing is persisted, so all the data is lost on restart.
+pub struct MemoryStore {
+    path: String,
+    objects: RwLock<BTreeMap<String, (Vec<u8>, ObjectVersion)>>,
+}
+
+impl MemoryStore {
+    pub fn new(path: &str) -> MemoryStore {
+        MemoryStore {
+            path: path.to_string(),
+            objects: RwLock::new(BTreeMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn refresh_rate(&self) -> u64 {
+        10
+    }
+
+    async fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let path = path.to_string_lossy();
+
+        self.objects
+            .read()
+            .get(path.as_ref())
+            .map(|(data, _)| data.clone())
+            .ok_or_else(|| anyhow!("No object found for path: {}", path))
+    }
+
+    async fn write(&self, path: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let mut objects = self.objects.write();
+
+        // tag is the write count of the object
+        let write_count = objects.get(path).map_or(0, |(_, version)| version.tag.parse::<u64>().unwrap_or_default()) + 1;
+        let version = ObjectVersion {
+            modification_time: chrono::Local::now().timestamp_millis(),
+            tag: write_count.to_string(),
+        };
+
+        objects.insert(path.to_string(), (data, version));
+
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str) -> anyhow::Result<()> {
+        self.objects.write().remove(path);
+
+        Ok(())
+    }
+
+    async fn list(&self, path: &str) -> anyhow::Result<Vec<(PathBuf, ObjectVersion)>> {
+        let objects = self
+            .objects
+            .read()
+            .iter()
+            .filter(|(object_path, _)| object_path.starts_with(path))
+            .map(|(object_path, (_, version))| (PathBuf::from(object_path), version.clone()))
+            .collect();
+
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crossbeam_epoch as epoch;
+
+    use crate::core::App;
+    use crate::experiment_store::ExperimentStore;
+    use crate::service::AbOptimisationService;
+
+    use super::MemoryStore;
+
+    #[tokio::test]
+    async fn load_written_app() {
+        let experiment_store = ExperimentStore::new(Box::new(MemoryStore::new("data")));
+
+        let mut app: App = serde_json::from_str(r#"{"name": "Test App", "short_name": "test"}"#).unwrap();
+        app.id = "app1".to_string();
+        experiment_store.write_app_data(&app).await.unwrap();
+
+        let service = AbOptimisationService::new(experiment_store);
+        service.load_data().await.unwrap();
+
+        {
+            let guard = &epoch::pin();
+            let entry = service.apps.get("app1", guard).unwrap();
+            assert_eq!(entry.value().read().short_name, "test");
+        }
+
+        // deleted object is pruned on next load
+        let mut batch = service.experiment_store.batch();
+        batch.delete_app_data("app1");
+        batch.commit().await.unwrap();
+
+        service.load_data().await.unwrap();
+        assert!(service.apps.get("app1", &epoch::pin()).is_none());
+    }
+}
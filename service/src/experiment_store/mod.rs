@@ -1,20 +1,38 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
 use anyhow::Context;
+use crossbeam_epoch as epoch;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
+use parking_lot::Mutex;
 use regex::Regex;
-
-use crate::core::{App, AudienceList, Experiment, Project};
-pub use crate::experiment_store::store::Store;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use crate::core::{App, AudienceList, Experiment, Project, SegmentExpiries};
+pub use crate::experiment_store::local_store::LocalStore;
+pub use crate::experiment_store::memory_store::MemoryStore;
+pub use crate::experiment_store::s3_store::S3Store;
+pub use crate::experiment_store::sqlite_store::SqliteStore;
+pub use crate::experiment_store::store::{ObjectVersion, Store, StoreFactory, StoreOp, StoreRegistry};
+use crate::server::{ApiError, ApiResult};
 use crate::service::AbOptimisationService;
 use crate::settings;
 
+mod local_store;
+mod memory_store;
+mod s3_store;
+mod sqlite_store;
 mod store;
 
 lazy_static! {
     static ref APP_RE: Regex = Regex::new(
         r"(?x)
 (?P<app_id>[A-Za-z0-9_~-]+)  # app-id
-[.]app[.]data.json
+[.]app[.]data.json$
 ",
     )
     .unwrap();
@@ -23,7 +41,7 @@ lazy_static! {
 (?P<app_id>[A-Za-z0-9_~-]+)  # app-id
 [.]
 (?P<project_id>[A-Za-z0-9_~-]+) # project-id
-[.]project[.]data[.]json
+[.]project[.]data[.]json$
 ",
     )
     .unwrap();
@@ -34,7 +52,7 @@ lazy_static! {
 (?P<project_id>[A-Za-z0-9_~-]+) # project-id
 [.]
 (?P<experiment_id>[A-Za-z0-9_~-]+)   # experiment-id
-[.]experiment[.]data[.]json
+[.]experiment[.]data[.]json$
 ",
     )
     .unwrap();
@@ -45,32 +63,76 @@ lazy_static! {
 (?P<project_id>[A-Za-z0-9_~-]+) # project-id
 .
 (?P<list_id>[A-Za-z0-9_~-]+)   # list-id
-.audience-list.data.json
+.audience-list.data.json$
+",
+    )
+    .unwrap();
+    static ref SEGMENT_EXPIRIES_RE: Regex = Regex::new(
+        r"(?x)
+(?P<app_id>[A-Za-z0-9_~-]+)  # app-id
+[.]
+(?P<project_id>[A-Za-z0-9_~-]+) # project-id
+[.]
+(?P<list_id>[A-Za-z0-9_~-]+)   # list-id
+[.]segment-expiries[.]data[.]json$
 ",
     )
     .unwrap();
 }
 
 pub struct ExperimentStore {
-    store: Store,
+    store: Box<dyn Store>,
+
+    /// versions of the objects as of last load, keyed by object path. Held for the whole load, which awaits on store
+    versions: tokio::sync::Mutex<HashMap<String, ObjectVersion>>,
+
+    /// paths of corrupt objects, whose last good version loaded in memory is kept
+    quarantined: Mutex<HashSet<String>>,
 }
 
 impl ExperimentStore {
+    /// Store registered for `store_kind` setting, configured by `store_config` setting.
+    pub fn configured(registry: &StoreRegistry) -> anyhow::Result<ExperimentStore> {
+        let store = registry.create(&settings::store_kind())?;
+        Ok(ExperimentStore::new(store))
+    }
+
     pub fn new_local_store(local_store_config: settings::LocalStoreConfig) -> anyhow::Result<ExperimentStore> {
         info!("Configuring local store: {:?}", local_store_config);
-        let store = Store::new_local_store(local_store_config)?;
-        Ok(ExperimentStore { store })
+        let store = LocalStore::new(local_store_config);
+        Ok(ExperimentStore::new(Box::new(store)))
+    }
+
+    pub fn new_sqlite_store(sqlite_store_config: settings::SqliteStoreConfig) -> anyhow::Result<ExperimentStore> {
+        info!("Configuring sqlite store: {:?}", sqlite_store_config);
+        let store = SqliteStore::new(sqlite_store_config)?;
+        Ok(ExperimentStore::new(Box::new(store)))
     }
 
-    pub fn new_s3_store(s3_store_config: settings::S3StoreConfig) -> anyhow::Result<ExperimentStore> {
-        info!("Configuring s3 store: {:?}", s3_store_config);
-        let store = Store::new_s3_store(s3_store_config)?;
-        Ok(ExperimentStore { store })
+    pub(crate) fn refresh_rate(&self) -> u64 {
+        self.store.refresh_rate()
     }
 
-    pub(crate) fn load_apps(&self, service: &AbOptimisationService) -> anyhow::Result<()> {
-        self.store.visit_path(&self.apps_path(), |data_path, last_modified_time| {
-            let f_name = data_path.to_string_lossy();
+    /// Notifies on changes in the store, if store supports watching.
+    pub(crate) fn watch(&self, changed: Arc<Notify>) -> anyhow::Result<()> {
+        self.store.watch(changed)
+    }
+
+    pub fn new(store: Box<dyn Store>) -> ExperimentStore {
+        ExperimentStore {
+            store,
+            versions: tokio::sync::Mutex::new(HashMap::new()),
+            quarantined: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub(crate) async fn load_apps(&self, service: &AbOptimisationService) -> anyhow::Result<()> {
+        let mut loaded_ids = HashSet::new();
+        let mut versions = self.versions.lock().await;
+        let mut visited_versions = HashMap::new();
+
+        for (data_path, version) in self.store.list(&self.apps_path()).await? {
+            let f_name = data_path.to_string_lossy().to_string();
             // info!("Got file: {}", f_name);
 
             if let Some(matches) = APP_RE.captures(&f_name) {
@@ -78,17 +140,37 @@ impl ExperimentStore {
 
                 // info!("Got app_id: {}", app_id);
 
-                let app: App = self.store.read_data(data_path)?;
-                service.load_app(app_id, app, last_modified_time)?;
+                let loaded = service.apps.get(app_id, &epoch::pin()).is_some();
+
+                self.refresh_object(&data_path, &version, loaded, &versions, &mut visited_versions, |app: App| {
+                    service.load_app(app_id, app, version.modification_time)
+                })
+                .await;
+
+                loaded_ids.insert(app_id.to_string());
+            }
+        }
+
+        for path in self.quarantined.lock().iter() {
+            if let Some(matches) = APP_RE.captures(path) {
+                loaded_ids.insert(matches.name("app_id").unwrap().as_str().to_string());
             }
+        }
+
+        // anything not in store anymore, was deleted (may be by other replica)
+        service.prune_apps(&loaded_ids);
+        Self::refresh_versions(&mut versions, &self.apps_path(), visited_versions);
 
-            Ok(())
-        })
+        Ok(())
     }
 
-    pub(crate) fn load_projects(&self, service: &AbOptimisationService) -> anyhow::Result<()> {
-        self.store.visit_path(&self.projects_path(), |data_path, last_modified_time| {
-            let f_name = data_path.to_string_lossy();
+    pub(crate) async fn load_projects(&self, service: &AbOptimisationService) -> anyhow::Result<()> {
+        let mut loaded_ids = HashSet::new();
+        let mut versions = self.versions.lock().await;
+        let mut visited_versions = HashMap::new();
+
+        for (data_path, version) in self.store.list(&self.projects_path()).await? {
+            let f_name = data_path.to_string_lossy().to_string();
             // info!("Got file: {}", f_name);
 
             if let Some(matches) = PROJECT_RE.captures(&f_name) {
@@ -97,19 +179,38 @@ impl ExperimentStore {
 
                 // info!("Got app_id: {} and project_id: {}", app_id, project_id);
 
-                let project: Project = self.store.read_data(data_path)?;
-                service
-                    .load_project(&f_name, app_id, project_id, project, last_modified_time)
-                    .with_context(|| format!("Error in adding project for file: {}", data_path.to_string_lossy()))?;
+                let loaded = service.visit_project(app_id, project_id, &epoch::pin(), |_| Ok(())).is_ok();
+
+                self.refresh_object(&data_path, &version, loaded, &versions, &mut visited_versions, |project: Project| {
+                    service
+                        .load_project(&f_name, app_id, project_id, project, version.modification_time)
+                        .with_context(|| format!("Error in adding project for file: {}", f_name))
+                })
+                .await;
+
+                loaded_ids.insert(format!("{}.{}", app_id, project_id));
+            }
+        }
+
+        for path in self.quarantined.lock().iter() {
+            if let Some(matches) = PROJECT_RE.captures(path) {
+                loaded_ids.insert(format!("{}.{}", &matches["app_id"], &matches["project_id"]));
             }
+        }
+
+        service.prune_projects(&loaded_ids);
+        Self::refresh_versions(&mut versions, &self.projects_path(), visited_versions);
 
-            Ok(())
-        })
+        Ok(())
     }
 
-    pub(crate) fn load_experiments(&self, service: &AbOptimisationService) -> anyhow::Result<()> {
-        self.store.visit_path(&self.experiments_path(), |data_path, last_modified_time| {
-            let f_name = data_path.to_string_lossy();
+    pub(crate) async fn load_experiments(&self, service: &AbOptimisationService) -> anyhow::Result<()> {
+        let mut loaded_ids = HashSet::new();
+        let mut versions = self.versions.lock().await;
+        let mut visited_versions = HashMap::new();
+
+        for (data_path, version) in self.store.list(&self.experiments_path()).await? {
+            let f_name = data_path.to_string_lossy().to_string();
 
             if let Some(matches) = EXPERIMENT_RE.captures(&f_name) {
                 let app_id = matches.name("app_id").unwrap().as_str();
@@ -118,19 +219,38 @@ impl ExperimentStore {
 
                 // info!("Got app_id: {} and project_id: {}, experiment_id: {}", app_id, project_id, experiment_id);
 
-                let experiment: Experiment = self.store.read_data(data_path)?;
-                service
-                    .load_experiment(&f_name, app_id, project_id, experiment_id, experiment, last_modified_time)
-                    .with_context(|| format!("Error in adding experiment for file: {}", data_path.to_string_lossy()))?;
+                let loaded = service.visit_experiment(app_id, project_id, experiment_id, &epoch::pin(), |_| Ok(())).is_ok();
+
+                self.refresh_object(&data_path, &version, loaded, &versions, &mut visited_versions, |experiment: Experiment| {
+                    service
+                        .load_experiment(&f_name, app_id, project_id, experiment_id, experiment, version.modification_time)
+                        .with_context(|| format!("Error in adding experiment for file: {}", f_name))
+                })
+                .await;
+
+                loaded_ids.insert(format!("{}.{}.{}", app_id, project_id, experiment_id));
+            }
+        }
+
+        for path in self.quarantined.lock().iter() {
+            if let Some(matches) = EXPERIMENT_RE.captures(path) {
+                loaded_ids.insert(format!("{}.{}.{}", &matches["app_id"], &matches["project_id"], &matches["experiment_id"]));
             }
+        }
 
-            Ok(())
-        })
+        service.prune_experiments(&loaded_ids);
+        Self::refresh_versions(&mut versions, &self.experiments_path(), visited_versions);
+
+        Ok(())
     }
 
-    pub(crate) fn load_audience_lists(&self, service: &AbOptimisationService) -> anyhow::Result<()> {
-        self.store.visit_path(&self.audience_lists_path(), |data_path, last_modified_time| {
-            let f_name = data_path.to_string_lossy();
+    pub(crate) async fn load_audience_lists(&self, service: &AbOptimisationService) -> anyhow::Result<()> {
+        let mut loaded_ids = HashSet::new();
+        let mut versions = self.versions.lock().await;
+        let mut visited_versions = HashMap::new();
+
+        for (data_path, version) in self.store.list(&self.audience_lists_path()).await? {
+            let f_name = data_path.to_string_lossy().to_string();
 
             if let Some(matches) = AUDIENCE_LIST_RE.captures(&f_name) {
                 let app_id = matches.name("app_id").unwrap().as_str();
@@ -139,52 +259,211 @@ impl ExperimentStore {
 
                 // info!("Got app_id: {} and project_id: {}, list_id: {}", app_id, project_id, list_id);
 
-                let audience_list: AudienceList = self.store.read_data(data_path)?;
-                service
-                    .load_audience_list(&f_name, app_id, project_id, list_id, audience_list, last_modified_time)
-                    .with_context(|| format!("Error in adding audience list for file: {}", data_path.to_string_lossy()))?;
+                let loaded = service.visit_audience_list(app_id, project_id, list_id, &epoch::pin(), |_| Ok(())).is_ok();
+
+                self.refresh_object(&data_path, &version, loaded, &versions, &mut visited_versions, |audience_list: AudienceList| {
+                    service
+                        .load_audience_list(&f_name, app_id, project_id, list_id, audience_list, version.modification_time)
+                        .with_context(|| format!("Error in adding audience list for file: {}", f_name))
+                })
+                .await;
+
+                loaded_ids.insert(format!("{}.{}.{}", app_id, project_id, list_id));
+            }
+        }
+
+        for path in self.quarantined.lock().iter() {
+            if let Some(matches) = AUDIENCE_LIST_RE.captures(path) {
+                loaded_ids.insert(format!("{}.{}.{}", &matches["app_id"], &matches["project_id"], &matches["list_id"]));
+            }
+        }
+
+        service.prune_audience_lists(&loaded_ids);
+        Self::refresh_versions(&mut versions, &self.audience_lists_path(), visited_versions);
+
+        Ok(())
+    }
+
+    /// Reads and loads the object, if it changed since the last load or is not loaded in memory.
+    ///
+    /// A corrupt object is quarantined, while its last good version loaded in memory is kept serving. Any other error is
+    /// logged, and the object is retried in the next refresh, so that one bad object doesn't fail loading of the rest.
+    async fn refresh_object<T, F>(
+        &self,
+        data_path: &Path,
+        version: &ObjectVersion,
+        loaded: bool,
+        versions: &HashMap<String, ObjectVersion>,
+        visited_versions: &mut HashMap<String, ObjectVersion>,
+        load: F,
+    ) where
+        T: DeserializeOwned,
+        F: FnOnce(T) -> anyhow::Result<()>,
+    {
+        let f_name = data_path.to_string_lossy().to_string();
+        let quarantined = self.quarantined.lock().contains(&f_name);
+
+        if versions.get(&f_name) == Some(version) && (loaded || quarantined) {
+            visited_versions.insert(f_name, version.clone());
+            return;
+        }
+
+        let result = self.read_data::<T>(data_path).await.and_then(|data| {
+            self.quarantined.lock().remove(&f_name);
+            load(data)
+        });
+
+        match result {
+            Ok(_) => {
+                visited_versions.insert(f_name, version.clone());
+            }
+            Err(err) if err.downcast_ref::<serde_json::Error>().is_some() => {
+                error!("Quarantining corrupt data file: {} ==> {:?}", f_name, err);
+
+                if let Err(err) = self.store.quarantine(data_path) {
+                    error!("Error in quarantining data file: {} ==> {:?}", f_name, err);
+                }
+
+                self.quarantined.lock().insert(f_name.to_string());
+                visited_versions.insert(f_name, version.clone());
             }
+            Err(err) => {
+                error!("Error in loading data file: {} ==> {:?}", f_name, err);
+            }
+        }
+    }
+
+    async fn read_data<T>(&self, path: &Path) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let buffer = self.store.read(path).await?;
 
-            Ok(())
-        })
+        serde_json::from_slice(&buffer).with_context(|| format!("Error in parsing data from path: {}", path.to_string_lossy()))
     }
 
-    pub(crate) fn write_app_data(&self, app: &App) -> anyhow::Result<()> {
-        let file_path = format!("{}/{}.app.data.json", self.apps_path(), &app.id);
+    async fn write_data<T>(&self, data: &T, path: &str) -> ApiResult<()>
+    where
+        T: Serialize,
+    {
+        let buffer = serde_json::to_vec(data)
+            .with_context(|| format!("Error in writing data to buffer: {}", path))
+            .map_err(ApiError::InternalServerError)?;
+
+        self.store.write(path, buffer).await.map_err(ApiError::InternalServerError)
+    }
+
+    /// Replaces versions of the objects under the path with the ones visited in the current load.
+    ///
+    /// An unchanged object is fetched again only if it is not loaded in memory, e.g. it was pruned along with its parent.
+    fn refresh_versions(versions: &mut HashMap<String, ObjectVersion>, path: &str, visited_versions: HashMap<String, ObjectVersion>) {
+        versions.retain(|object_path, _| !object_path.starts_with(path));
+        versions.extend(visited_versions);
+    }
+
+    pub(crate) async fn write_app_data(&self, app: &App) -> ApiResult<()> {
+        let file_path = self.app_file_path(&app.id);
 
         info!("Writing app data to file: {}", file_path);
 
-        self.store.write_data(app, &file_path)
+        self.write_data(app, &file_path).await
     }
 
-    pub(crate) fn write_project_data(&self, app_id: &str, proj: &Project) -> anyhow::Result<()> {
-        let file_path = format!("{}/{}.{}.project.data.json", self.projects_path(), app_id, &proj.id);
+    pub(crate) async fn write_project_data(&self, app_id: &str, proj: &Project) -> ApiResult<()> {
+        let file_path = self.project_file_path(app_id, &proj.id);
 
         info!("Writing project data to file: {}", file_path);
 
-        self.store.write_data(proj, &file_path)
+        self.write_data(proj, &file_path).await
     }
 
-    pub(crate) fn write_experiment_data(&self, app_id: &str, project_id: &str, experiment: &Experiment) -> anyhow::Result<()> {
-        let file_path = format!("{}/{}.{}.{}.experiment.data.json", self.experiments_path(), app_id, project_id, &experiment.id);
+    pub(crate) async fn write_experiment_data(&self, app_id: &str, project_id: &str, experiment: &Experiment) -> ApiResult<()> {
+        let file_path = self.experiment_file_path(app_id, project_id, &experiment.id);
 
         info!("Writing experiment data to file: {}", file_path);
 
-        self.store.write_data(experiment, &file_path)
+        self.write_data(experiment, &file_path).await
     }
 
-    pub(crate) fn write_audience_list_data(&self, app_id: &str, project_id: &str, audience_list: &AudienceList) -> anyhow::Result<()> {
-        let file_path = format!(
-            "{}/{}.{}.{}.audience-list.data.json",
-            self.audience_lists_path(),
-            app_id,
-            project_id,
-            &audience_list.id
-        );
+    pub(crate) async fn write_audience_list_data(&self, app_id: &str, project_id: &str, audience_list: &AudienceList) -> ApiResult<()> {
+        let file_path = self.audience_list_file_path(app_id, project_id, &audience_list.id);
 
         info!("Writing audience_list data to file: {}", file_path);
 
-        self.store.write_data(audience_list, &file_path)
+        self.write_data(audience_list, &file_path).await
+    }
+
+    /// Member expiries of the dynamic audience list, written alongside it on its refreshes.
+    pub(crate) async fn read_segment_expiries(&self, app_id: &str, project_id: &str, list_id: &str) -> anyhow::Result<SegmentExpiries> {
+        let file_path = self.segment_expiries_file_path(app_id, project_id, list_id);
+
+        self.read_data(Path::new(&file_path)).await
+    }
+
+    /// Batch of writes and deletes, applied together on commit.
+    pub(crate) fn batch(&self) -> StoreBatch<'_> {
+        StoreBatch { store: self, ops: vec![] }
+    }
+
+    /// Copies all the objects of this store to the target store in a single batch, and returns the number of objects
+    /// copied. Objects are copied as is, without being loaded.
+    pub(crate) async fn copy_to(&self, target: &ExperimentStore) -> anyhow::Result<usize> {
+        let mut batch = target.batch();
+
+        let paths = [
+            (self.apps_path(), target.apps_path(), &*APP_RE),
+            (self.projects_path(), target.projects_path(), &*PROJECT_RE),
+            (self.experiments_path(), target.experiments_path(), &*EXPERIMENT_RE),
+            (self.audience_lists_path(), target.audience_lists_path(), &*AUDIENCE_LIST_RE),
+            (self.audience_lists_path(), target.audience_lists_path(), &*SEGMENT_EXPIRIES_RE),
+        ];
+
+        for (source_path, target_path, regex) in paths.iter() {
+            for (data_path, _) in self.store.list(source_path).await? {
+                let f_name = data_path.to_string_lossy().to_string();
+
+                if let Some(file_name) = data_path.file_name().map(|name| name.to_string_lossy().to_string()) {
+                    if regex.is_match(&f_name) {
+                        let data = self.store.read(&data_path).await?;
+                        batch.ops.push(StoreOp::Write {
+                            path: format!("{}/{}", target_path, file_name),
+                            data,
+                        });
+                    }
+                }
+            }
+        }
+
+        let count = batch.ops.len();
+        batch.commit().await?;
+
+        Ok(count)
+    }
+
+    fn app_file_path(&self, app_id: &str) -> String {
+        format!("{}/{}.app.data.json", self.apps_path(), app_id)
+    }
+
+    fn project_file_path(&self, app_id: &str, project_id: &str) -> String {
+        format!("{}/{}.{}.project.data.json", self.projects_path(), app_id, project_id)
+    }
+
+    fn experiment_file_path(&self, app_id: &str, project_id: &str, experiment_id: &str) -> String {
+        format!("{}/{}.{}.{}.experiment.data.json", self.experiments_path(), app_id, project_id, experiment_id)
+    }
+
+    fn audience_list_file_path(&self, app_id: &str, project_id: &str, list_id: &str) -> String {
+        format!("{}/{}.{}.{}.audience-list.data.json", self.audience_lists_path(), app_id, project_id, list_id)
+    }
+
+    fn segment_expiries_file_path(&self, app_id: &str, project_id: &str, list_id: &str) -> String {
+        format!(
+            "{}/{}.{}.{}.segment-expiries.data.json",
+            self.audience_lists_path(),
+            app_id,
+            project_id,
+            list_id
+        )
     }
 
     fn apps_path(&self) -> String {
@@ -203,3 +482,195 @@ impl ExperimentStore {
         format!("{}/audience_lists", self.store.path())
     }
 }
+
+/// Writes and deletes applied together on commit.
+///
+/// With sqlite store the batch is atomic, with the others ops are applied one by one in the order added.
+pub(crate) struct StoreBatch<'a> {
+    store: &'a ExperimentStore,
+    ops: Vec<StoreOp>,
+}
+
+impl<'a> StoreBatch<'a> {
+    pub(crate) fn write_app_data(&mut self, app: &App) -> ApiResult<()> {
+        self.write(self.store.app_file_path(&app.id), app)
+    }
+
+    pub(crate) fn write_project_data(&mut self, app_id: &str, proj: &Project) -> ApiResult<()> {
+        self.write(self.store.project_file_path(app_id, &proj.id), proj)
+    }
+
+    pub(crate) fn write_experiment_data(&mut self, app_id: &str, project_id: &str, experiment: &Experiment) -> ApiResult<()> {
+        self.write(self.store.experiment_file_path(app_id, project_id, &experiment.id), experiment)
+    }
+
+    pub(crate) fn write_audience_list_data(&mut self, app_id: &str, project_id: &str, audience_list: &AudienceList) -> ApiResult<()> {
+        self.write(self.store.audience_list_file_path(app_id, project_id, &audience_list.id), audience_list)
+    }
+
+    pub(crate) fn write_segment_expiries(&mut self, app_id: &str, project_id: &str, list_id: &str, expiries: &SegmentExpiries) -> ApiResult<()> {
+        self.write(self.store.segment_expiries_file_path(app_id, project_id, list_id), expiries)
+    }
+
+    pub(crate) fn delete_app_data(&mut self, app_id: &str) {
+        self.ops.push(StoreOp::Delete {
+            path: self.store.app_file_path(app_id),
+        });
+    }
+
+    pub(crate) fn delete_project_data(&mut self, app_id: &str, project_id: &str) {
+        self.ops.push(StoreOp::Delete {
+            path: self.store.project_file_path(app_id, project_id),
+        });
+    }
+
+    pub(crate) fn delete_experiment_data(&mut self, app_id: &str, project_id: &str, experiment_id: &str) {
+        self.ops.push(StoreOp::Delete {
+            path: self.store.experiment_file_path(app_id, project_id, experiment_id),
+        });
+    }
+
+    pub(crate) fn delete_audience_list_data(&mut self, app_id: &str, project_id: &str, list_id: &str) {
+        self.ops.push(StoreOp::Delete {
+            path: self.store.audience_list_file_path(app_id, project_id, list_id),
+        });
+        self.ops.push(StoreOp::Delete {
+            path: self.store.segment_expiries_file_path(app_id, project_id, list_id),
+        });
+    }
+
+    fn write<T: Serialize>(&mut self, path: String, data: &T) -> ApiResult<()> {
+        let data = serde_json::to_vec(data)
+            .with_context(|| format!("Error in serialising data for path: {}", path))
+            .map_err(ApiError::InternalServerError)?;
+        self.ops.push(StoreOp::Write { path, data });
+
+        Ok(())
+    }
+
+    /// Applies the ops, failing with internal server error, as the failure is of the store and not of the request.
+    pub(crate) async fn commit(self) -> ApiResult<()> {
+        if self.ops.is_empty() {
+            return Ok(());
+        }
+
+        info!("Applying batch of {} ops to store", self.ops.len());
+
+        {
+            let mut quarantined = self.store.quarantined.lock();
+            for op in self.ops.iter() {
+                if let StoreOp::Delete { path } = op {
+                    quarantined.remove(path);
+                }
+            }
+        }
+
+        self.store.store.apply_batch(self.ops).await.map_err(ApiError::InternalServerError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use crossbeam_epoch as epoch;
+    use parking_lot::Mutex;
+    use serde_json::json;
+
+    use crate::core::fixtures::{empty_service, experiment_json, visit_experiment, write_app};
+    use crate::core::{Experiment, ExperimentState};
+    use crate::experiment_store::{ExperimentStore, MemoryStore, ObjectVersion, Store};
+    use crate::service::AbOptimisationService;
+
+    /// Memory store recording the paths of the objects read.
+    struct ReadRecordingStore {
+        store: MemoryStore,
+        reads: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Store for ReadRecordingStore {
+        fn path(&self) -> &str {
+            self.store.path()
+        }
+
+        fn refresh_rate(&self) -> u64 {
+            self.store.refresh_rate()
+        }
+
+        async fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+            self.reads.lock().push(path.to_string_lossy().to_string());
+            self.store.read(path).await
+        }
+
+        async fn write(&self, path: &str, data: Vec<u8>) -> anyhow::Result<()> {
+            self.store.write(path, data).await
+        }
+
+        async fn delete(&self, path: &str) -> anyhow::Result<()> {
+            self.store.delete(path).await
+        }
+
+        async fn list(&self, path: &str) -> anyhow::Result<Vec<(PathBuf, ObjectVersion)>> {
+            self.store.list(path).await
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_changed_object() -> anyhow::Result<()> {
+        let reads = Arc::new(Mutex::new(vec![]));
+        let store = ReadRecordingStore {
+            store: MemoryStore::new("data"),
+            reads: reads.clone(),
+        };
+        let service = AbOptimisationService::new(ExperimentStore::new(Box::new(store)));
+
+        write_app(&service.experiment_store, ExperimentState::Running).await?;
+        service.load_data().await?;
+        assert_eq!(reads.lock().len(), 5);
+
+        // unchanged objects are not read again
+        reads.lock().clear();
+        service.load_data().await?;
+        assert!(reads.lock().is_empty());
+
+        // loaded object is updated only by a later modification, which is in millis
+        tokio::time::sleep(Duration::from_millis(2)).await;
+
+        let mut experiment: Experiment = serde_json::from_value(experiment_json())?;
+        experiment.id = "e1".to_string();
+        experiment.data = Some(json!({"color": "blue"}));
+        service.experiment_store.write_experiment_data("a1", "p1", &experiment).await?;
+
+        service.load_data().await?;
+        assert_eq!(*reads.lock(), vec!["data/experiments/a1.p1.e1.experiment.data.json"]);
+        assert_eq!(
+            visit_experiment(&service, |experiment| experiment.data.clone())?,
+            Some(json!({"color": "blue"}))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn prune_removed_object() -> anyhow::Result<()> {
+        let service = empty_service();
+
+        write_app(&service.experiment_store, ExperimentState::Running).await?;
+        service.load_data().await?;
+
+        // removed by another replica, straight from the store
+        let mut batch = service.experiment_store.batch();
+        batch.delete_audience_list_data("a1", "p1", "l2");
+        batch.commit().await?;
+
+        service.load_data().await?;
+        assert!(service.visit_audience_list("a1", "p1", "l2", &epoch::pin(), |_| Ok(())).is_err());
+        assert!(service.visit_audience_list("a1", "p1", "l1", &epoch::pin(), |_| Ok(())).is_ok());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use async_trait::async_trait;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use s3::creds::Credentials;
+use s3::region::Region;
+use s3::Bucket;
+
+use crate::experiment_store::store::{ObjectVersion, Store};
+use crate::settings;
+
+/// Store keeping each object as an object in a S3 bucket.
+pub struct S3Store {
+    config: settings::S3StoreConfig,
+
+    /// bucket is connected once, as parsing region and loading credentials on every call is costly
+    bucket: Bucket,
+}
+
+impl S3Store {
+    pub fn new(config: settings::S3StoreConfig) -> anyhow::Result<S3Store> {
+        let bucket = Self::connect_bucket(&config)?;
+
+        Ok(S3Store { config, bucket })
+    }
+
+    /// Bucket of the config, also used for reading other objects than the store ones, like segment exports.
+    pub(crate) fn connect_bucket(config: &settings::S3StoreConfig) -> anyhow::Result<Bucket> {
+        // custom endpoint is for S3 compatible stores, like MinIO
+        let region = match config.endpoint.as_ref() {
+            Some(endpoint) => Region::Custom {
+                region: config.region.to_string(),
+                endpoint: endpoint.to_string(),
+            },
+            None => config.region.parse()?,
+        };
+        let credentials = Credentials::default()?;
+
+        let bucket = if config.path_style {
+            Bucket::new_with_path_style(&config.bucket, region, credentials)
+        } else {
+            Bucket::new(&config.bucket, region, credentials)
+        };
+
+        bucket.with_context(|| format!("Error in connecting to bucket for config: {:?}", config))
+    }
+}
+
+#[async_trait]
+impl Store for S3Store {
+    fn path(&self) -> &str {
+        &self.config.path
+    }
+
+    fn refresh_rate(&self) -> u64 {
+        self.config.refresh_rate
+    }
+
+    async fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let (buffer, _) = self
+            .bucket
+            .get_object(path.to_string_lossy())
+            .await
+            .with_context(|| format!("Error in reading data from path: {}", path.to_string_lossy()))?;
+
+        Ok(buffer)
+    }
+
+    async fn write(&self, path: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        self.bucket
+            .put_object(path, &data)
+            .await
+            .with_context(|| format!("Error in writing data to S3 file: {}", path))?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, path: &str) -> anyhow::Result<()> {
+        self.bucket
+            .delete_object(path)
+            .await
+            .with_context(|| format!("Error in deleting S3 file: {}", path))?;
+
+        Ok(())
+    }
+
+    async fn list(&self, path: &str) -> anyhow::Result<Vec<(PathBuf, ObjectVersion)>> {
+        let mut objects = vec![];
+        let mut consumed = false;
+        let mut continuation_token = None;
+
+        while !consumed {
+            let (list_results, _) = self
+                .bucket
+                .list_page(path.to_string(), None, continuation_token.clone(), None, Some(100))
+                .await
+                .with_context(|| format!("Error in listing objects in path: {}", path))?;
+
+            for entry in list_results.contents {
+                let modified_time =
+                    chrono::DateTime::parse_from_rfc3339(&entry.last_modified).with_context(|| format!("Error in parsing last_modified time"))?;
+
+                let version = ObjectVersion {
+                    modification_time: modified_time.timestamp_millis(),
+                    tag: entry.e_tag.to_string(),
+                };
+
+                objects.push((PathBuf::from(entry.key), version));
+            }
+
+            consumed = !list_results.is_truncated;
+            if !consumed {
+                continuation_token = list_results.next_continuation_token;
+            }
+        }
+
+        Ok(objects)
+    }
+
+    fn quarantine(&self, _path: &Path) -> anyhow::Result<()> {
+        // S3 writes are atomic, so a corrupt object can only be a manual upload -- left as is
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use chrono::SecondsFormat;
+    use crossbeam_epoch as epoch;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Method, Request, Response, Server, StatusCode};
+    use parking_lot::Mutex;
+
+    use crate::core::fixtures::{visit_experiment, write_app};
+    use crate::core::ExperimentState;
+    use crate::experiment_store::s3_store::S3Store;
+    use crate::experiment_store::ExperimentStore;
+    use crate::service::AbOptimisationService;
+    use crate::settings;
+
+    /// data and last modified time of the objects, by key
+    type Objects = Arc<Mutex<BTreeMap<String, (Vec<u8>, String)>>>;
+
+    fn config(endpoint: &str, path_style: bool) -> settings::S3StoreConfig {
+        // credentials are loaded on connecting, though not checked by the local server
+        std::env::set_var("AWS_ACCESS_KEY_ID", "abof");
+        std::env::set_var("AWS_SECRET_ACCESS_KEY", "abof-secret");
+
+        settings::S3StoreConfig {
+            bucket: "abof-data".to_string(),
+            region: "us-east-1".to_string(),
+            path: "data".to_string(),
+            refresh_rate: 10,
+            endpoint: Some(endpoint.to_string()),
+            path_style,
+        }
+    }
+
+    fn decode(value: &str) -> String {
+        let mut decoded = vec![];
+        let mut bytes = value.bytes();
+
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'%' => {
+                    let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                    decoded.push(u8::from_str_radix(&String::from_utf8_lossy(&hex), 16).unwrap_or_default());
+                }
+                b'+' => decoded.push(b' '),
+                _ => decoded.push(byte),
+            }
+        }
+
+        String::from_utf8_lossy(&decoded).to_string()
+    }
+
+    fn status(status: StatusCode) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = status;
+        response
+    }
+
+    /// Serves the object apis used by the store, of a bucket addressed in path, like S3 compatible stores do.
+    async fn serve(objects: Objects, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+        let key = decode(req.uri().path().trim_start_matches("/abof-data").trim_start_matches('/'));
+        let prefix = req
+            .uri()
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| *name == "prefix")
+            .map(|(_, value)| decode(value))
+            .unwrap_or_default();
+        let method = req.method().clone();
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap_or_default();
+
+        let mut objects = objects.lock();
+        let response = match (method, key.as_str()) {
+            (Method::GET, "") => {
+                let contents: String = objects
+                    .iter()
+                    .filter(|(key, _)| key.starts_with(&prefix))
+                    .map(|(key, (data, modified))| {
+                        format!(
+                            "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>\"{}\"</ETag><Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+                            key,
+                            modified,
+                            modified,
+                            data.len()
+                        )
+                    })
+                    .collect();
+
+                Response::new(Body::from(format!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?><ListBucketResult><Name>abof-data</Name><Prefix>{}</Prefix><MaxKeys>100</MaxKeys><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+                    prefix, contents
+                )))
+            }
+            (Method::GET, key) => match objects.get(key) {
+                Some((data, _)) => Response::new(Body::from(data.clone())),
+                None => status(StatusCode::NOT_FOUND),
+            },
+            (Method::PUT, key) => {
+                let modified = chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+                objects.insert(key.to_string(), (body.to_vec(), modified));
+                status(StatusCode::OK)
+            }
+            (Method::DELETE, key) => {
+                objects.remove(key);
+                status(StatusCode::NO_CONTENT)
+            }
+            _ => status(StatusCode::METHOD_NOT_ALLOWED),
+        };
+
+        Ok(response)
+    }
+
+    fn start_server(objects: Objects) -> SocketAddr {
+        let make_service = make_service_fn(move |_| {
+            let objects = objects.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| serve(objects.clone(), req))) }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        addr
+    }
+
+    #[test]
+    fn custom_endpoint() -> anyhow::Result<()> {
+        let bucket = S3Store::connect_bucket(&config("http://127.0.0.1:9000", true))?;
+        assert_eq!(bucket.url(), "http://127.0.0.1:9000/abof-data");
+
+        let bucket = S3Store::connect_bucket(&config("http://127.0.0.1:9000", false))?;
+        assert_eq!(bucket.url(), "http://abof-data.127.0.0.1:9000");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn load_from_bucket() -> anyhow::Result<()> {
+        let objects = Objects::default();
+        let addr = start_server(objects.clone());
+
+        let store = S3Store::new(config(&format!("http://{}", addr), true))?;
+        let service = AbOptimisationService::new(ExperimentStore::new(Box::new(store)));
+
+        write_app(&service.experiment_store, ExperimentState::Running).await?;
+        assert!(objects.lock().contains_key("data/experiments/a1.p1.e1.experiment.data.json"));
+
+        service.load_data().await?;
+        assert_eq!(visit_experiment(&service, |experiment| experiment.version)?, 1);
+
+        let mut batch = service.experiment_store.batch();
+        batch.delete_audience_list_data("a1", "p1", "l2");
+        batch.commit().await?;
+
+        service.load_data().await?;
+        assert!(service.visit_audience_list("a1", "p1", "l2", &epoch::pin(), |_| Ok(())).is_err());
+        assert!(service.visit_audience_list("a1", "p1", "l1", &epoch::pin(), |_| Ok(())).is_ok());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context};
+use async_trait::async_trait;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::experiment_store::store::{ObjectVersion, Store, StoreOp};
+use crate::settings;
+
+/// Root of the object paths, which are mapped to `{root}/{table}/{key}`.
+const ROOT_PATH: &str = "sqlite";
+
+const TABLES: [&str; 4] = ["apps", "projects", "experiments", "audience_lists"];
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS apps (key TEXT PRIMARY KEY, data TEXT NOT NULL, version INTEGER NOT NULL, modification_time INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS projects (key TEXT PRIMARY KEY, data TEXT NOT NULL, version INTEGER NOT NULL, modification_time INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS experiments (key TEXT PRIMARY KEY, data TEXT NOT NULL, version INTEGER NOT NULL, modification_time INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS audience_lists (key TEXT PRIMARY KEY, data TEXT NOT NULL, version INTEGER NOT NULL, modification_time INTEGER NOT NULL);
+
+CREATE TABLE IF NOT EXISTS versions (
+    kind TEXT NOT NULL,
+    key TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    modification_time INTEGER NOT NULL,
+    PRIMARY KEY (kind, key, version)
+);
+
+CREATE TABLE IF NOT EXISTS audit (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time INTEGER NOT NULL,
+    action TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    key TEXT NOT NULL,
+    version INTEGER
+);
+CREATE INDEX IF NOT EXISTS audit_kind_key ON audit (kind, key);
+";
+
+/// Store backed by an embedded SQLite database.
+///
+/// Each kind of data has its own table, with a version incremented on every write. All the written versions are kept
+/// in `versions` table, and every write and delete is recorded in `audit` table. A batch of writes is applied in a
+/// single transaction. Database calls are blocking, hence run on the blocking thread pool.
+pub struct SqliteStore {
+    config: settings::SqliteStoreConfig,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn new(config: settings::SqliteStoreConfig) -> anyhow::Result<SqliteStore> {
+        let connection = Connection::open(&config.path).with_context(|| format!("Error in opening sqlite db: {}", config.path))?;
+
+        connection
+            .execute_batch(SCHEMA)
+            .with_context(|| format!("Error in creating schema in sqlite db: {}", config.path))?;
+
+        Ok(SqliteStore {
+            config,
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Runs the db call with the connection on the blocking thread pool, so that it doesn't hold up an async worker for
+    /// the whole transaction and its sync.
+    async fn with_connection<F, R>(&self, call: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut Connection) -> anyhow::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let connection = self.connection.clone();
+
+        tokio::task::spawn_blocking(move || call(&mut connection.lock()))
+            .await
+            .with_context(|| "Error in running sqlite call")?
+    }
+
+    fn write_in(tx: &Transaction, data: &[u8], path: &str) -> anyhow::Result<()> {
+        let (table, key) = Self::table_and_key(path)?;
+        let data = std::str::from_utf8(data).with_context(|| format!("Error in writing non utf-8 data to path: {}", path))?;
+        let now = chrono::Local::now().timestamp_millis();
+
+        tx.execute(
+            &format!(
+                "INSERT INTO {table} (key, data, version, modification_time) VALUES (?1, ?2, 1, ?3)
+                 ON CONFLICT (key) DO UPDATE SET data = excluded.data, version = {table}.version + 1, modification_time = excluded.modification_time",
+                table = table
+            ),
+            params![key, data, now],
+        )
+        .with_context(|| format!("Error in writing data to path: {}", path))?;
+
+        let version: i64 = tx.query_row(&format!("SELECT version FROM {} WHERE key = ?1", table), params![key], |row| row.get(0))?;
+
+        tx.execute(
+            "INSERT INTO versions (kind, key, version, data, modification_time) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![table, key, version, data, now],
+        )?;
+
+        Self::audit(tx, "write", table, key, Some(version))
+    }
+
+    fn delete_in(tx: &Transaction, path: &str) -> anyhow::Result<()> {
+        let (table, key) = Self::table_and_key(path)?;
+
+        let version: Option<i64> = tx
+            .query_row(&format!("SELECT version FROM {} WHERE key = ?1", table), params![key], |row| row.get(0))
+            .optional()?;
+
+        // already deleted, may be by another replica
+        if version.is_none() {
+            return Ok(());
+        }
+
+        tx.execute(&format!("DELETE FROM {} WHERE key = ?1", table), params![key])
+            .with_context(|| format!("Error in deleting path: {}", path))?;
+
+        Self::audit(tx, "delete", table, key, version)
+    }
+
+    fn audit(tx: &Transaction, action: &str, table: &str, key: &str, version: Option<i64>) -> anyhow::Result<()> {
+        tx.execute(
+            "INSERT INTO audit (time, action, kind, key, version) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![chrono::Local::now().timestamp_millis(), action, table, key, version],
+        )?;
+
+        Ok(())
+    }
+
+    /// Table for path `{root}/{table}`.
+    fn table(path: &str) -> anyhow::Result<&'static str> {
+        let table = path
+            .strip_prefix(ROOT_PATH)
+            .and_then(|path| path.strip_prefix('/'))
+            .ok_or_else(|| anyhow!("Path: {} is not in sqlite store", path))?;
+
+        TABLES
+            .iter()
+            .find(|name| **name == table)
+            .copied()
+            .ok_or_else(|| anyhow!("No table for path: {}", path))
+    }
+
+    /// Table and key for path `{root}/{table}/{key}`.
+    fn table_and_key(path: &str) -> anyhow::Result<(&'static str, &str)> {
+        let index = path.rfind('/').ok_or_else(|| anyhow!("No key in path: {}", path))?;
+
+        Ok((Self::table(&path[..index])?, &path[index + 1..]))
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    fn path(&self) -> &str {
+        ROOT_PATH
+    }
+
+    fn refresh_rate(&self) -> u64 {
+        self.config.refresh_rate
+    }
+
+    async fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>> {
+        let path = path.to_string_lossy().to_string();
+
+        self.with_connection(move |connection| {
+            let (table, key) = Self::table_and_key(&path)?;
+
+            let data: String = connection
+                .query_row(&format!("SELECT data FROM {} WHERE key = ?1", table), params![key], |row| row.get(0))
+                .with_context(|| format!("Error in reading data from path: {}", path))?;
+
+            Ok(data.into_bytes())
+        })
+        .await
+    }
+
+    async fn write(&self, path: &str, data: Vec<u8>) -> anyhow::Result<()> {
+        let path = path.to_string();
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+
+            Self::write_in(&tx, &data, &path)?;
+
+            tx.commit().with_context(|| format!("Error in committing write of: {}", path))
+        })
+        .await
+    }
+
+    async fn delete(&self, path: &str) -> anyhow::Result<()> {
+        let path = path.to_string();
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+
+            Self::delete_in(&tx, &path)?;
+
+            tx.commit().with_context(|| format!("Error in committing delete of: {}", path))
+        })
+        .await
+    }
+
+    async fn list(&self, path: &str) -> anyhow::Result<Vec<(PathBuf, ObjectVersion)>> {
+        let table = Self::table(path)?;
+
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(&format!("SELECT key, version, modification_time FROM {}", table))?;
+
+            let rows = statement.query_map(params![], |row| {
+                let key: String = row.get(0)?;
+                let version: i64 = row.get(1)?;
+
+                let object_path = PathBuf::from(format!("{}/{}/{}", ROOT_PATH, table, key));
+                let version = ObjectVersion {
+                    modification_time: row.get(2)?,
+                    tag: version.to_string(),
+                };
+
+                Ok((object_path, version))
+            })?;
+
+            let objects = rows.collect::<Result<Vec<_>, _>>()?;
+
+            Ok(objects)
+        })
+        .await
+    }
+
+    /// Applies all the ops in a single transaction.
+    async fn apply_batch(&self, ops: Vec<StoreOp>) -> anyhow::Result<()> {
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+
+            for op in ops.iter() {
+                match op {
+                    StoreOp::Write { path, data } => Self::write_in(&tx, data, path)?,
+                    StoreOp::Delete { path } => Self::delete_in(&tx, path)?,
+                }
+            }
+
+            tx.commit().with_context(|| format!("Error in committing batch of {} ops", ops.len()))
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::experiment_store::store::{Store, StoreOp};
+    use crate::settings;
+
+    use super::SqliteStore;
+
+    fn store() -> SqliteStore {
+        SqliteStore::new(settings::SqliteStoreConfig {
+            path: ":memory:".to_string(),
+            refresh_rate: 10,
+        })
+        .unwrap()
+    }
+
+    fn write(path: &str, data: &str) -> StoreOp {
+        StoreOp::Write {
+            path: path.to_string(),
+            data: data.as_bytes().to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_is_atomic() {
+        let store = store();
+
+        // failing op rolls back the ops before it
+        let ops = vec![
+            write("sqlite/apps/a1", "{}"),
+            write("sqlite/projects/a1.p1", "{}"),
+            write("sqlite/unknown/x", "{}"),
+        ];
+        assert!(store.apply_batch(ops).await.is_err());
+
+        assert!(store.list("sqlite/apps").await.unwrap().is_empty());
+        assert!(store.list("sqlite/projects").await.unwrap().is_empty());
+        assert!(store.read(Path::new("sqlite/apps/a1")).await.is_err());
+
+        let ops = vec![write("sqlite/apps/a1", "{}"), write("sqlite/projects/a1.p1", "{}")];
+        store.apply_batch(ops).await.unwrap();
+
+        assert_eq!(store.read(Path::new("sqlite/apps/a1")).await.unwrap(), b"{}");
+        assert_eq!(store.list("sqlite/projects").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn versions() {
+        let store = store();
+
+        store.write("sqlite/apps/a1", b"{\"v\": 1}".to_vec()).await.unwrap();
+        store.write("sqlite/apps/a1", b"{\"v\": 2}".to_vec()).await.unwrap();
+
+        let objects = store.list("sqlite/apps").await.unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].0, Path::new("sqlite/apps/a1"));
+        assert_eq!(objects[0].1.tag, "2");
+        assert_eq!(store.read(Path::new("sqlite/apps/a1")).await.unwrap(), b"{\"v\": 2}");
+
+        let ops = vec![
+            StoreOp::Delete {
+                path: "sqlite/apps/a1".to_string(),
+            },
+            // deleting a missing object is not an error
+            StoreOp::Delete {
+                path: "sqlite/apps/a2".to_string(),
+            },
+        ];
+        store.apply_batch(ops).await.unwrap();
+        assert!(store.list("sqlite/apps").await.unwrap().is_empty());
+    }
+}
@@ -1,186 +1,126 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::Context;
+use async_trait::async_trait;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
-use s3::creds::Credentials;
-use s3::Bucket;
-use serde::Serialize;
-use walkdir::WalkDir;
+use tokio::sync::Notify;
 
+use crate::experiment_store::local_store::LocalStore;
+use crate::experiment_store::memory_store::MemoryStore;
+use crate::experiment_store::s3_store::S3Store;
+use crate::experiment_store::sqlite_store::SqliteStore;
 use crate::settings;
 
-pub enum Store {
-    Local(LocalStore),
-    S3(S3Store),
-}
-
-impl Store {
-    pub fn new_local_store(local_store_config: settings::LocalStoreConfig) -> anyhow::Result<Store> {
-        Ok(Store::Local(LocalStore { path: local_store_config.path }))
-    }
+/// Version of a stored object, as seen while listing the store.
+///
+/// Used on refresh to skip fetching the objects that haven't changed since the last load.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectVersion {
+    /// last modification time in millis
+    pub modification_time: i64,
 
-    pub fn new_s3_store(s3_store_config: settings::S3StoreConfig) -> anyhow::Result<Store> {
-        let store = S3Store::new(s3_store_config)?;
-        Ok(Store::S3(store))
-    }
+    /// any value that changes with every write of the object, e.g. ETag of S3 object
+    pub tag: String,
+}
 
-    pub(crate) fn path(&self) -> &str {
-        match self {
-            Store::Local(store) => &store.path,
-            Store::S3(store) => &store.path,
-        }
-    }
+/// Single write or delete of an object, as part of a batch.
+pub enum StoreOp {
+    Write { path: String, data: Vec<u8> },
+    Delete { path: String },
+}
 
-    pub(crate) fn write_data<T>(&self, data: &T, path: &str) -> anyhow::Result<()>
-    where
-        T: Serialize,
-    {
-        match self {
-            Store::Local(store) => store.write_data(data, path),
-            Store::S3(store) => store.write_data(data, path),
-        }
-    }
+/// Backend keeping the serialised objects, addressed by their paths under the root path of the store.
+///
+/// Objects are stored as is, parsing them is left to the caller.
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Root path, under which all the objects of the store are kept.
+    fn path(&self) -> &str;
 
-    pub(crate) fn visit_path<F>(&self, path: &str, visitor: F) -> anyhow::Result<()>
-    where
-        F: Fn(&Path, i64) -> anyhow::Result<()>,
-    {
-        match self {
-            Store::Local(store) => store.visit_path(path, visitor),
-            Store::S3(store) => store.visit_path(path, visitor),
-        }
-    }
+    /// Interval in seconds for polling the store for changes.
+    fn refresh_rate(&self) -> u64;
 
-    pub(crate) fn read_data<T>(&self, path: &Path) -> anyhow::Result<T>
-    where
-        T: serde::de::DeserializeOwned,
-    {
-        match self {
-            Store::Local(store) => store.read_data(path),
-            Store::S3(store) => store.read_data(path),
-        }
+    /// Notifies on changes in the store, if store supports watching.
+    fn watch(&self, _changed: Arc<Notify>) -> anyhow::Result<()> {
+        Ok(())
     }
-}
 
-pub struct LocalStore {
-    path: String,
-}
+    async fn read(&self, path: &Path) -> anyhow::Result<Vec<u8>>;
 
-impl LocalStore {
-    fn write_data<T>(&self, data: &T, path: &str) -> anyhow::Result<()>
-    where
-        T: Serialize,
-    {
-        let file = File::create(path)?;
-        let mut writer = BufWriter::new(file);
+    async fn write(&self, path: &str, data: Vec<u8>) -> anyhow::Result<()>;
 
-        // Write the JSON contents to the file.
-        serde_json::to_writer(&mut writer, data).with_context(|| format!("Error in writing data to file: {}", path))?;
+    /// Deleting a missing object is not an error, as it may have been deleted by another replica.
+    async fn delete(&self, path: &str) -> anyhow::Result<()>;
 
-        writer.flush()?;
+    /// Lists all the objects under the path, along with their versions.
+    async fn list(&self, path: &str) -> anyhow::Result<Vec<(PathBuf, ObjectVersion)>>;
 
+    /// Moves aside a corrupt object, so that it can be inspected and isn't read again.
+    ///
+    /// By default the object is left as is, its version stays unchanged until rewritten, so it isn't read again.
+    fn quarantine(&self, _path: &Path) -> anyhow::Result<()> {
         Ok(())
     }
 
-    fn visit_path<F>(&self, path: &str, visitor: F) -> anyhow::Result<()>
-    where
-        F: Fn(&Path, i64) -> anyhow::Result<()>,
-    {
-        for entry in WalkDir::new(path).follow_links(true).into_iter().filter_map(|e| e.ok()) {
-            visitor(entry.path(), 0)?;
+    /// Applies the ops in order, by default one by one stopping at the first failing op, and leaving the ones before it
+    /// applied. Stores supporting transactions apply them atomically.
+    async fn apply_batch(&self, ops: Vec<StoreOp>) -> anyhow::Result<()> {
+        for op in ops {
+            match op {
+                StoreOp::Write { path, data } => self.write(&path, data).await?,
+                StoreOp::Delete { path } => self.delete(&path).await?,
+            }
         }
 
         Ok(())
     }
+}
 
-    fn read_data<T>(&self, path: &Path) -> anyhow::Result<T>
-    where
-        T: serde::de::DeserializeOwned,
-    {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
+/// Creates the store, reading its config from the settings.
+pub type StoreFactory = Box<dyn Fn() -> anyhow::Result<Box<dyn Store>> + Send + Sync>;
 
-        serde_json::from_reader(reader).with_context(|| format!("Error in parsing data from path: {}", path.to_string_lossy()))
-    }
+/// Stores by their `store_kind` name.
+pub struct StoreRegistry {
+    factories: HashMap<String, StoreFactory>,
 }
 
-pub struct S3Store {
-    config: settings::S3StoreConfig,
-    path: String,
-}
+impl Default for StoreRegistry {
+    fn default() -> Self {
+        let mut registry = StoreRegistry { factories: HashMap::new() };
 
-impl S3Store {
-    pub fn new(config: settings::S3StoreConfig) -> anyhow::Result<S3Store> {
-        let path = config.path.to_string();
+        registry.register("local", || {
+            let config = settings::local_store_config();
+            config.validate()?;
 
-        Ok(S3Store { config, path })
-    }
+            Ok(Box::new(LocalStore::new(config)))
+        });
+        registry.register("s3", || Ok(Box::new(S3Store::new(settings::s3_store_config())?)));
+        registry.register("sqlite", || Ok(Box::new(SqliteStore::new(settings::sqlite_store_config())?)));
+        registry.register("memory", || Ok(Box::new(MemoryStore::new("data"))));
 
-    fn connect_bucket(&self) -> anyhow::Result<Bucket> {
-        let region = self.config.region.parse()?;
-        let credentials = Credentials::default()?;
-        Bucket::new(&self.config.bucket, region, credentials).with_context(|| format!("Error in connecting to bucket for config: {:?}", self.config))
+        registry
     }
+}
 
-    fn write_data<T>(&self, data: &T, path: &str) -> anyhow::Result<()>
+impl StoreRegistry {
+    /// Registers the store for the kind, replacing any store registered for it already.
+    pub fn register<F>(&mut self, store_kind: &str, factory: F)
     where
-        T: Serialize,
+        F: Fn() -> anyhow::Result<Box<dyn Store>> + Send + Sync + 'static,
     {
-        let buffer = serde_json::to_vec(data).with_context(|| format!("Error in writing data to buffer: {}", path))?;
-        self.connect_bucket()?
-            .put_object_blocking(path, &buffer)
-            .with_context(|| format!("Error in writing data to S3 file: {}", path))?;
-
-        Ok(())
+        self.factories.insert(store_kind.to_string(), Box::new(factory));
     }
 
-    fn visit_path<F>(&self, path: &str, visitor: F) -> anyhow::Result<()>
-    where
-        F: Fn(&Path, i64) -> anyhow::Result<()>,
-    {
-        let mut consumed = false;
-        let mut continuation_token = None;
-
-        // info!("Visiting path: {:?}", path);
+    pub fn create(&self, store_kind: &str) -> anyhow::Result<Box<dyn Store>> {
+        let factory = self
+            .factories
+            .get(store_kind)
+            .ok_or_else(|| anyhow::anyhow!("Unknown store kind: {}", store_kind))?;
 
-        while !consumed {
-            let (list_results, _) = self
-                .connect_bucket()?
-                .list_page_blocking(path.to_string(), None, continuation_token.clone(), None, Some(100))
-                .with_context(|| format!("Error in listing objects in path: {}", path))?;
-
-            // info!("Found list results: {:?}", list_results);
-
-            for entry in list_results.contents {
-                // info!("Found entry: {:?}", entry);
-                let object_path = Path::new(&entry.key);
-                let modified_time =
-                    chrono::DateTime::parse_from_rfc3339(&entry.last_modified).with_context(|| format!("Error in parsing last_modified time"))?;
-
-                visitor(object_path, modified_time.timestamp_millis())?;
-            }
-
-            consumed = !list_results.is_truncated;
-            if !consumed {
-                continuation_token = list_results.next_continuation_token;
-            }
-        }
-
-        Ok(())
-    }
-
-    fn read_data<T>(&self, path: &Path) -> anyhow::Result<T>
-    where
-        T: serde::de::DeserializeOwned,
-    {
-        let (buffer, _) = self
-            .connect_bucket()?
-            .get_object_blocking(path.to_string_lossy())
-            .with_context(|| format!("Error in reading data from path: {}", path.to_string_lossy()))?;
+        info!("Configuring {} store", store_kind);
 
-        serde_json::from_slice(&buffer).with_context(|| format!("Error in parsing data from path: {}", path.to_string_lossy()))
+        factory()
     }
 }
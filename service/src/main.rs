@@ -22,6 +22,8 @@ extern crate pest_derive;
 // #[macro_use]
 pub extern crate serde_json;
 
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::path::Path;
 
 use anyhow::Context;
@@ -30,9 +32,11 @@ use log::{debug, error, info, warn};
 use log4rs;
 
 // crate specific imports
+use crate::core::{AppBundle, ImportMode};
+use crate::experiment_store::{ExperimentStore, StoreRegistry};
 use crate::server::{start_http_server, ServiceBuilder};
 use crate::service::{AbOptimisationService, AbOptimisationServiceBuilder, AbOptimisationServiceDaemon};
-use crate::settings::load_global_config;
+use crate::settings::{load_global_config, LocalStoreConfig, SqliteStoreConfig};
 
 mod api;
 mod core;
@@ -46,7 +50,7 @@ static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<(), anyhow::Error> {
-    run_main(AbOptimisationServiceBuilder {}).await
+    run_main(AbOptimisationServiceBuilder::default()).await
 }
 
 pub async fn run_main<AppBuilder>(app_builder: AppBuilder) -> anyhow::Result<()>
@@ -77,7 +81,72 @@ where
 
             start_http_server(addr, app_builder).await
         }
+        Some("export") => {
+            let arg_match = matches.subcommand_matches("export").unwrap();
+            let app_id: &str = &value_t!(arg_match, "app_id", String).unwrap();
+            let output: &str = &value_t!(arg_match, "output", String).unwrap();
+
+            let service = offline_service(arg_match).await?;
+            let bundle = service.export_app_bundle(app_id)?;
+
+            let file = File::create(output).with_context(|| format!("Error in creating bundle file: {}", output))?;
+            serde_json::to_writer_pretty(BufWriter::new(file), &bundle).with_context(|| format!("Error in writing bundle file: {}", output))?;
+
+            info!("Exported app:{} to {}", app_id, output);
+            Ok(())
+        }
+        Some("import") => {
+            let arg_match = matches.subcommand_matches("import").unwrap();
+            let input: &str = &value_t!(arg_match, "input", String).unwrap();
+            let mode = ImportMode::parse(&value_t!(arg_match, "mode", String).unwrap())?;
+
+            let service = offline_service(arg_match).await?;
+
+            let file = File::open(input).with_context(|| format!("Error in opening bundle file: {}", input))?;
+            let bundle: AppBundle = serde_json::from_reader(BufReader::new(file)).with_context(|| format!("Error in reading bundle file: {}", input))?;
+            let response = service.import_app_bundle(bundle, mode).await?;
+
+            info!("Imported {} as app:{}", input, response.app_id);
+            Ok(())
+        }
+        Some("migrate") => {
+            let arg_match = matches.subcommand_matches("migrate").unwrap();
+            let from_path: &str = &value_t!(arg_match, "from_path", String).unwrap();
+            let sqlite_path: &str = &value_t!(arg_match, "sqlite_path", String).unwrap();
+
+            let source = ExperimentStore::new_local_store(LocalStoreConfig {
+                path: from_path.to_string(),
+                refresh_rate: 0,
+                watch: false,
+            })?;
+            let target = ExperimentStore::new_sqlite_store(SqliteStoreConfig {
+                path: sqlite_path.to_string(),
+                refresh_rate: 0,
+            })?;
+
+            let count = source.copy_to(&target).await?;
+
+            info!("Migrated {} objects from {} to {}", count, from_path, sqlite_path);
+            Ok(())
+        }
         None => anyhow::bail!("No sub command match"),
         _ => anyhow::bail!("Some other sub command was used"),
     }
 }
+
+/// Service over the store, without http server and refresh daemon, for offline commands.
+async fn offline_service(arg_match: &clap::ArgMatches) -> anyhow::Result<AbOptimisationService> {
+    let experiment_store = match arg_match.value_of("store_path") {
+        Some(path) => ExperimentStore::new_local_store(LocalStoreConfig {
+            path: path.to_string(),
+            refresh_rate: 0,
+            watch: false,
+        })?,
+        None => ExperimentStore::configured(&StoreRegistry::default())?,
+    };
+
+    let service = AbOptimisationService::new(experiment_store);
+    service.load_data().await?;
+
+    Ok(service)
+}
@@ -2,165 +2,150 @@ use anyhow::Context;
 use crossbeam_epoch as epoch;
 use crossbeam_skiplist::SkipList;
 use http::{header, HeaderValue};
+use hyper::body::HttpBody;
 use hyper::Body;
-use log::{info, log_enabled, Level};
+use log::{log_enabled, Level};
 use metered::{measure, HitCount};
-use prometheus::{Encoder, Opts, Registry};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
 use serde::ser::{SerializeMap, SerializeSeq};
 use serde::{Serialize, Serializer};
 
 use crate::server::access_logger::metrics::CounterIncrementer;
 use crate::server::{HttpResponse, HttpResult, HttpRoute};
+use crate::settings::{self, AccessLogFormat};
 
+use super::access_record::{AccessRecord, LoggedBody};
 use super::metrics::ErrorCounter;
 use super::response_time::ResponseTime;
 
 lazy_static! {
+    /// registry of all the prometheus metrics, served at `/metrics`
+    pub static ref METRICS_REGISTRY: Registry = Registry::new();
     pub static ref ACCESS_LOGGER: AccessLogger = AccessLogger::new();
 }
 
 pub struct AccessLogger {
     registry: ApiMetricsRegistry,
+
+    /// labels: path, method, code
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
 }
 
 impl AccessLogger {
     pub fn new() -> AccessLogger {
+        let labels = ["path", "method", "code"];
+
+        let requests = IntCounterVec::new(Opts::new("http_requests_total", "Number of http requests"), &labels).unwrap();
+        METRICS_REGISTRY.register(Box::new(requests.clone())).unwrap();
+
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Response time of http requests in seconds"),
+            &labels,
+        )
+        .unwrap();
+        METRICS_REGISTRY.register(Box::new(request_duration.clone())).unwrap();
+
         AccessLogger {
             registry: ApiMetricsRegistry {
                 metrics: SkipList::new(epoch::default_collector().clone()),
             },
+            requests,
+            request_duration,
         }
     }
 
-    pub fn log_access(&self, route: &HttpRoute<'_>, response: &HttpResult) {
-        let elapsed_time = route.req_instant.elapsed();
+    /// Records the metrics of the response, and logs its access once the response body is sent, to log the bytes sent.
+    pub fn log_access(&self, route: &HttpRoute<'_>, response: HttpResult) -> HttpResult {
+        self.record_metrics(route, &response);
 
-        if log_enabled!(target: "access_log", Level::Info) {
-            if let Ok(response) = response {
-                let time_taken_in_millis = (elapsed_time.as_nanos() as f64) / 1_000_000.0;
-
-                let response_status = response.status().as_u16();
-                let query = route.query.unwrap_or_else(|| "");
-
-                // RemoteAddr
-                // RequestTime
-                // ResponseStatus
-                // TimeInMillis
-                // ResponseContentLength
-                // ResponseContentType
-                // ResponseContentEncoding
-                // URLPath
-                // QueryPath
-                // RequestContentLength
-                // RequestContentType
-                // RequestContentEncoding
-                // RequestAcceptEncoding
-                info!(target: "access_log", "{} {} {} {:.6} {:?} {:?} {:?} {} {:?} {:?} {:?} {:?} {:?}",
-                      route.remote_addr.ip().to_string(),
-                      route.req_time.to_rfc3339(),
-                      response_status,
-                      time_taken_in_millis,
-                      response.headers().get(header::CONTENT_LENGTH), // TODO: have mechanism to properly get response size
-                      response.headers().get(header::CONTENT_TYPE).unwrap_or_else(|| &EMPTY_HEADER_VALUE),
-                      response.headers().get(header::CONTENT_ENCODING).unwrap_or_else(|| &EMPTY_HEADER_VALUE),
-                      route.path,
-                      query,
-                      route.req.headers().get(header::CONTENT_LENGTH).unwrap_or_else(|| &EMPTY_HEADER_VALUE), // TODO: have mechanism to properly get request size
-                      route.req.headers().get(header::CONTENT_TYPE).unwrap_or_else(|| &EMPTY_HEADER_VALUE),
-                      route.req.headers().get(header::CONTENT_ENCODING).unwrap_or_else(|| &EMPTY_HEADER_VALUE),
-                      route.req.headers().get(header::ACCEPT_ENCODING).unwrap_or_else(|| &EMPTY_HEADER_VALUE),
-                );
-            }
+        if !log_enabled!(target: "access_log", Level::Info) {
+            return response;
         }
 
-        if log_enabled!(target: "metrics_log", Level::Debug) {
-            if let Ok(response) = response {
-                let path = route.metric_path.unwrap_or_else(|| route.path);
-                let code = response.status().as_u16();
-                let metric_label = format!("{}/{}/{}", path, route.method, code);
-
-                let guard = &epoch::pin();
-                let api_metrics_entry = self.registry.metrics.get_or_insert_with(
-                    metric_label,
-                    || ApiMetrics {
-                        path: path.to_string(),
-                        method: route.method.to_string(),
-                        code,
-                        hits: Default::default(),
-                        errors: Default::default(),
-                        response_time: Default::default(),
-                    },
-                    guard,
-                );
-
-                let api_metrics: &ApiMetrics = api_metrics_entry.value();
-
-                api_metrics.response_time.increment_time_by_duration(elapsed_time);
-                let hits = &api_metrics.hits;
-                measure!(hits, {});
-
-                if !response.status().is_success() {
-                    api_metrics.errors.increment_by(1);
+        match response {
+            Ok(response) => {
+                let record = AccessRecord::new(route, Some(&response));
+
+                match HttpBody::size_hint(response.body()).exact() {
+                    Some(size) => {
+                        record.log(size);
+                        Ok(response)
+                    }
+                    None => Ok(response.map(|body| LoggedBody::wrap(body, record))),
+                }
+            }
+            Err(err) => {
+                // only json logs carry the error, text logs stay as they were, of built responses only
+                if settings::access_log_format() == AccessLogFormat::Json {
+                    route.add_log_field("error", &err.to_string());
+                    AccessRecord::new(route, None).log(0);
                 }
 
-                api_metrics_entry.release(guard);
+                Err(err)
             }
         }
     }
 
-    // labels: code, method, path ==> hits, errors, response time
-    pub async fn get_api_metrics_for_prometheus(&self, route: &HttpRoute<'_>) -> HttpResult {
-        let registry = Registry::new();
-
-        // register 3 counters vector... hits, errors, quantiles... label being ["path", "method", "code"]
-        let labels = vec!["path", "method", "code"];
-        let hits_counter_opts = Opts::new("hits", "hits counter");
-        let hits_counter = prometheus::CounterVec::new(hits_counter_opts, &labels).with_context(|| format!("Error in building hits counter"))?;
-        registry
-            .register(Box::new(hits_counter.clone()))
-            .with_context(|| format!("Error in registering hits counter"))?;
-
-        let errors_counter_opts = Opts::new("errors", "errors counter");
-        let errors_counter = prometheus::CounterVec::new(errors_counter_opts, &labels).with_context(|| format!("Error in building errors counter"))?;
-        registry
-            .register(Box::new(errors_counter.clone()))
-            .with_context(|| format!("Error in registering errors counter"))?;
-
-        let quantile_counter_opts = Opts::new("quantiles", "quantiles counter");
-        let labels = vec!["path", "method", "code", "quantile"];
-        let quantiles_counter = prometheus::CounterVec::new(quantile_counter_opts, &labels).with_context(|| format!("Error in building quantiles counter"))?;
-        registry
-            .register(Box::new(quantiles_counter.clone()))
-            .with_context(|| format!("Error in registering quantiles counter"))?;
-
-        // iterate over registry and serialize
-        let guard = &epoch::pin();
-        for entry in self.registry.metrics.iter(guard) {
-            let api_metrics: &ApiMetrics = entry.value();
-
-            let code = format!("{}", api_metrics.code);
-            hits_counter
-                .with_label_values(&[&api_metrics.path, &api_metrics.method, &code])
-                .inc_by(api_metrics.hits.0.get() as f64);
-
-            errors_counter
-                .with_label_values(&[&api_metrics.path, &api_metrics.method, &code])
-                .inc_by(api_metrics.errors.0.get() as f64);
+    fn record_metrics(&self, route: &HttpRoute<'_>, response: &HttpResult) {
+        let elapsed_time = route.req_instant.elapsed();
 
-            let percentile_map = api_metrics.response_time.get_percentile_map()?;
-            for (metric, value) in percentile_map {
-                quantiles_counter
-                    .with_label_values(&[&api_metrics.path, &api_metrics.method, &code, &metric])
-                    .inc_by(value as f64);
+        if let Ok(response) = response {
+            // path is templated, without the ids in it, so that the number of metrics stays bounded
+            let path = route.metric_path.unwrap_or_else(|| route.path);
+            let code = response.status().as_u16();
+            let method = route.method.as_str();
+            let code_label = code.to_string();
+
+            self.requests.with_label_values(&[path, method, &code_label]).inc();
+            self.request_duration
+                .with_label_values(&[path, method, &code_label])
+                .observe(elapsed_time.as_secs_f64());
+
+            let metric_label = format!("{}/{}/{}", path, method, code);
+
+            let guard = &epoch::pin();
+            let api_metrics_entry = self.registry.metrics.get_or_insert_with(
+                metric_label,
+                || ApiMetrics {
+                    path: path.to_string(),
+                    method: method.to_string(),
+                    code,
+                    hits: Default::default(),
+                    errors: Default::default(),
+                    response_time: Default::default(),
+                },
+                guard,
+            );
+
+            let api_metrics: &ApiMetrics = api_metrics_entry.value();
+
+            api_metrics.response_time.increment_time_by_duration(elapsed_time);
+            let hits = &api_metrics.hits;
+            measure!(hits, {});
+
+            if !response.status().is_success() {
+                api_metrics.errors.increment_by(1);
             }
+
+            api_metrics_entry.release(guard);
         }
+    }
 
-        let metric_families = registry.gather();
+    /// All the metrics in the registry, in prometheus text format.
+    pub async fn get_api_metrics_for_prometheus(&self, route: &HttpRoute<'_>) -> HttpResult {
+        let metric_families = METRICS_REGISTRY.gather();
         let mut buffer = vec![];
         let encoder = prometheus::TextEncoder::new();
         encoder.encode(&metric_families, &mut buffer).with_context(|| "Error in encoding prometheus")?;
 
-        HttpResponse::ok(route, Body::from(buffer))
+        let mut response = HttpResponse::ok(route, Body::from(buffer))?;
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(encoder.format_type()).with_context(|| "Error in building content type header")?,
+        );
+
+        Ok(response)
     }
 
     pub async fn get_api_metrics_as_json(&self, route: &HttpRoute<'_>) -> HttpResult {
@@ -217,6 +202,46 @@ impl Serialize for ApiMetricsRegistry {
     }
 }
 
-lazy_static! {
-    static ref EMPTY_HEADER_VALUE: HeaderValue = HeaderValue::from_static("");
+#[cfg(test)]
+mod tests {
+    use crossbeam_epoch as epoch;
+    use hyper::{Body, Request};
+
+    use crate::core::fixtures::{empty_service, route};
+    use crate::server::access_logger::ACCESS_LOGGER;
+    use crate::server::{HttpResponse, Service};
+
+    #[test]
+    fn metrics_by_templated_path() -> anyhow::Result<()> {
+        let service = empty_service();
+        let template = "/api/experiments/{app_id}/{project_id}/{experiment_id}/archive";
+
+        for n in 0..10 {
+            let experiment_id = format!("e{}", n);
+            let req = Request::builder()
+                .uri(format!("/api/experiments/a1/p1/{}/archive", experiment_id))
+                .body(Body::empty())?;
+            let mut route = route(&req);
+            route.metric_path = service.metric_path(&["experiments", "a1", "p1", experiment_id.as_str(), "archive"]);
+
+            ACCESS_LOGGER.record_metrics(&route, &HttpResponse::str(&route, "SUCCESS"));
+        }
+
+        // one metric for all the experiments, and none by the ids in path
+        let guard = &epoch::pin();
+        let labels: Vec<String> = ACCESS_LOGGER
+            .registry
+            .metrics
+            .iter(guard)
+            .map(|entry| entry.key().to_string())
+            .filter(|label| label.starts_with("/api/experiments/"))
+            .collect();
+        assert_eq!(labels, vec![format!("{}/GET/200", template)]);
+        assert_eq!(ACCESS_LOGGER.requests.with_label_values(&[template, "GET", "200"]).get(), 10);
+
+        // unknown actions aren't templated
+        assert_eq!(service.metric_path(&["experiments", "a1", "p1", "e1", "e2"]), None);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,217 @@
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use http::{header, HeaderMap, HeaderValue, Response};
+use hyper::Body;
+use log::info;
+use serde::Serialize;
+
+use crate::server::HttpRoute;
+use crate::settings::{self, AccessLogFormat};
+
+lazy_static! {
+    static ref EMPTY_HEADER_VALUE: HeaderValue = HeaderValue::from_static("");
+}
+
+/// Access log entry of a request, owned so that it can be logged after the response body is sent.
+pub struct AccessRecord {
+    request_id: String,
+    remote_addr: SocketAddr,
+    req_time: chrono::DateTime<chrono::Local>,
+    time_taken: Duration,
+    method: String,
+    path: String,
+    route: Option<&'static str>,
+    query: Option<String>,
+    status: u16,
+    request_bytes: Arc<AtomicU64>,
+    request_headers: HeaderMap,
+    response_content_type: HeaderValue,
+    response_content_encoding: HeaderValue,
+    fields: Vec<(&'static str, String)>,
+}
+
+#[derive(Serialize)]
+struct JsonAccessLog<'a> {
+    time: String,
+    request_id: &'a str,
+    remote_addr: String,
+    method: &'a str,
+    path: &'a str,
+    route: Option<&'static str>,
+    query: Option<&'a str>,
+    status: u16,
+    time_taken_ms: f64,
+    request_bytes: u64,
+    response_bytes: u64,
+    request_content_type: Option<&'a str>,
+    request_content_encoding: Option<&'a str>,
+    request_accept_encoding: Option<&'a str>,
+    response_content_type: Option<&'a str>,
+    response_content_encoding: Option<&'a str>,
+    user_agent: Option<&'a str>,
+
+    #[serde(flatten)]
+    fields: std::collections::BTreeMap<&'static str, &'a str>,
+}
+
+impl AccessRecord {
+    /// Record of the response, or of a failure in building it, taken as internal server error.
+    pub fn new(route: &HttpRoute<'_>, response: Option<&Response<Body>>) -> AccessRecord {
+        let header_value = |name| {
+            response
+                .and_then(|response| response.headers().get(name))
+                .unwrap_or(&EMPTY_HEADER_VALUE)
+                .clone()
+        };
+
+        AccessRecord {
+            request_id: route.request_id.to_string(),
+            remote_addr: route.remote_addr,
+            req_time: route.req_time,
+            time_taken: route.req_instant.elapsed(),
+            method: route.method.to_string(),
+            path: route.path.to_string(),
+            route: route.metric_path,
+            query: route.query.map(|query| query.to_string()),
+            status: response.map_or(500, |response| response.status().as_u16()),
+            request_bytes: route.request_bytes.clone(),
+            request_headers: route.req.headers().clone(),
+            response_content_type: header_value(header::CONTENT_TYPE),
+            response_content_encoding: header_value(header::CONTENT_ENCODING),
+            fields: route.log_fields.lock().clone(),
+        }
+    }
+
+    pub fn log(&self, response_bytes: u64) {
+        match settings::access_log_format() {
+            AccessLogFormat::Text => self.log_text(response_bytes),
+            AccessLogFormat::Json => self.log_json(response_bytes),
+        }
+    }
+
+    fn request_header(&self, name: header::HeaderName) -> &HeaderValue {
+        self.request_headers.get(name).unwrap_or(&EMPTY_HEADER_VALUE)
+    }
+
+    fn log_text(&self, response_bytes: u64) {
+        let time_taken_in_millis = (self.time_taken.as_nanos() as f64) / 1_000_000.0;
+
+        // RemoteAddr
+        // RequestTime
+        // ResponseStatus
+        // TimeInMillis
+        // ResponseContentLength
+        // ResponseContentType
+        // ResponseContentEncoding
+        // URLPath
+        // QueryPath
+        // RequestContentLength
+        // RequestContentType
+        // RequestContentEncoding
+        // RequestAcceptEncoding
+        // RequestId
+        info!(target: "access_log", "{} {} {} {:.6} {} {:?} {:?} {} {:?} {} {:?} {:?} {:?} {}",
+              self.remote_addr.ip().to_string(),
+              self.req_time.to_rfc3339(),
+              self.status,
+              time_taken_in_millis,
+              response_bytes,
+              self.response_content_type,
+              self.response_content_encoding,
+              self.path,
+              self.query.as_deref().unwrap_or_default(),
+              self.request_bytes.load(Ordering::Relaxed),
+              self.request_header(header::CONTENT_TYPE),
+              self.request_header(header::CONTENT_ENCODING),
+              self.request_header(header::ACCEPT_ENCODING),
+              self.request_id,
+        );
+    }
+
+    fn log_json(&self, response_bytes: u64) {
+        let header_str = |value: &HeaderValue| value.to_str().ok().filter(|value| !value.is_empty());
+        let request_header_str = |name| self.request_headers.get(name).and_then(|value| value.to_str().ok());
+
+        let entry = JsonAccessLog {
+            time: self.req_time.to_rfc3339(),
+            request_id: &self.request_id,
+            remote_addr: self.remote_addr.ip().to_string(),
+            method: &self.method,
+            path: &self.path,
+            route: self.route,
+            query: self.query.as_deref(),
+            status: self.status,
+            time_taken_ms: (self.time_taken.as_nanos() as f64) / 1_000_000.0,
+            request_bytes: self.request_bytes.load(Ordering::Relaxed),
+            response_bytes,
+            request_content_type: request_header_str(header::CONTENT_TYPE),
+            request_content_encoding: request_header_str(header::CONTENT_ENCODING),
+            request_accept_encoding: request_header_str(header::ACCEPT_ENCODING),
+            response_content_type: header_str(&self.response_content_type),
+            response_content_encoding: header_str(&self.response_content_encoding),
+            user_agent: request_header_str(header::USER_AGENT),
+            fields: self.fields.iter().map(|(name, value)| (*name, value.as_str())).collect(),
+        };
+
+        if let Ok(line) = serde_json::to_string(&entry) {
+            info!(target: "access_log", "{}", line);
+        }
+    }
+}
+
+/// Response body, which logs the access record with the number of bytes sent, once the body is fully sent or dropped
+/// on client going away.
+pub struct LoggedBody {
+    inner: Body,
+    bytes: u64,
+    record: Option<AccessRecord>,
+}
+
+impl LoggedBody {
+    pub fn wrap(body: Body, record: AccessRecord) -> Body {
+        Body::wrap_stream(LoggedBody {
+            inner: body,
+            bytes: 0,
+            record: Some(record),
+        })
+    }
+}
+
+impl Stream for LoggedBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        let poll = this.inner.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            this.bytes += chunk.len() as u64;
+        }
+
+        poll
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if let Some(record) = self.record.take() {
+            record.log(self.bytes);
+        }
+    }
+}
+
+/// Request body, counting the bytes read from it.
+pub fn counted_body(body: Body, counter: Arc<AtomicU64>) -> Body {
+    Body::wrap_stream(body.inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+    }))
+}
@@ -1,5 +1,7 @@
-pub use access_logger::ACCESS_LOGGER;
+pub use access_logger::{ACCESS_LOGGER, METRICS_REGISTRY};
+pub use access_record::counted_body;
 
 mod access_logger;
+mod access_record;
 mod metrics;
 mod response_time;
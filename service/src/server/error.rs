@@ -1,8 +1,11 @@
+use http::StatusCode;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
+use serde::Serialize;
 use thiserror::Error;
+use validator::ValidationErrors;
 
-use crate::server::{HttpResponse, HttpResult};
+use crate::server::{HttpResponse, HttpResult, HttpRoute};
 
 #[derive(Error, Debug)]
 pub enum ApiError {
@@ -15,21 +18,184 @@ pub enum ApiError {
     #[error("Forbidden Error: {0}")]
     Forbidden(String),
 
+    #[error("Conflict Error: {0}")]
+    Conflict(String),
+
     #[error("Bad Request Error: {0}")]
-    BadRequest(#[from] anyhow::Error),
+    BadRequest(anyhow::Error),
+
+    #[error("Validation Error: {0}")]
+    Invalid(ValidationErrors),
+
+    #[error("Invalid Tracking Data Error: {0}")]
+    InvalidTrackingData(anyhow::Error),
 
     #[error("Not Content: {0}")]
     NoContent(String),
 }
 
-impl Into<HttpResult> for ApiError {
-    fn into(self) -> HttpResult {
+/// Errors not mapped to an api error explicitly, e.g. by `?`, are failures in serving the request, and not of the
+/// request itself, which are mapped to bad request where they are raised.
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        ApiError::InternalServerError(error)
+    }
+}
+
+/// Body of the error responses.
+#[derive(Serialize)]
+pub struct ErrorResponse<'a> {
+    /// stable code of the error, for clients to act on
+    pub code: &'static str,
+
+    pub message: String,
+
+    /// errors by field, of invalid data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<&'a ValidationErrors>,
+
+    pub request_id: &'a str,
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::BadRequest(_) | ApiError::Invalid(_) | ApiError::InvalidTrackingData(_) => StatusCode::BAD_REQUEST,
+            ApiError::NoContent(_) => StatusCode::NO_CONTENT,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
         match self {
-            ApiError::InternalServerError(error) => HttpResponse::internal_server_error(error),
-            ApiError::NotFound(reason) => HttpResponse::not_found(&reason),
-            ApiError::Forbidden(reason) => HttpResponse::forbidden(&reason),
-            ApiError::BadRequest(error) => HttpResponse::bad_request(error),
-            ApiError::NoContent(reason) => HttpResponse::no_content(&reason),
+            ApiError::InternalServerError(_) => "internal_error",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Invalid(_) => "validation_failed",
+            ApiError::InvalidTrackingData(_) => "invalid_tracking_data",
+            ApiError::NoContent(_) => "no_content",
+        }
+    }
+
+    /// Response with the error in JSON body, except for internal errors, which are logged and not detailed to the client.
+    ///
+    /// Of bad requests, only the top level message is returned, and the whole chain of causes is logged.
+    pub fn into_response(self, route: &HttpRoute<'_>) -> HttpResult {
+        let message = match &self {
+            ApiError::InternalServerError(error) => {
+                error!("Error in serving request_id: {} ==> {:?}", route.request_id, error);
+                "Error in serving request".to_string()
+            }
+            ApiError::NotFound(reason) | ApiError::Forbidden(reason) | ApiError::Conflict(reason) => reason.to_string(),
+            ApiError::BadRequest(error) | ApiError::InvalidTrackingData(error) => {
+                // only the top level message is returned, as the causes may detail the internals, hence logged instead
+                info!("Bad request_id: {} ==> {:?}", route.request_id, error);
+                error.to_string()
+            }
+            ApiError::Invalid(_) => "Invalid data".to_string(),
+            ApiError::NoContent(_) => return HttpResponse::no_content(),
+        };
+
+        let fields = match &self {
+            ApiError::Invalid(errors) => Some(errors),
+            _ => None,
+        };
+
+        HttpResponse::error(
+            self.status(),
+            &ErrorResponse {
+                code: self.code(),
+                message,
+                fields,
+                request_id: &route.request_id,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use http::StatusCode;
+    use serde_json::json;
+    use validator::{ValidationError, ValidationErrors};
+
+    use crate::core::fixtures::{request, response_json, route};
+    use crate::server::ApiError;
+
+    fn invalid() -> ApiError {
+        let mut errors = ValidationErrors::new();
+        errors.add("name", ValidationError::new("length"));
+
+        ApiError::Invalid(errors)
+    }
+
+    #[tokio::test]
+    async fn error_responses() -> anyhow::Result<()> {
+        let req = request("alice")?;
+        let route = route(&req);
+
+        let errors = vec![
+            (ApiError::NotFound("No app".to_string()), StatusCode::NOT_FOUND, "not_found", "No app"),
+            (ApiError::Forbidden("No access".to_string()), StatusCode::FORBIDDEN, "forbidden", "No access"),
+            (ApiError::Conflict("In use".to_string()), StatusCode::CONFLICT, "conflict", "In use"),
+            (ApiError::BadRequest(anyhow!("No name")), StatusCode::BAD_REQUEST, "bad_request", "No name"),
+            (
+                ApiError::InvalidTrackingData(anyhow!("No version")),
+                StatusCode::BAD_REQUEST,
+                "invalid_tracking_data",
+                "No version",
+            ),
+            (invalid(), StatusCode::BAD_REQUEST, "validation_failed", "Invalid data"),
+        ];
+
+        for (error, status, code, message) in errors {
+            let fields = matches!(error, ApiError::Invalid(_));
+
+            let response = error.into_response(&route)?;
+            assert_eq!(response.status(), status, "{}", code);
+
+            let body = response_json(response).await?;
+            assert_eq!((body["code"].as_str(), body["message"].as_str()), (Some(code), Some(message)));
+            assert_eq!(body["request_id"].as_str(), Some(route.request_id.as_str()));
+
+            if fields {
+                assert_eq!(body["fields"], json!({"name": [{"code": "length", "message": null, "params": {}}]}));
+            } else {
+                assert!(body.get("fields").is_none(), "fields of {}", code);
+            }
         }
+
+        let response = ApiError::NoContent("No change".to_string()).into_response(&route)?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn error_causes_are_not_leaked() -> anyhow::Result<()> {
+        let req = request("alice")?;
+        let route = route(&req);
+
+        let response = ApiError::InternalServerError(anyhow!("Password mismatch for user db").context("Error in writing to store")).into_response(&route)?;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response_json(response).await?;
+        assert_eq!(
+            (body["code"].as_str(), body["message"].as_str()),
+            (Some("internal_error"), Some("Error in serving request"))
+        );
+        assert!(!body.to_string().contains("Password"), "{}", body);
+
+        // only the top level message of a bad request is returned
+        let response = ApiError::BadRequest(anyhow!("Password mismatch for user db").context("Error in decoding body")).into_response(&route)?;
+        let body = response_json(response).await?.to_string();
+        assert!(body.contains("Error in decoding body") && !body.contains("Password"), "{}", body);
+
+        Ok(())
     }
 }
@@ -3,7 +3,7 @@ use std::sync::atomic::Ordering;
 use http::Method;
 use hyper::Body;
 
-use crate::server::HttpResult;
+use crate::server::{ApiError, HttpResult};
 
 use super::service::IN_ROTATION;
 use super::HttpResponse;
@@ -39,6 +39,6 @@ pub fn oor_handler(route: &HttpRoute<'_>) -> HttpResult {
     match method {
         &Method::GET => switch_oor_status(route),
         &Method::POST => switch_oor_status(route),
-        _ => HttpResponse::not_found(route.path),
+        _ => Err(ApiError::NotFound(format!("No route for path: {}", route.path))),
     }
 }
@@ -5,18 +5,58 @@ use hyper::Body;
 use serde::Deserialize;
 
 use super::commons::{BR_CONTENT_ENCODING, DEFLATE_CONTENT_ENCODING, GZIP_CONTENT_ENCODING};
-use super::HttpRoute;
+use super::json_patch::JsonPatch;
+use super::{ApiError, ApiResult, HttpRoute};
 
 pub struct HttpRequest;
 
 impl HttpRequest {
-    pub async fn bytes(route: &HttpRoute<'_>, body: Body) -> anyhow::Result<impl Buf> {
+    pub async fn bytes(route: &HttpRoute<'_>, body: Body) -> ApiResult<impl Buf> {
         // TODO: validate content length
         // let content_length = route.req.headers().get(header::CONTENT_LENGTH);
 
+        let body = Self::decoded(route, body);
+
+        // Aggregate the body...
+        hyper::body::aggregate(body)
+            .await
+            .with_context(|| "Error in aggregating body")
+            .map_err(ApiError::BadRequest)
+    }
+
+    /// Visits the body line by line as it streams in, without aggregating the whole body, for large uploads.
+    ///
+    /// Trailing `\r` and blank lines are skipped. Errors of the visitor fail the request as bad request.
+    pub async fn lines<F>(route: &HttpRoute<'_>, body: Body, mut visitor: F) -> ApiResult<()>
+    where
+        F: FnMut(&str) -> anyhow::Result<()>,
+    {
+        use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+        use tokio::io::AsyncBufReadExt;
+
+        let body = Self::decoded(route, body).map_err(|err| IOError::new(IOErrorKind::InvalidData, err));
+        let mut lines = tokio_util::io::StreamReader::new(body).lines();
+
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .with_context(|| "Error in reading body lines")
+            .map_err(ApiError::BadRequest)?
+        {
+            let line = line.trim_end_matches('\r');
+
+            if !line.trim().is_empty() {
+                visitor(line).map_err(ApiError::BadRequest)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decoded(route: &HttpRoute<'_>, body: Body) -> Body {
         use std::io::{Error as IOError, ErrorKind as IOErrorKind};
 
-        let body = if let Some(content_encoding) = &route.content_encoding {
+        if let Some(content_encoding) = &route.content_encoding {
             match &content_encoding[..] {
                 BR_CONTENT_ENCODING => Body::wrap_stream(brotli_decode(body.map_err(|_| IOError::from(IOErrorKind::InvalidData)))),
                 DEFLATE_CONTENT_ENCODING => Body::wrap_stream(deflate_decode(body.map_err(|_| IOError::from(IOErrorKind::InvalidData)))),
@@ -28,13 +68,11 @@ impl HttpRequest {
             }
         } else {
             body
-        };
-
-        // Aggregate the body...
-        hyper::body::aggregate(body).await.with_context(|| "Error in aggregating body")
+        }
     }
 
-    pub async fn value<T>(route: &HttpRoute<'_>, body: Body) -> anyhow::Result<T>
+    #[tracing::instrument(name = "decode_body", skip(route, body))]
+    pub async fn value<T>(route: &HttpRoute<'_>, body: Body) -> ApiResult<T>
     where
         T: for<'de> Deserialize<'de>,
     {
@@ -44,7 +82,22 @@ impl HttpRequest {
         let whole_body = Self::bytes(route, body).await?;
 
         // Decode as JSON...
-        serde_json::from_reader(whole_body.reader()).with_context(|| "Error in decoding body_as_value")
+        serde_json::from_reader(whole_body.reader())
+            .with_context(|| "Error in decoding body_as_value")
+            .map_err(ApiError::BadRequest)
+    }
+
+    pub async fn patch(route: &HttpRoute<'_>, body: Body) -> ApiResult<JsonPatch> {
+        let content_type = route
+            .req
+            .headers()
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap_or(value).trim());
+
+        let value = Self::value::<serde_json::Value>(route, body).await?;
+
+        JsonPatch::from_value(content_type, value).map_err(ApiError::BadRequest)
     }
 }
 
@@ -6,7 +6,7 @@ use hyper::Body;
 use serde::Serialize;
 
 use crate::server::commons::get_hostname_header;
-use crate::server::{HttpResult, HttpRoute};
+use crate::server::{ApiError, HttpResult, HttpRoute};
 
 use super::commons::{BR_CONTENT_ENCODING, DEFLATE_CONTENT_ENCODING, GZIP_CONTENT_ENCODING};
 
@@ -25,28 +25,28 @@ impl HttpResponse {
         HttpResponse::build_response(StatusCode::INTERNAL_SERVER_ERROR, body)
     }
 
-    pub fn not_found(reason: &str) -> HttpResult {
-        let body = Body::from(format!("Not found: {}", reason));
-
-        HttpResponse::build_response(StatusCode::NOT_FOUND, body)
-    }
-
-    pub fn forbidden(reason: &str) -> HttpResult {
-        let body = Body::from(format!("Forbidden: {}", reason));
-
-        HttpResponse::build_response(StatusCode::FORBIDDEN, body)
-    }
+    /// Error response with JSON body.
+    pub fn error<S>(code: StatusCode, body: &S) -> HttpResult
+    where
+        S: Serialize,
+    {
+        let body = serde_json::to_vec(body)
+            .with_context(|| "Error in serialising")
+            .map_err(ApiError::InternalServerError)?;
 
-    pub fn bad_request(error: anyhow::Error) -> HttpResult {
-        let body = Body::from(format!("Bad Request: {:?}", error));
+        let response = Response::builder()
+            .status(code)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::HOST, get_hostname_header().clone())
+            .body(Body::from(body))
+            .with_context(|| "Error in building HttpResponse")
+            .map_err(ApiError::InternalServerError)?;
 
-        HttpResponse::build_response(StatusCode::BAD_REQUEST, body)
+        Ok(response)
     }
 
-    pub fn no_content(reason: &str) -> HttpResult {
-        let body = Body::from(format!("No Content: {}", reason));
-
-        HttpResponse::build_response(StatusCode::NO_CONTENT, body)
+    pub fn no_content() -> HttpResult {
+        HttpResponse::build_response(StatusCode::NO_CONTENT, Body::empty())
     }
 
     fn build_response(code: StatusCode, body: Body) -> HttpResult {
@@ -54,7 +54,8 @@ impl HttpResponse {
             .status(code)
             .header(header::HOST, get_hostname_header().clone())
             .body(body)
-            .with_context(|| "Error in building HttpResponse")?;
+            .with_context(|| "Error in building HttpResponse")
+            .map_err(ApiError::InternalServerError)?;
 
         Ok(response)
     }
@@ -64,7 +65,8 @@ impl HttpResponse {
             .status(StatusCode::OK)
             .header(header::HOST, get_hostname_header().clone())
             .body(body)
-            .with_context(|| "Error in building HttpResponse")?;
+            .with_context(|| "Error in building HttpResponse")
+            .map_err(ApiError::InternalServerError)?;
 
         Ok(Self::compress_response(route, response))
     }
@@ -74,7 +76,8 @@ impl HttpResponse {
             .status(StatusCode::OK)
             .header(header::HOST, get_hostname_header().clone())
             .body(Body::from(body))
-            .with_context(|| "Error in building HttpResponse")?;
+            .with_context(|| "Error in building HttpResponse")
+            .map_err(ApiError::InternalServerError)?;
 
         Ok(Self::compress_response(route, response))
     }
@@ -84,7 +87,8 @@ impl HttpResponse {
             .status(StatusCode::OK)
             .header(header::HOST, get_hostname_header().clone())
             .body(Body::from(body))
-            .with_context(|| "Error in building HttpResponse")?;
+            .with_context(|| "Error in building HttpResponse")
+            .map_err(ApiError::InternalServerError)?;
 
         Ok(Self::compress_response(route, response))
     }
@@ -93,7 +97,9 @@ impl HttpResponse {
     where
         S: Serialize,
     {
-        let body = serde_json::to_vec(body).with_context(|| "Error in serialising")?;
+        let body = serde_json::to_vec(body)
+            .with_context(|| "Error in serialising")
+            .map_err(ApiError::InternalServerError)?;
         let body = Body::from(body);
 
         let response = Response::builder()
@@ -101,7 +107,8 @@ impl HttpResponse {
             .header(header::CONTENT_TYPE, "application/json")
             .header(header::HOST, get_hostname_header().clone())
             .body(body)
-            .with_context(|| "Error in building HttpResponse")?;
+            .with_context(|| "Error in building HttpResponse")
+            .map_err(ApiError::InternalServerError)?;
 
         Ok(Self::compress_response(route, response))
     }
@@ -111,7 +118,9 @@ impl HttpResponse {
     where
         S: Serialize,
     {
-        let body = serde_json::to_vec(body).with_context(|| "Error in serialising")?;
+        let body = serde_json::to_vec(body)
+            .with_context(|| "Error in serialising")
+            .map_err(ApiError::InternalServerError)?;
         let body = Body::from(body);
 
         let response = Response::builder()
@@ -119,7 +128,8 @@ impl HttpResponse {
             .header(header::CONTENT_TYPE, "application/json")
             .header(header::HOST, get_hostname_header().clone())
             .body(body)
-            .with_context(|| "Error in building HttpResponse")?;
+            .with_context(|| "Error in building HttpResponse")
+            .map_err(ApiError::InternalServerError)?;
 
         Ok(Self::compress_response(route, response))
     }
@@ -136,7 +146,8 @@ impl HttpResponse {
             .header(header::CONTENT_TYPE, "application/json")
             .header(header::HOST, get_hostname_header().clone())
             .body(body)
-            .with_context(|| "Error in building HttpResponse")?;
+            .with_context(|| "Error in building HttpResponse")
+            .map_err(ApiError::InternalServerError)?;
 
         Ok(Self::compress_response(route, response))
     }
@@ -194,6 +205,13 @@ impl HttpResponse {
     //     Ok(Self::compress_response(route, response))
     // }
 
+    /// Wraps the body in the encoder of the accepted encoding. Body is encoded as it is streamed to the client, after the
+    /// span of compression is closed.
+    #[tracing::instrument(
+        name = "compress_response",
+        skip(route, response),
+        fields(encoding = %String::from_utf8_lossy(route.accept_encoding.unwrap_or_default()))
+    )]
     pub fn compress_response(route: &HttpRoute<'_>, mut response: Response<Body>) -> Response<Body> {
         use std::io::{Error as IOError, ErrorKind as IOErrorKind};
 
@@ -1,9 +1,12 @@
 use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
 use std::time::Instant;
 
 use chrono::Local;
 use http::{header, Method, Request, Uri};
 use hyper::Body;
+use parking_lot::Mutex;
 
 use crate::server::commons::{BR_CONTENT_ENCODING, DEFLATE_CONTENT_ENCODING, GZIP_CONTENT_ENCODING};
 
@@ -19,8 +22,19 @@ pub struct HttpRoute<'a> {
     pub accept_encoding: Option<&'a [u8]>,
     pub metric_path: Option<&'static str>,
     pub remote_addr: SocketAddr,
+
+    /// id of the request, as propagated in `X-Request-Id` header, or generated
+    pub request_id: String,
+
+    /// fields added to access log by the handler, e.g. app id of the run request
+    pub log_fields: Mutex<Vec<(&'static str, String)>>,
+
+    /// bytes of the request body read so far
+    pub request_bytes: Arc<AtomicU64>,
 }
 
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
 const CONTENT_ENCODINGS: [&'static [u8]; 3] = [BR_CONTENT_ENCODING, GZIP_CONTENT_ENCODING, DEFLATE_CONTENT_ENCODING];
 
 impl<'a> HttpRoute<'a> {
@@ -47,6 +61,29 @@ impl<'a> HttpRoute<'a> {
             }),
             metric_path: None,
             remote_addr,
+            request_id: Self::request_id(req),
+            log_fields: Mutex::new(vec![]),
+            request_bytes: Arc::new(AtomicU64::new(0)),
         }
     }
+
+    /// Propagated request id, if it is sane, else a generated one.
+    fn request_id(req: &Request<Body>) -> String {
+        req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty() && value.len() <= 128)
+            .map_or_else(|| uuid::Uuid::new_v4().to_string(), |value| value.to_string())
+    }
+
+    pub fn add_log_field(&self, name: &'static str, value: &str) {
+        self.log_fields.lock().push((name, value.to_string()));
+    }
+
+    /// Value of the query param, not url decoded.
+    pub fn query_param(&self, name: &str) -> Option<&'a str> {
+        self.query?
+            .split('&')
+            .find_map(|param| param.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')))
+    }
 }
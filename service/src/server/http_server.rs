@@ -10,12 +10,16 @@ use hyper::service::{make_service_fn, service_fn};
 use hyper::Body;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
+use tracing::Instrument;
 
-use crate::server::{HttpResult, Service, ServiceBuilder, ServiceDaemon, IN_ROTATION, SHUTDOWN};
+use crate::server::{ApiError, HttpResult, Service, ServiceBuilder, ServiceDaemon, IN_ROTATION, SHUTDOWN};
+use crate::settings;
 
-use super::access_logger::ACCESS_LOGGER;
+use super::access_logger::{counted_body, ACCESS_LOGGER};
 use super::health_check::{get_in_rotation_status, oor_handler};
 use super::http_response::HttpResponse;
+use super::http_route::REQUEST_ID_HEADER;
+use super::telemetry;
 // use super::ACCESS_LOGGER;
 use super::HttpRoute;
 
@@ -45,7 +49,8 @@ where
     let req_instant = Instant::now();
 
     let req_body = mem::replace(req.body_mut(), Body::empty());
-    let route = HttpRoute::new(&req, req_time, req_instant, remote_addr);
+    let mut route = HttpRoute::new(&req, req_time, req_instant, remote_addr);
+    let req_body = counted_body(req_body, route.request_bytes.clone());
 
     // let body_buf;
     // if matches!(route.method, &Method::POST) || matches!(route.method, &Method::PUT) || matches!(route.method, &Method::PATCH) {
@@ -57,38 +62,58 @@ where
 
     let parts: Vec<_> = route.path.split("/").filter(|part| !part.is_empty()).collect();
 
-    let response = match &parts[..] {
-        [] if matches!(route.method, &Method::GET) => index(&route),
-        ["oor"] => oor_handler(&route),
-        ["status"] if matches!(route.method, &Method::GET) => get_in_rotation_status(&route),
-        // ["metrics", rest @ ..] if matches!(route.method, &Method::GET) => match rest {
-        //     // sub routes
-        //     ["service", "prometheus"] => ACCESS_LOGGER.get_api_metrics_for_prometheus(&route).await,
-        //
-        //     ["service"] => ACCESS_LOGGER.get_api_metrics_as_json(&route).await,
-        //     _ => {
-        //         // TODO: other routes...
-        //         HttpResponse::not_found()
-        //     }
-        // },
-        ["api", rest @ ..] => app.api_handler(req_body, &route, rest).await,
-        _ => HttpResponse::not_found(route.path),
-    };
-
-    let response = match response {
-        Ok(mut response) => {
+    route.metric_path = Some(match &parts[..] {
+        [] => "/",
+        ["oor"] => "/oor",
+        ["status"] => "/status",
+        ["metrics"] => "/metrics",
+        ["metrics", "json"] => "/metrics/json",
+        ["api", rest @ ..] => app.metric_path(rest).unwrap_or("/api/{unknown}"),
+        _ => "/{unknown}",
+    });
+
+    let span = telemetry::request_span(&route);
+
+    let response = async {
+        match &parts[..] {
+            [] if matches!(route.method, &Method::GET) => index(&route),
+            ["oor"] => oor_handler(&route),
+            ["status"] if matches!(route.method, &Method::GET) => get_in_rotation_status(&route),
+            ["metrics"] if matches!(route.method, &Method::GET) => ACCESS_LOGGER.get_api_metrics_for_prometheus(&route).await,
+            ["metrics", "json"] if matches!(route.method, &Method::GET) => ACCESS_LOGGER.get_api_metrics_as_json(&route).await,
+            ["api", rest @ ..] => app.api_handler(req_body, &route, rest).await,
+            _ => Err(ApiError::NotFound(format!("No route for path: {}", route.path))),
+        }
+    }
+    .instrument(span.clone())
+    .await;
+
+    // error responses carry the same headers, so that failed requests can be traced by request id too
+    let response = response
+        .or_else(|err| {
+            route.add_log_field("error", &err.to_string());
+            err.into_response(&route)
+        })
+        .and_then(|mut response| {
             let time_taken = format!("{}", humantime::Duration::from(req_instant.elapsed()));
-            let time_taken_header = HeaderValue::from_str(&time_taken).with_context(|| format!("Error in building header value time_taken"))?;
+            let time_taken_header = HeaderValue::from_str(&time_taken)
+                .context("Error in building header value time_taken")
+                .map_err(ApiError::InternalServerError)?;
             response.headers_mut().append("X-time-taken", time_taken_header);
+            let request_id_header = HeaderValue::from_str(&route.request_id)
+                .context("Error in building header value request_id")
+                .map_err(ApiError::InternalServerError)?;
+            response.headers_mut().insert(REQUEST_ID_HEADER, request_id_header);
+            telemetry::inject_span(&span, response.headers_mut());
             Ok(response)
-        }
-        Err(err) => err.into(),
-    };
+        });
 
-    // log & metrics
-    ACCESS_LOGGER.log_access(&route, &response);
+    if let Ok(response) = &response {
+        span.record("http.status_code", &response.status().as_u16());
+    }
 
-    response
+    // log & metrics
+    ACCESS_LOGGER.log_access(&route, response)
 }
 
 pub async fn start_http_server<App, AppDaemon, AppBuilder>(addr: &str, app_builder: AppBuilder) -> anyhow::Result<()>
@@ -103,7 +128,9 @@ where
         .parse::<SocketAddr>()
         .with_context(|| format!("Parsing node addr '{}' as SocketAddr", addr))?;
 
-    let (app, app_daemon) = app_builder.build().with_context(|| "Error in building app")?;
+    telemetry::init_tracing(&settings::tracing_config())?;
+
+    let (app, app_daemon) = app_builder.build().await.with_context(|| "Error in building app")?;
     let app = Arc::new(app);
 
     if let Some(app_daemon) = app_daemon {
@@ -139,5 +166,9 @@ where
     info!("Started server");
 
     // Run this server for... forever!
-    graceful.await.with_context(|| "Error in starting server")
+    let result = graceful.await.with_context(|| "Error in starting server");
+
+    telemetry::shutdown_tracing();
+
+    result
 }
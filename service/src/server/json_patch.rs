@@ -0,0 +1,128 @@
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+
+pub const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+pub const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
+/// Patch document sent with PATCH requests.
+///
+/// Either a RFC 7396 JSON Merge Patch or a RFC 6902 JSON Patch.
+pub enum JsonPatch {
+    Merge(JsonValue),
+    Patch(json_patch::Patch),
+}
+
+impl JsonPatch {
+    pub fn from_value(content_type: Option<&str>, value: JsonValue) -> anyhow::Result<JsonPatch> {
+        match content_type {
+            Some(MERGE_PATCH_CONTENT_TYPE) => Ok(JsonPatch::Merge(value)),
+            Some(JSON_PATCH_CONTENT_TYPE) => Self::json_patch(value),
+            // no explicit patch content type, then json patch is always a list of operations
+            _ if value.is_array() => Self::json_patch(value),
+            _ => Ok(JsonPatch::Merge(value)),
+        }
+    }
+
+    fn json_patch(value: JsonValue) -> anyhow::Result<JsonPatch> {
+        let patch = json_patch::from_value(value).with_context(|| "Error in decoding body as json patch")?;
+
+        Ok(JsonPatch::Patch(patch))
+    }
+
+    /// Applies patch over the serialised form of existing data, and returns the patched copy.
+    pub fn apply<T>(&self, existing: &T) -> anyhow::Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let mut doc = serde_json::to_value(existing).with_context(|| "Error in serialising existing data for patch")?;
+
+        match self {
+            JsonPatch::Merge(patch) => json_patch::merge(&mut doc, patch),
+            JsonPatch::Patch(patch) => json_patch::patch(&mut doc, patch).with_context(|| "Error in applying json patch")?,
+        }
+
+        serde_json::from_value(doc).with_context(|| "Error in decoding patched data")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use crate::server::json_patch::{JsonPatch, JSON_PATCH_CONTENT_TYPE, MERGE_PATCH_CONTENT_TYPE};
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Entity {
+        name: String,
+        active: bool,
+        #[serde(default)]
+        tags: Vec<String>,
+    }
+
+    fn entity() -> Entity {
+        Entity {
+            name: "a".to_string(),
+            active: true,
+            tags: vec!["x".to_string()],
+        }
+    }
+
+    #[test]
+    fn merge_patch() -> anyhow::Result<()> {
+        let patch = JsonPatch::from_value(Some(MERGE_PATCH_CONTENT_TYPE), json!({"active": false}))?;
+        let patched = patch.apply(&entity())?;
+
+        assert_eq!(patched.name, "a");
+        assert!(!patched.active);
+        assert_eq!(patched.tags, vec!["x"]);
+
+        // null removes the field, which falls back to its default
+        let patch = JsonPatch::from_value(None, json!({"tags": null}))?;
+        assert!(patch.apply(&entity())?.tags.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn json_patch() -> anyhow::Result<()> {
+        let operations = json!([
+            {"op": "replace", "path": "/name", "value": "b"},
+            {"op": "add", "path": "/tags/-", "value": "y"}
+        ]);
+
+        let patch = JsonPatch::from_value(Some(JSON_PATCH_CONTENT_TYPE), operations.clone())?;
+        let patched = patch.apply(&entity())?;
+        assert_eq!(patched.name, "b");
+        assert_eq!(patched.tags, vec!["x", "y"]);
+
+        // without content type, a list of operations is taken as json patch
+        let patch = JsonPatch::from_value(None, operations)?;
+        assert_eq!(patch.apply(&entity())?.name, "b");
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_json_patch() -> anyhow::Result<()> {
+        assert!(JsonPatch::from_value(Some(JSON_PATCH_CONTENT_TYPE), json!({"active": false})).is_err());
+
+        // failed test operation fails the whole patch
+        let patch = JsonPatch::from_value(
+            None,
+            json!([
+                {"op": "replace", "path": "/name", "value": "b"},
+                {"op": "test", "path": "/active", "value": false}
+            ]),
+        )?;
+        assert!(patch.apply(&entity()).is_err());
+
+        // patched data is still decoded as the entity
+        let patch = JsonPatch::from_value(None, json!({"active": "no"}))?;
+        assert!(patch.apply(&entity()).is_err());
+
+        Ok(())
+    }
+}
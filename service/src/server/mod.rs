@@ -1,11 +1,13 @@
 use hyper::Body;
 use hyper::Response;
 
+pub use access_logger::METRICS_REGISTRY;
 pub use error::ApiError;
 pub use http_request::HttpRequest;
 pub use http_response::HttpResponse;
 pub use http_route::HttpRoute;
 pub use http_server::start_http_server;
+pub use json_patch::JsonPatch;
 // pub(crate) use access_logger::ACCESS_LOGGER;
 pub use service::{Service, ServiceBuilder, ServiceDaemon, IN_ROTATION, SHUTDOWN};
 
@@ -20,4 +22,6 @@ mod http_request;
 mod http_response;
 mod http_route;
 mod http_server;
+mod json_patch;
 mod service;
+mod telemetry;
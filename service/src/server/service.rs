@@ -12,13 +12,19 @@ lazy_static! {
     pub static ref SHUTDOWN: AtomicBool = AtomicBool::new(false);
 }
 
+#[async_trait]
 pub trait ServiceBuilder<T: Service, D: ServiceDaemon<T>> {
-    fn build(self) -> anyhow::Result<(T, Option<D>)>;
+    async fn build(self) -> anyhow::Result<(T, Option<D>)>;
 }
 
 #[async_trait]
 pub trait Service: Send + Sync {
     async fn api_handler<'a>(&'a self, body: Body, route: &HttpRoute<'a>, path: &[&str]) -> Result<Response<Body>, ApiError>;
+
+    /// Templated path of the api route, e.g. `/api/apps/{app_id}`, used as metrics label instead of the actual path.
+    fn metric_path(&self, _path: &[&str]) -> Option<&'static str> {
+        None
+    }
 }
 
 #[async_trait]
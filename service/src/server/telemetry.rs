@@ -0,0 +1,133 @@
+use anyhow::Context;
+use http::{HeaderMap, HeaderValue};
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace as sdktrace;
+use opentelemetry::sdk::Resource;
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::server::HttpRoute;
+use crate::settings::{TraceExporter, TracingConfig};
+
+/// Installs the exporter of the request traces, and W3C trace context propagation of `traceparent` headers.
+pub fn init_tracing(config: &TracingConfig) -> anyhow::Result<()> {
+    if config.exporter == TraceExporter::None {
+        return Ok(());
+    }
+
+    info!("Configuring tracing: {:?}", config);
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let trace_config = sdktrace::config().with_resource(Resource::new(vec![KeyValue::new("service.name", config.service_name.to_string())]));
+
+    let tracer = match config.exporter {
+        TraceExporter::Stdout => opentelemetry::sdk::export::trace::stdout::new_pipeline()
+            .with_trace_config(trace_config)
+            .install_simple(),
+        _ => opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(&config.endpoint))
+            .with_trace_config(trace_config)
+            .install_batch(opentelemetry::runtime::Tokio)
+            .with_context(|| format!("Error in installing OTLP exporter for endpoint: {}", config.endpoint))?,
+    };
+
+    let subscriber = tracing_subscriber::Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber).with_context(|| "Error in setting tracing subscriber")
+}
+
+/// Flushes the spans yet to be exported.
+pub fn shutdown_tracing() {
+    global::shutdown_tracer_provider();
+}
+
+/// Span of the request, continuing the trace of the `traceparent` header, if any.
+pub fn request_span(route: &HttpRoute<'_>) -> tracing::Span {
+    let span = tracing::info_span!(
+        "request",
+        http.method = %route.method,
+        http.route = route.metric_path.unwrap_or_default(),
+        http.status_code = tracing::field::Empty,
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(route.req.headers())));
+    span.set_parent(parent);
+
+    span
+}
+
+/// Adds `traceparent` header of the span, so that the caller can look up the trace of the request.
+pub fn inject_span(span: &tracing::Span, headers: &mut HeaderMap) {
+    let context = span.context();
+
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut HeaderInjector(headers)));
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (http::header::HeaderName::from_bytes(key.as_bytes()), HeaderValue::from_str(&value)) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderMap;
+    use hyper::{Body, Request};
+    use opentelemetry::global;
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::core::fixtures::route;
+    use crate::server::telemetry::{inject_span, request_span};
+
+    #[test]
+    fn traceparent_round_trip() -> anyhow::Result<()> {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = opentelemetry::sdk::export::trace::stdout::new_pipeline()
+            .with_writer(std::io::sink())
+            .install_simple();
+        let subscriber = tracing_subscriber::Registry::default().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        let req = Request::builder()
+            .header("traceparent", "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+            .body(Body::empty())?;
+        let route = route(&req);
+
+        let mut headers = HeaderMap::new();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = request_span(&route);
+            inject_span(&span, &mut headers);
+        });
+
+        // same trace, with the request span as parent
+        let traceparent = headers.get("traceparent").map(|value| value.to_str()).transpose()?.unwrap_or_default();
+        let parts: Vec<&str> = traceparent.split('-').collect();
+        assert_eq!(parts.len(), 4, "traceparent: {}", traceparent);
+        assert_eq!((parts[0], parts[1], parts[3]), ("00", "0af7651916cd43dd8448eb211c80319c", "01"));
+        assert_ne!(parts[2], "b7ad6b7169203331");
+
+        Ok(())
+    }
+}
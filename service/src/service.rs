@@ -8,66 +8,99 @@ use crossbeam_skiplist::SkipList;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 use parking_lot::RwLock;
+use tokio::sync::Notify;
 
 use crate::core::{App, ScriptEvaluator};
-use crate::experiment_store::ExperimentStore;
+use crate::experiment_store::{ExperimentStore, Store, StoreRegistry};
 use crate::server::{ServiceBuilder, ServiceDaemon, SHUTDOWN};
-use crate::settings;
+use crate::settings::{self, ApprovalConfig};
 
 pub struct AbOptimisationService {
     pub apps: SkipList<String, RwLock<App>>,
     pub script_evaluator: Arc<ScriptEvaluator>,
     pub experiment_store: ExperimentStore,
+
+    /// approval workflow of the experiments, as per the settings when the service is created
+    pub approval: ApprovalConfig,
 }
 
 pub struct AbOptimisationServiceDaemon {}
 
-pub struct AbOptimisationServiceBuilder {}
+#[derive(Default)]
+pub struct AbOptimisationServiceBuilder {
+    /// stores available for `store_kind` setting
+    pub stores: StoreRegistry,
+}
+
+impl AbOptimisationServiceBuilder {
+    /// Adds a store backend for the kind, e.g. to keep data in a store not supported out of the box.
+    pub fn with_store<F>(mut self, store_kind: &str, factory: F) -> Self
+    where
+        F: Fn() -> anyhow::Result<Box<dyn Store>> + Send + Sync + 'static,
+    {
+        self.stores.register(store_kind, factory);
+        self
+    }
+}
 
 #[async_trait]
 impl ServiceDaemon<AbOptimisationService> for AbOptimisationServiceDaemon {
     async fn start(&self, service: Arc<AbOptimisationService>) {
-        let mut interval = tokio::time::interval(Duration::from_secs(settings::s3_store_config().refresh_rate));
+        let changed = Arc::new(Notify::new());
+        if let Err(err) = service.experiment_store.watch(changed.clone()) {
+            warn!("Error in watching store, falling back to polling: {:?}", err);
+        }
+
+        // refresh rate of 0 disables polling, leaving refresh to store changes
+        let refresh_rate = service.experiment_store.refresh_rate();
+        let polling = refresh_rate > 0;
+        let mut interval = tokio::time::interval(Duration::from_secs(refresh_rate.max(1)));
 
         while !SHUTDOWN.load(Ordering::Relaxed) {
-            interval.tick().await;
-            info!("Refreshing data: {:?}", chrono::Local::now());
-            if let Err(err) = service.load_data() {
+            tokio::select! {
+                _ = interval.tick(), if polling => {
+                    info!("Refreshing data: {:?}", chrono::Local::now());
+                }
+                _ = changed.notified() => {
+                    info!("Refreshing data on store change: {:?}", chrono::Local::now());
+                }
+            }
+
+            if let Err(err) = service.load_data().await {
                 error!("Error in loading data: {:?}", err);
             }
+
+            service.refresh_segments().await;
         }
     }
 }
 
+#[async_trait]
 impl ServiceBuilder<AbOptimisationService, AbOptimisationServiceDaemon> for AbOptimisationServiceBuilder {
-    fn build(self) -> anyhow::Result<(AbOptimisationService, Option<AbOptimisationServiceDaemon>)> {
-        let apps = SkipList::new(epoch::default_collector().clone());
-        let script_evaluator = Arc::new(ScriptEvaluator::new());
-
-        let experiment_store = match crate::settings::store_kind().as_str() {
-            "local" => ExperimentStore::new_local_store(settings::local_store_config()),
-            "s3" => ExperimentStore::new_s3_store(settings::s3_store_config()),
-            _ => Err(anyhow::anyhow!("Unknown store kind: {}", settings::store_kind())),
-        }?;
-
-        let service = AbOptimisationService {
-            apps,
-            script_evaluator,
-            experiment_store,
-        };
+    async fn build(self) -> anyhow::Result<(AbOptimisationService, Option<AbOptimisationServiceDaemon>)> {
+        let service = AbOptimisationService::new(ExperimentStore::configured(&self.stores)?);
 
-        service.load_data()?;
+        service.load_data().await?;
 
         Ok((service, Some(AbOptimisationServiceDaemon {})))
     }
 }
 
 impl AbOptimisationService {
-    pub fn load_data(&self) -> anyhow::Result<()> {
-        self.experiment_store.load_apps(&self)?;
-        self.experiment_store.load_projects(&self)?;
-        self.experiment_store.load_audience_lists(&self)?;
-        self.experiment_store.load_experiments(&self)?;
+    pub fn new(experiment_store: ExperimentStore) -> AbOptimisationService {
+        AbOptimisationService {
+            apps: SkipList::new(epoch::default_collector().clone()),
+            script_evaluator: Arc::new(ScriptEvaluator::new()),
+            experiment_store,
+            approval: settings::approval_config(),
+        }
+    }
+
+    pub async fn load_data(&self) -> anyhow::Result<()> {
+        self.experiment_store.load_apps(&self).await?;
+        self.experiment_store.load_projects(&self).await?;
+        self.experiment_store.load_audience_lists(&self).await?;
+        self.experiment_store.load_experiments(&self).await?;
 
         Ok(())
     }
@@ -1,5 +1,6 @@
 use std::borrow::BorrowMut;
 use std::path::Path;
+use std::time::Duration;
 
 use config::{Config, File};
 #[allow(unused_imports)]
@@ -12,6 +13,8 @@ lazy_static! {
     static ref HTTP_WORKERS: usize = http_workers();
     static ref JSON_PAYLOAD_LIMIT: usize = json_payload_limit();
     static ref SECURE_COOKIE_SETTING: bool = secure_cookie_setting();
+    static ref ACCESS_LOG_FORMAT: AccessLogFormat = access_log_format_setting();
+    static ref CIRCUIT_BREAKER_CONFIG: CircuitBreakerConfig = circuit_breaker_config_setting();
 }
 
 pub fn settings() -> &'static RwLock<Config> {
@@ -30,6 +33,28 @@ fn secure_cookie_setting() -> bool {
     settings().read().get::<bool>("secure_cookie").unwrap_or_else(|_| false)
 }
 
+/// Format of the access log lines.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    /// positional, space separated fields
+    Text,
+
+    /// JSON object per line, including failed responses too
+    Json,
+}
+
+fn access_log_format_setting() -> AccessLogFormat {
+    settings()
+        .read()
+        .get::<AccessLogFormat>("access_log_format")
+        .unwrap_or_else(|_| AccessLogFormat::Text)
+}
+
+pub fn access_log_format() -> AccessLogFormat {
+    *ACCESS_LOG_FORMAT
+}
+
 pub fn store_kind() -> String {
     settings().read().get::<String>("store_kind").unwrap_or_else(|_| "local".to_string())
 }
@@ -37,13 +62,36 @@ pub fn store_kind() -> String {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LocalStoreConfig {
     pub path: String,
+
+    #[serde(default = "default_refresh_rate")]
+    pub refresh_rate: u64,
+
+    /// reload on file system changes, with refresh_rate as fallback
+    #[serde(default = "default_watch")]
+    pub watch: bool,
+}
+
+fn default_watch() -> bool {
+    true
+}
+
+impl LocalStoreConfig {
+    /// Validates the config of the store used by the service, which is refreshed at least on either of its triggers.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.refresh_rate == 0 && !self.watch {
+            anyhow::bail!("store_config.refresh_rate should be at least 1 second, when watch is disabled");
+        }
+
+        Ok(())
+    }
 }
 
 pub fn local_store_config() -> LocalStoreConfig {
-    settings()
-        .read()
-        .get::<LocalStoreConfig>("store_config")
-        .unwrap_or_else(|_| LocalStoreConfig { path: "data".to_string() })
+    settings().read().get::<LocalStoreConfig>("store_config").unwrap_or_else(|_| LocalStoreConfig {
+        path: "data".to_string(),
+        refresh_rate: 10,
+        watch: true,
+    })
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,6 +102,14 @@ pub struct S3StoreConfig {
 
     #[serde(default = "default_refresh_rate")]
     pub refresh_rate: u64,
+
+    /// endpoint of a S3 compatible store (e.g. MinIO), instead of AWS endpoint of the region
+    #[serde(default)]
+    pub endpoint: Option<String>,
+
+    /// address bucket in path (`{endpoint}/{bucket}`) instead of host name, as needed by most S3 compatible stores
+    #[serde(default)]
+    pub path_style: bool,
 }
 
 fn default_refresh_rate() -> u64 {
@@ -66,9 +122,135 @@ pub fn s3_store_config() -> S3StoreConfig {
         region: "ap-south-1".to_string(),
         path: "data".to_string(),
         refresh_rate: 10,
+        endpoint: None,
+        path_style: false,
     })
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SqliteStoreConfig {
+    /// database file, created if missing
+    pub path: String,
+
+    #[serde(default = "default_refresh_rate")]
+    pub refresh_rate: u64,
+}
+
+pub fn sqlite_store_config() -> SqliteStoreConfig {
+    settings()
+        .read()
+        .get::<SqliteStoreConfig>("store_config")
+        .unwrap_or_else(|_| SqliteStoreConfig {
+            path: "data/abof.db".to_string(),
+            refresh_rate: 10,
+        })
+}
+
+/// Approval workflow for experiment launches and edits.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApprovalConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// users having approver role
+    #[serde(default)]
+    pub approvers: Vec<String>,
+}
+
+pub fn approval_config() -> ApprovalConfig {
+    settings().read().get::<ApprovalConfig>("approval").unwrap_or_else(|_| ApprovalConfig {
+        enabled: false,
+        approvers: vec![],
+    })
+}
+
+/// Exporter of the request traces.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceExporter {
+    /// tracing is disabled
+    None,
+
+    /// spans are printed to stdout, for development
+    Stdout,
+
+    /// spans are exported to an OpenTelemetry collector over OTLP / gRPC
+    Otlp,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TracingConfig {
+    pub exporter: TraceExporter,
+
+    /// OTLP endpoint of the collector
+    #[serde(default = "default_otlp_endpoint")]
+    pub endpoint: String,
+
+    #[serde(default = "default_trace_service_name")]
+    pub service_name: String,
+}
+
+fn default_otlp_endpoint() -> String {
+    "http://localhost:4317".to_string()
+}
+
+fn default_trace_service_name() -> String {
+    "ab-optimisation-framework".to_string()
+}
+
+pub fn tracing_config() -> TracingConfig {
+    settings().read().get::<TracingConfig>("tracing").unwrap_or_else(|_| TracingConfig {
+        exporter: TraceExporter::None,
+        endpoint: default_otlp_endpoint(),
+        service_name: default_trace_service_name(),
+    })
+}
+
+/// Circuit breaker of the experiments failing in run, e.g. on script errors.
+#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+pub struct CircuitBreakerConfig {
+    /// consecutive failures, after which the experiment is skipped in run
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+
+    /// time for which the experiment is skipped, before it is tried again
+    #[serde(default = "default_open_duration", with = "humantime_serde")]
+    pub open_duration: Duration,
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_open_duration() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn circuit_breaker_config_setting() -> CircuitBreakerConfig {
+    settings()
+        .read()
+        .get::<CircuitBreakerConfig>("circuit_breaker")
+        .unwrap_or_else(|_| CircuitBreakerConfig {
+            failure_threshold: default_failure_threshold(),
+            open_duration: default_open_duration(),
+        })
+}
+
+pub fn circuit_breaker_config() -> CircuitBreakerConfig {
+    *CIRCUIT_BREAKER_CONFIG
+}
+
+/// Path of the MaxMind GeoIP2 / GeoLite2 City database, for geo enrichment of run requests.
+pub fn geoip_database() -> Option<String> {
+    settings().read().get::<String>("geoip_database").ok()
+}
+
+/// Whether this replica refreshes the dynamic audience lists from their segments. Disabled by default, it should be
+/// enabled on only one of the replicas, so that they don't race to write the same lists.
+pub fn segment_refresh() -> bool {
+    settings().read().get::<bool>("segment_refresh").unwrap_or(false)
+}
+
 pub fn secure_cookie() -> bool {
     *SECURE_COOKIE_SETTING
 }